@@ -0,0 +1,149 @@
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::cargo;
+use crate::repo::Repo;
+
+/// What is done to the page cache before every timed build. Samples taken in different modes
+/// are not comparable, so the mode is recorded with them.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub(crate) enum CacheMode {
+    /// Nothing. Whatever the previous build left in the cache stays there.
+    #[default]
+    Unmanaged,
+    /// Drops the page cache, so that sources and dependencies are read from disk. Linux only,
+    /// and needs root.
+    Cold,
+    /// Reads the repo's sources and those of its dependencies, so that none are read from disk.
+    Warm,
+}
+
+/// Prepares the page cache for one repo. Created once per repo, since finding the sources of
+/// its dependencies needs a cargo invocation.
+pub(crate) struct Cache {
+    mode: CacheMode,
+    directories: Vec<PathBuf>,
+}
+
+impl CacheMode {
+    pub(crate) fn is_unmanaged(&self) -> bool {
+        *self == CacheMode::Unmanaged
+    }
+}
+
+impl std::str::FromStr for CacheMode {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "Unmanaged" => Ok(CacheMode::Unmanaged),
+            "Cold" => Ok(CacheMode::Cold),
+            "Warm" => Ok(CacheMode::Warm),
+            _ => Err("unknown cache mode"),
+        }
+    }
+}
+
+impl Cache {
+    /// Call after the repo's dependencies have been downloaded.
+    pub(crate) fn new(repo: &Repo, mode: CacheMode) -> Result<Cache> {
+        let directories = match mode {
+            CacheMode::Warm => cargo::package_directories(repo)?,
+            CacheMode::Unmanaged | CacheMode::Cold => Vec::new(),
+        };
+        Ok(Cache { mode, directories })
+    }
+
+    /// Leaves the page cache alone, for builds that aren't timed.
+    pub(crate) fn unmanaged() -> Cache {
+        Cache {
+            mode: CacheMode::Unmanaged,
+            directories: Vec::new(),
+        }
+    }
+
+    /// Called right before every timed build.
+    pub(crate) fn prepare(&self) -> Result<()> {
+        match self.mode {
+            CacheMode::Unmanaged => Ok(()),
+            CacheMode::Cold => drop_page_cache(),
+            CacheMode::Warm => {
+                for directory in &self.directories {
+                    read_tree(directory)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn drop_page_cache() -> Result<()> {
+    // Dirty pages can't be dropped, so write them out first.
+    unsafe {
+        libc::sync();
+    }
+    std::fs::write("/proc/sys/vm/drop_caches", "3\n")
+        .with_context(|| "failed to drop the page cache, which needs root")
+}
+
+#[cfg(not(target_os = "linux"))]
+fn drop_page_cache() -> Result<()> {
+    Err(anyhow!(
+        "Dropping the page cache is only supported on Linux"
+    ))
+}
+
+/// Reads every file under `directory`, except build output and git objects.
+fn read_tree(directory: &Path) -> Result<()> {
+    let mut buffer = vec![0; 64 * 1024];
+    let mut pending = vec![directory.to_path_buf()];
+    while let Some(directory) = pending.pop() {
+        let entries = std::fs::read_dir(&directory)
+            .with_context(|| anyhow!("failed to read directory - {:?}", directory))?;
+        for entry in entries {
+            let entry = entry?;
+            let file_type = entry.file_type()?;
+            if file_type.is_dir() {
+                if entry.file_name() != "target" && entry.file_name() != ".git" {
+                    pending.push(entry.path());
+                }
+            } else if file_type.is_file() {
+                let mut file = std::fs::File::open(entry.path())?;
+                while file.read(&mut buffer)? > 0 {}
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use anyhow::Result;
+
+    #[test]
+    fn test_read_tree() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("arewefastyet-cache-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("src"))?;
+        std::fs::create_dir_all(dir.join("target"))?;
+        std::fs::write(dir.join("src/lib.rs"), "pub fn f() {}")?;
+        std::fs::write(dir.join("Cargo.toml"), "[package]")?;
+
+        let result = read_tree(&dir);
+        std::fs::remove_dir_all(&dir)?;
+        result?;
+        assert!(read_tree(&dir).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_cache_mode_from_str() {
+        assert_eq!("Cold".parse(), Ok(CacheMode::Cold));
+        assert_eq!("Warm".parse(), Ok(CacheMode::Warm));
+        assert!("cold".parse::<CacheMode>().is_err());
+    }
+}
@@ -1,5 +1,6 @@
-use std::collections::BTreeMap;
-use std::process::Command;
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
 
 use anyhow::{anyhow, Context, Result};
 use enum_iterator::IntoEnumIterator;
@@ -7,7 +8,12 @@ use once_cell::unsync::Lazy;
 use parse_duration::parse;
 use serde::{Deserialize, Serialize};
 
-use crate::repo::Repo;
+use crate::cache::{Cache, CacheMode};
+use crate::conditions::{self, Conditions};
+use crate::process::{self, CommandError, CommandKind};
+use crate::repo::{self, Repo};
+use crate::rustup::Version;
+use crate::stats;
 
 #[derive(
     Debug,
@@ -53,63 +59,337 @@ pub(crate) struct Bytes(u64);
 #[derive(Debug, Copy, Clone, Serialize, PartialOrd, Ord, PartialEq, Eq, Deserialize)]
 pub(crate) struct Milliseconds(u64);
 
+/// How many samples to take of each pair of CompilerMode and ProfileMode.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Sampling {
+    /// Samples always taken.
+    pub(crate) times: u32,
+    /// Samples taken at most while the samples of a pair vary by more than `cv_threshold`.
+    pub(crate) max_times: u32,
+    /// Highest acceptable coefficient of variation, e.g. 0.05 for 5%.
+    pub(crate) cv_threshold: f64,
+    /// Samples further than this many scaled MADs from the median are flagged as outliers.
+    pub(crate) outlier_fence: f64,
+    /// Untimed builds of each pair before the first sample.
+    pub(crate) warmup: u32,
+    pub(crate) cache: CacheMode,
+}
+
+/// The samples of one pair of CompilerMode and ProfileMode, with how they were taken.
+#[derive(Debug, Clone)]
+pub(crate) struct Samples {
+    pub(crate) times: Vec<Milliseconds>,
+    pub(crate) info: SampleInfo,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(crate) struct SampleInfo {
+    /// Coefficient of variation of all samples.
+    pub(crate) cv: f64,
+    /// Indices of samples outside the outlier fence.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub(crate) outliers: Vec<usize>,
+    pub(crate) stop_reason: StopReason,
+    /// Seed of the interleaved schedule the samples were taken in, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) seed: Option<u64>,
+    /// Untimed builds of the pair before the first sample.
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub(crate) warmup: u32,
+    #[serde(default, skip_serializing_if = "CacheMode::is_unmanaged")]
+    pub(crate) cache: CacheMode,
+    /// Set when the environment was controlled with --quiet-machine, --cpus or --jobs.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) conditions: Option<Conditions>,
+}
+
+/// Why sampling stopped. The pairs of a CompilerMode are sampled together, so they share one.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) enum StopReason {
+    /// All pairs were within the CV threshold after the planned number of samples.
+    Times,
+    /// Extra samples were taken until all pairs were within the CV threshold.
+    Converged,
+    /// At least one pair was still above the CV threshold at the sample cap.
+    MaxSamples,
+    /// The run was stopped, by Ctrl-C or an error, while the pair was still above the CV
+    /// threshold.
+    Stopped,
+}
+
+/// Sizes of everything a build of the repo produced. `output` is the size of the artifact
+/// named by `Repo::output`, or the sum of all artifacts when the repo doesn't name one.
+#[derive(Debug, Clone)]
+pub(crate) struct OutputSizes {
+    pub(crate) output: Bytes,
+    pub(crate) artifacts: BTreeMap<String, Bytes>,
+}
+
+/// The features and cargo arguments a repo is built with. Recorded with the results, since
+/// numbers measured with different feature sets can't be compared.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct FeatureSet {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub(crate) features: Vec<String>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub(crate) all_features: bool,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub(crate) no_default_features: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) package: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub(crate) cargo_args: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct Metadata {
+    packages: Vec<Package>,
+    workspace_members: Vec<String>,
+    pub(crate) target_directory: PathBuf,
+}
+
+#[derive(Debug, Deserialize)]
+struct Package {
+    name: String,
+    id: String,
+    manifest_path: PathBuf,
+}
+
+#[derive(Debug, Deserialize)]
+struct Message {
+    reason: String,
+    #[serde(default)]
+    package_id: Option<String>,
+    #[serde(default)]
+    target: Option<Target>,
+    #[serde(default)]
+    filenames: Vec<PathBuf>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Target {
+    kind: Vec<String>,
+}
+
 pub(crate) fn compile_time_profile(
     repo: &Repo,
-    times: u32,
-) -> Result<BTreeMap<(CompilerMode, ProfileMode), Vec<Milliseconds>>> {
-    cargo_check(repo)?; // download dependencies
+    sampling: &Sampling,
+) -> Result<BTreeMap<(CompilerMode, ProfileMode), Samples>> {
+    fetch(repo, None)?;
 
+    let cache = Cache::new(repo, sampling.cache)?;
     let mut results = BTreeMap::new();
 
-    results.extend(repeat(repo, CompilerMode::Check, times)?);
-    results.extend(repeat(repo, CompilerMode::Debug, times)?);
-    results.extend(repeat(repo, CompilerMode::Release, times)?);
+    results.extend(repeat(repo, CompilerMode::Check, sampling, &cache)?);
+    results.extend(repeat(repo, CompilerMode::Debug, sampling, &cache)?);
+    results.extend(repeat(repo, CompilerMode::Release, sampling, &cache)?);
 
     Ok(results)
 }
 
-pub(crate) fn size_profile(repo: &Repo) -> Result<(Bytes, Bytes)> {
-    let debug_size = get_file_size(repo, CompilerMode::Debug)?;
-    let release_size = get_file_size(repo, CompilerMode::Release)?;
-    Ok((debug_size, release_size))
+pub(crate) fn size_profile(repo: &Repo) -> Result<(OutputSizes, OutputSizes)> {
+    let debug_sizes = get_output_sizes(repo, CompilerMode::Debug)?;
+    let release_sizes = get_output_sizes(repo, CompilerMode::Release)?;
+    Ok((debug_sizes, release_sizes))
+}
+
+pub(crate) fn metadata(repo: &Repo) -> Result<Metadata> {
+    run_metadata(repo, &["--no-deps"])
+}
+
+/// Directories of the repo's packages and of all their dependencies.
+pub(crate) fn package_directories(repo: &Repo) -> Result<Vec<PathBuf>> {
+    let metadata = run_metadata(repo, &[])?;
+    let mut directories: Vec<PathBuf> = metadata
+        .packages
+        .into_iter()
+        .filter_map(|package| package.manifest_path.parent().map(Path::to_path_buf))
+        .collect();
+    directories.sort();
+    directories.dedup();
+    Ok(directories)
+}
+
+fn run_metadata(repo: &Repo, extra_args: &[&str]) -> Result<Metadata> {
+    let dir = repo
+        .get_base_directory()
+        .ok_or_else(|| anyhow!("Could not find repo dir"))?;
+    let output = process::run(
+        Command::new("cargo")
+            .current_dir(dir)
+            .envs(&repo.options.env)
+            .args(["metadata", "--format-version", "1"])
+            .args(extra_args),
+        CommandKind::Cargo,
+    )?;
+    if !output.status.success() {
+        return Err(CommandError::new("cargo metadata", &output.stderr).into());
+    }
+    serde_json::from_slice(&output.stdout).with_context(|| "failed to parse cargo metadata")
 }
 
 fn repeat(
     repo: &Repo,
     compiler_mode: CompilerMode,
-    times: u32,
-) -> Result<BTreeMap<(CompilerMode, ProfileMode), Vec<Milliseconds>>> {
-    let mut result = BTreeMap::new();
+    sampling: &Sampling,
+    cache: &Cache,
+) -> Result<BTreeMap<(CompilerMode, ProfileMode), Samples>> {
+    let mut result: BTreeMap<_, Vec<Milliseconds>> = BTreeMap::new();
     let f = match compiler_mode {
         CompilerMode::Check => cargo_check,
         CompilerMode::Debug => cargo_debug,
         CompilerMode::Release => cargo_release,
     };
 
-    for _ in 0..times {
+    for _ in 0..sampling.warmup {
+        log::info!("{} - Warming up {:?}", &repo.name, compiler_mode);
+        repo.remove_target_dir()?;
+        f(repo)?;
+        repo.touch_src()?;
+        f(repo)?;
+        repo.add_println()?;
+        f(repo)?;
+        repo.git_reset()?;
+    }
+
+    let mut conditions = None;
+    let mut iterations = 0;
+    let stop_reason = loop {
+        if iterations >= sampling.times {
+            if !result.values().any(|times| sampling.is_noisy(times)) {
+                break if iterations == sampling.times {
+                    StopReason::Times
+                } else {
+                    StopReason::Converged
+                };
+            }
+            if iterations >= sampling.max_times {
+                break StopReason::MaxSamples;
+            }
+            log::info!(
+                "{} - {:?} samples vary by more than {}, taking another",
+                &repo.name,
+                compiler_mode,
+                sampling.cv_threshold
+            );
+        }
+
         repo.remove_target_dir()?;
+        repo.ensure_target_dir_empty()?;
+        conditions = Conditions::merge(conditions, before_timed_build(cache)?);
         result
             .entry((compiler_mode, ProfileMode::Clean))
-            .or_insert_with(|| Vec::with_capacity(times as usize))
+            .or_insert_with(|| Vec::with_capacity(sampling.times as usize))
             .push(f(repo)?);
 
         repo.touch_src()?;
+        conditions = Conditions::merge(conditions, before_timed_build(cache)?);
         result
             .entry((compiler_mode, ProfileMode::Incremental))
-            .or_insert_with(|| Vec::with_capacity(times as usize))
+            .or_insert_with(|| Vec::with_capacity(sampling.times as usize))
             .push(f(repo)?);
 
         repo.add_println()?;
+        conditions = Conditions::merge(conditions, before_timed_build(cache)?);
         result
             .entry((compiler_mode, ProfileMode::PatchIncremental))
-            .or_insert_with(|| Vec::with_capacity(times as usize))
+            .or_insert_with(|| Vec::with_capacity(sampling.times as usize))
             .push(f(repo)?);
         repo.git_reset()?;
+        iterations += 1;
+    };
+
+    Ok(result
+        .into_iter()
+        .map(|(key, times)| {
+            let mut info = sampling.info(&times, stop_reason);
+            info.conditions = conditions.clone();
+            (key, Samples { times, info })
+        })
+        .collect())
+}
+
+/// Takes one sample of a pair with `toolchain`. Unlike `repeat`, which relies on the build
+/// before it, this first prepares the target directory and source tree the pair needs, so
+/// samples can be taken in any order. Also returns the conditions the sample was taken under.
+pub(crate) fn measure(
+    repo: &Repo,
+    toolchain: Version,
+    compiler_mode: CompilerMode,
+    profile_mode: ProfileMode,
+    cache: &Cache,
+) -> Result<(Milliseconds, Option<Conditions>)> {
+    log::info!(
+        "{} - Measuring {:?} {:?} on {}",
+        &repo.name,
+        compiler_mode,
+        profile_mode,
+        toolchain.get_string()
+    );
+    let toolchain = Some(toolchain);
+    match profile_mode {
+        ProfileMode::Clean => {
+            repo.remove_target_dir()?;
+            repo.ensure_target_dir_empty()?;
+            let conditions = before_timed_build(cache)?;
+            Ok((
+                cargo_with_toolchain(repo, compiler_mode, toolchain)?,
+                conditions,
+            ))
+        }
+        ProfileMode::Incremental => {
+            cargo_with_toolchain(repo, compiler_mode, toolchain)?;
+            repo.touch_src()?;
+            let conditions = before_timed_build(cache)?;
+            Ok((
+                cargo_with_toolchain(repo, compiler_mode, toolchain)?,
+                conditions,
+            ))
+        }
+        ProfileMode::PatchIncremental => {
+            cargo_with_toolchain(repo, compiler_mode, toolchain)?;
+            repo.add_println()?;
+            let result = before_timed_build(cache).and_then(|conditions| {
+                Ok((
+                    cargo_with_toolchain(repo, compiler_mode, toolchain)?,
+                    conditions,
+                ))
+            });
+            repo.git_reset()?;
+            result
+        }
     }
-    Ok(result)
+}
+
+/// Checks the machine, then prepares the page cache.
+fn before_timed_build(cache: &Cache) -> Result<Option<Conditions>> {
+    let conditions = conditions::check()?;
+    cache.prepare()?;
+    Ok(conditions)
 }
 
 fn cargo(repo: &Repo, mode: CompilerMode) -> Result<Milliseconds> {
+    cargo_with_toolchain(repo, mode, None)
+}
+
+fn cargo_with_toolchain(
+    repo: &Repo,
+    mode: CompilerMode,
+    toolchain: Option<Version>,
+) -> Result<Milliseconds> {
+    let output = run_cargo(repo, mode, toolchain, &[])?;
+    let stderr =
+        std::str::from_utf8(&output.stderr).with_context(|| "failed to decode stderr of cargo")?;
+    parse_run_time(stderr).ok_or_else(|| anyhow!("Failed to parse cargo output"))
+}
+
+/// Runs cargo with the default toolchain, or with `toolchain` if given.
+fn run_cargo(
+    repo: &Repo,
+    mode: CompilerMode,
+    toolchain: Option<Version>,
+    extra_args: &[&str],
+) -> Result<Output> {
     let dir = repo
         .get_base_directory()
         .ok_or_else(|| anyhow!("Could not find repo dir"))?;
@@ -119,26 +399,63 @@ fn cargo(repo: &Repo, mode: CompilerMode) -> Result<Milliseconds> {
         CompilerMode::Release => &["build", "--release"],
     };
 
-    let output = Command::new("cargo")
+    let mut command = Command::new("cargo");
+    command
         .current_dir(dir)
+        .envs(&repo.options.env)
         .args(args)
-        .output()
-        .with_context(|| "failed to execute cargo")?;
+        .args(repo.feature_set().args())
+        .args(extra_args);
+    if let Some(jobs) = conditions::jobs() {
+        command.arg("-j").arg(jobs.to_string());
+    }
+    if let Some(toolchain) = toolchain {
+        command.env("RUSTUP_TOOLCHAIN", toolchain.get_string());
+    }
+    let output = process::run(&mut command, CommandKind::Cargo)?;
     if !output.status.success() {
-        let stderr =
-            std::str::from_utf8(&output.stderr).with_context(|| "failed to decode output")?;
-        return Err(anyhow!("Failed to execute cargo. Stderr - {:?}", stderr));
+        return Err(CommandError::new("cargo", &output.stderr).into());
     }
-    let stderr =
-        std::str::from_utf8(&output.stderr).with_context(|| "failed to decode stderr of cargo")?;
-    parse_run_time(stderr).ok_or_else(|| anyhow!("Failed to parse cargo output"))
+    Ok(output)
 }
 
-fn cargo_check(repo: &Repo) -> Result<Milliseconds> {
+/// Downloads the repo's dependencies, so that no sample includes that. Fails with a
+/// `CommandError` of "cargo fetch", which `FailureReason::build_error` records against the
+/// version unless the network was at fault.
+pub(crate) fn fetch(repo: &Repo, toolchain: Option<Version>) -> Result<()> {
+    log::info!("{} - Fetching dependencies", &repo.name);
+    let dir = repo
+        .get_base_directory()
+        .ok_or_else(|| anyhow!("Could not find repo dir"))?;
+    let mut command = Command::new("cargo");
+    command
+        .current_dir(dir)
+        .envs(&repo.options.env)
+        .arg("fetch");
+    if let Some(toolchain) = toolchain {
+        command.env("RUSTUP_TOOLCHAIN", toolchain.get_string());
+    }
+    let output = process::run(&mut command, CommandKind::Cargo)?;
+    if !output.status.success() {
+        return Err(CommandError::new("cargo fetch", &output.stderr).into());
+    }
+    Ok(())
+}
+
+pub(crate) fn cargo_check(repo: &Repo) -> Result<Milliseconds> {
     log::info!("{} - Running cargo check", &repo.name);
     cargo(repo, CompilerMode::Check)
 }
 
+pub(crate) fn cargo_check_with_toolchain(repo: &Repo, toolchain: Version) -> Result<Milliseconds> {
+    log::info!(
+        "{} - Running cargo check on {}",
+        &repo.name,
+        toolchain.get_string()
+    );
+    cargo_with_toolchain(repo, CompilerMode::Check, Some(toolchain))
+}
+
 fn cargo_debug(repo: &Repo) -> Result<Milliseconds> {
     log::info!("{} - Running cargo build", &repo.name);
     cargo(repo, CompilerMode::Debug)
@@ -159,23 +476,222 @@ fn parse_run_time(stderr: &str) -> Option<Milliseconds> {
         .map(|d| Milliseconds(d.as_millis() as u64))
 }
 
-fn get_file_size(repo: &Repo, compiler_mode: CompilerMode) -> Result<Bytes> {
-    let output_path = match compiler_mode {
-        CompilerMode::Debug => {
-            cargo_debug(repo)?;
-            repo.get_debug_output_path()
+/// Builds the repo in Debug to check that it produces the `output` it names, if any.
+pub(crate) fn check_output(repo: &Repo) -> Result<()> {
+    if repo.output.is_some() {
+        get_output_sizes(repo, CompilerMode::Debug)?;
+    }
+    Ok(())
+}
+
+fn get_output_sizes(repo: &Repo, compiler_mode: CompilerMode) -> Result<OutputSizes> {
+    match compiler_mode {
+        CompilerMode::Debug => log::info!("{} - Running cargo build", &repo.name),
+        CompilerMode::Release => log::info!("{} - Running cargo release", &repo.name),
+        CompilerMode::Check => return Err(anyhow!("No associated output")),
+    };
+    let metadata = metadata(repo)?;
+    let base_directory = repo
+        .get_base_directory()
+        .ok_or_else(|| anyhow!("Could not find repo dir"))?;
+    let packages = metadata.built_packages(&base_directory, repo.options.package.as_deref());
+
+    let output = run_cargo(repo, compiler_mode, None, &["--message-format=json"])?;
+    let stdout =
+        std::str::from_utf8(&output.stdout).with_context(|| "failed to decode stdout of cargo")?;
+
+    let mut artifacts = BTreeMap::new();
+    for path in parse_artifacts(stdout, &packages) {
+        let file = std::fs::File::open(&path)
+            .with_context(|| anyhow!("failed to find output - {:?}", path))?;
+        let metadata = file.metadata()?;
+        artifacts.insert(artifact_name(&path), Bytes(metadata.len()));
+    }
+    if artifacts.is_empty() {
+        return Err(anyhow!("Build of {} produced no artifacts", &repo.name));
+    }
+
+    let output = match &repo.output {
+        Some(name) => *artifacts.get(name).ok_or_else(|| {
+            anyhow!(
+                "Output {} not found. Artifacts - {:?}",
+                name,
+                artifacts.keys().collect::<Vec<_>>()
+            )
+        })?,
+        None => Bytes(artifacts.values().map(|size| size.0).sum()),
+    };
+    Ok(OutputSizes { output, artifacts })
+}
+
+/// Finds the binaries and libraries produced for `packages` in cargo's `--message-format=json`
+/// output. Build scripts and metadata-only (`.rmeta`) files are skipped.
+fn parse_artifacts(stdout: &str, packages: &BTreeSet<&str>) -> Vec<PathBuf> {
+    let mut paths = Vec::new();
+    for line in stdout.lines().filter(|line| line.starts_with('{')) {
+        let message: Message = match serde_json::from_str(line) {
+            Ok(message) => message,
+            Err(e) => {
+                log::warn!("Failed to parse cargo message - {}", e);
+                continue;
+            }
+        };
+        if message.reason != "compiler-artifact" {
+            continue;
+        }
+        let in_package = message
+            .package_id
+            .as_deref()
+            .is_some_and(|id| packages.contains(id));
+        let is_build_script = message
+            .target
+            .is_some_and(|target| target.kind.iter().any(|k| k == "custom-build"));
+        if !in_package || is_build_script {
+            continue;
+        }
+        paths.extend(
+            message
+                .filenames
+                .into_iter()
+                .filter(|path| path.extension().is_none_or(|ext| ext != "rmeta")),
+        );
+    }
+    paths.sort();
+    paths.dedup();
+    paths
+}
+
+/// Name of an artifact with the `-<hash>` cargo appends to files under `deps/` removed, so that
+/// `deps/libgrep_cli-0a1b2c3d4e5f6a7b.rlib` is reported as `libgrep_cli.rlib`.
+fn artifact_name(path: &Path) -> String {
+    let file_name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let (stem, extension) = match file_name.find('.') {
+        Some(i) => file_name.split_at(i),
+        None => (file_name.as_str(), ""),
+    };
+    let stem = match stem.rfind('-') {
+        Some(i) if stem.len() - i == 17 && stem[i + 1..].chars().all(|c| c.is_ascii_hexdigit()) => {
+            &stem[..i]
+        }
+        _ => stem,
+    };
+    format!("{}{}", stem, extension)
+}
+
+fn is_zero(n: &u32) -> bool {
+    *n == 0
+}
+
+impl Milliseconds {
+    pub(crate) fn as_f64(self) -> f64 {
+        self.0 as f64
+    }
+
+    pub(crate) fn as_u64(self) -> u64 {
+        self.0
+    }
+}
+
+impl Bytes {
+    pub(crate) fn as_u64(self) -> u64 {
+        self.0
+    }
+}
+
+impl Sampling {
+    pub(crate) fn is_noisy(&self, times: &[Milliseconds]) -> bool {
+        let samples: Vec<f64> = times.iter().map(|t| t.as_f64()).collect();
+        stats::coefficient_of_variation(&samples) > self.cv_threshold
+    }
+
+    pub(crate) fn info(&self, times: &[Milliseconds], stop_reason: StopReason) -> SampleInfo {
+        let samples: Vec<f64> = times.iter().map(|t| t.as_f64()).collect();
+        SampleInfo {
+            cv: stats::coefficient_of_variation(&samples),
+            outliers: stats::outliers(&samples, self.outlier_fence),
+            stop_reason,
+            seed: None,
+            warmup: self.warmup,
+            cache: self.cache,
+            conditions: None,
+        }
+    }
+}
+
+impl SampleInfo {
+    /// Describes `times`, which are these samples followed by those of another run. Results
+    /// don't record the fence outliers were flagged with, so the default one is used, and the
+    /// seed of either run's schedule no longer applies.
+    pub(crate) fn merged(self, times: &[Milliseconds]) -> SampleInfo {
+        let samples: Vec<f64> = times.iter().map(|t| t.as_f64()).collect();
+        SampleInfo {
+            cv: stats::coefficient_of_variation(&samples),
+            outliers: stats::outliers(&samples, repo::DEFAULT_OUTLIER_FENCE),
+            seed: None,
+            ..self
+        }
+    }
+}
+
+impl FeatureSet {
+    pub(crate) fn is_default(&self) -> bool {
+        self == &FeatureSet::default()
+    }
+
+    fn args(&self) -> Vec<String> {
+        let mut args = Vec::new();
+        if !self.features.is_empty() {
+            args.push("--features".to_string());
+            args.push(self.features.join(","));
+        }
+        if self.all_features {
+            args.push("--all-features".to_string());
+        }
+        if self.no_default_features {
+            args.push("--no-default-features".to_string());
+        }
+        if let Some(package) = &self.package {
+            args.push("-p".to_string());
+            args.push(package.clone());
+        }
+        args.extend(self.cargo_args.iter().cloned());
+        args
+    }
+}
+
+impl Metadata {
+    /// Packages cargo builds when invoked from `base_directory`. That's `package` if one was
+    /// chosen, otherwise the package whose manifest lives there, or every workspace member if
+    /// it's a virtual manifest.
+    fn built_packages(&self, base_directory: &Path, package: Option<&str>) -> BTreeSet<&str> {
+        if let Some(name) = package {
+            return self
+                .packages
+                .iter()
+                .filter(|package| package.name == name)
+                .map(|package| package.id.as_str())
+                .collect();
         }
-        CompilerMode::Release => {
-            cargo_release(repo)?;
-            repo.get_release_output_path()
+        let manifest = base_directory.join("Cargo.toml");
+        let manifest = manifest.canonicalize().unwrap_or(manifest);
+        let root: BTreeSet<&str> = self
+            .packages
+            .iter()
+            .filter(|package| {
+                let path = &package.manifest_path;
+                path.canonicalize().as_ref().unwrap_or(path) == &manifest
+            })
+            .map(|package| package.id.as_str())
+            .collect();
+        if root.is_empty() {
+            self.workspace_members.iter().map(String::as_str).collect()
+        } else {
+            root
         }
-        _ => None,
     }
-    .ok_or_else(|| anyhow!("No associated output"))?;
-    let file = std::fs::File::open(&output_path)
-        .with_context(|| anyhow!("failed to find output - {:?}", output_path))?;
-    let metadata = file.metadata()?;
-    Ok(Bytes(metadata.len()))
 }
 
 impl std::str::FromStr for CompilerMode {
@@ -213,13 +729,22 @@ mod test {
     fn compile_time_hello_world() -> Result<()> {
         let repo = init_repo()?;
         let times: usize = 2;
-        let compile_times = compile_time_profile(&repo, times as u32)?; // run once on any version
+        let sampling = Sampling {
+            times: times as u32,
+            max_times: times as u32,
+            cv_threshold: 0.05,
+            outlier_fence: 3.0,
+            warmup: 0,
+            cache: CacheMode::Unmanaged,
+        };
+        let compile_times = compile_time_profile(&repo, &sampling)?; // run once on any version
 
         for compiler_mode in CompilerMode::into_enum_iter() {
             for profile_mode in ProfileMode::into_enum_iter() {
-                let result_times = compile_times.get(&(compiler_mode, profile_mode)).unwrap();
-                assert_eq!(result_times.len(), times);
-                assert!(result_times[0] > Milliseconds(0));
+                let samples = compile_times.get(&(compiler_mode, profile_mode)).unwrap();
+                assert_eq!(samples.times.len(), times);
+                assert!(samples.times[0] > Milliseconds(0));
+                assert_ne!(samples.info.stop_reason, StopReason::Converged);
             }
         }
 
@@ -230,10 +755,11 @@ mod test {
     fn output_size_hello_world() -> Result<()> {
         let repo = init_repo()?;
 
-        let (debug_size, release_size) = size_profile(&repo)?;
+        let (debug_sizes, release_sizes) = size_profile(&repo)?;
 
-        assert!(release_size > Bytes(0));
-        assert!(debug_size > release_size);
+        assert!(release_sizes.output > Bytes(0));
+        assert!(debug_sizes.output > release_sizes.output);
+        assert!(debug_sizes.artifacts.contains_key("helloworld"));
 
         Ok(())
     }
@@ -285,4 +811,135 @@ mod test {
         }
         Ok(())
     }
+
+    #[test]
+    fn parse_artifact_messages() {
+        let stdout = r#"{"reason":"compiler-artifact","package_id":"memchr 2.3.4 (registry+https://github.com/rust-lang/crates.io-index)","target":{"kind":["lib"],"crate_types":["lib"],"name":"memchr"},"filenames":["/t/debug/deps/libmemchr-3f1ba1b2c1a2d4e5.rlib","/t/debug/deps/libmemchr-3f1ba1b2c1a2d4e5.rmeta"],"executable":null,"fresh":true}
+{"reason":"compiler-artifact","package_id":"grep-cli 0.1.5 (path+file:///r/crates/cli)","target":{"kind":["lib"],"crate_types":["lib"],"name":"grep_cli"},"filenames":["/t/debug/deps/libgrep_cli-0a1b2c3d4e5f6a7b.rlib","/t/debug/deps/libgrep_cli-0a1b2c3d4e5f6a7b.rmeta"],"executable":null,"fresh":false}
+{"reason":"compiler-artifact","package_id":"ripgrep 12.1.1 (path+file:///r)","target":{"kind":["custom-build"],"crate_types":["bin"],"name":"build-script-build"},"filenames":["/t/debug/build/ripgrep-1234567890abcdef/build-script-build"],"executable":null,"fresh":false}
+{"reason":"build-script-executed","package_id":"ripgrep 12.1.1 (path+file:///r)","linked_libs":[],"linked_paths":[],"cfgs":[],"env":[],"out_dir":"/t/debug/build/ripgrep-fedcba0987654321/out"}
+{"reason":"compiler-artifact","package_id":"ripgrep 12.1.1 (path+file:///r)","target":{"kind":["bin"],"crate_types":["bin"],"name":"rg"},"filenames":["/t/debug/rg"],"executable":"/t/debug/rg","fresh":false}
+{"reason":"build-finished","success":true}"#;
+        let packages = [
+            "grep-cli 0.1.5 (path+file:///r/crates/cli)",
+            "ripgrep 12.1.1 (path+file:///r)",
+        ]
+        .iter()
+        .copied()
+        .collect();
+
+        let artifacts = parse_artifacts(stdout, &packages);
+        assert_eq!(
+            artifacts,
+            vec![
+                PathBuf::from("/t/debug/deps/libgrep_cli-0a1b2c3d4e5f6a7b.rlib"),
+                PathBuf::from("/t/debug/rg"),
+            ]
+        );
+    }
+
+    #[test]
+    fn artifact_names() {
+        let inputs = [
+            ("/t/debug/rg", "rg"),
+            ("/t/debug/libsyn.rlib", "libsyn.rlib"),
+            (
+                "/t/release/deps/libgrep_cli-0a1b2c3d4e5f6a7b.rlib",
+                "libgrep_cli.rlib",
+            ),
+            (
+                "/t/release/deps/libserde_derive-52a8c7f7a1d2e3f4.so",
+                "libserde_derive.so",
+            ),
+            ("/t/release/tree-sitter-cli", "tree-sitter-cli"),
+        ];
+        for (input, expected) in inputs.iter() {
+            assert_eq!(&artifact_name(Path::new(input)), expected);
+        }
+    }
+
+    #[test]
+    fn parse_metadata() -> Result<()> {
+        let metadata: Metadata = serde_json::from_str(
+            r#"{
+                "packages": [
+                    {"name": "html5ever", "id": "html5ever 0.25.1 (path+file:///w/html5ever)", "manifest_path": "/w/html5ever/Cargo.toml"},
+                    {"name": "markup5ever", "id": "markup5ever 0.10.0 (path+file:///w/markup5ever)", "manifest_path": "/w/markup5ever/Cargo.toml"}
+                ],
+                "workspace_members": [
+                    "html5ever 0.25.1 (path+file:///w/html5ever)",
+                    "markup5ever 0.10.0 (path+file:///w/markup5ever)"
+                ],
+                "resolve": null,
+                "target_directory": "/w/target",
+                "version": 1,
+                "workspace_root": "/w"
+            }"#,
+        )?;
+        assert_eq!(metadata.target_directory, PathBuf::from("/w/target"));
+
+        let member = metadata.built_packages(Path::new("/w/html5ever"), None);
+        assert_eq!(
+            member.into_iter().collect::<Vec<_>>(),
+            vec!["html5ever 0.25.1 (path+file:///w/html5ever)"]
+        );
+        let workspace = metadata.built_packages(Path::new("/w"), None);
+        assert_eq!(workspace.len(), 2);
+        let selected = metadata.built_packages(Path::new("/w"), Some("markup5ever"));
+        assert_eq!(
+            selected.into_iter().collect::<Vec<_>>(),
+            vec!["markup5ever 0.10.0 (path+file:///w/markup5ever)"]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn feature_set_args() {
+        assert!(FeatureSet::default().args().is_empty());
+
+        let feature_set = FeatureSet {
+            features: vec!["full".to_string(), "test-util".to_string()],
+            all_features: false,
+            no_default_features: true,
+            package: Some("tokio".to_string()),
+            cargo_args: vec!["--locked".to_string()],
+        };
+        assert_eq!(
+            feature_set.args(),
+            vec![
+                "--features",
+                "full,test-util",
+                "--no-default-features",
+                "-p",
+                "tokio",
+                "--locked"
+            ]
+        );
+    }
+
+    #[test]
+    fn sample_info() {
+        let sampling = Sampling {
+            times: 3,
+            max_times: 6,
+            cv_threshold: 0.05,
+            outlier_fence: 3.0,
+            warmup: 0,
+            cache: CacheMode::Unmanaged,
+        };
+        let stable = [Milliseconds(1000), Milliseconds(1010), Milliseconds(990)];
+        assert!(!sampling.is_noisy(&stable));
+
+        let noisy = [
+            Milliseconds(1000),
+            Milliseconds(1010),
+            Milliseconds(990),
+            Milliseconds(1005),
+            Milliseconds(1800),
+        ];
+        assert!(sampling.is_noisy(&noisy));
+        let info = sampling.info(&noisy, StopReason::MaxSamples);
+        assert_eq!(info.outliers, vec![4]);
+        assert_eq!(info.stop_reason, StopReason::MaxSamples);
+    }
 }
@@ -0,0 +1,345 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Result};
+use enum_iterator::IntoEnumIterator;
+use serde::Serialize;
+
+use crate::cargo::{CompilerMode, Milliseconds, ProfileMode};
+use crate::rustup::Version;
+use crate::system::SystemInfo;
+use crate::{stats, store};
+
+/// How `compare` prints its results.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) enum Format {
+    Table,
+    Json,
+    Markdown,
+}
+
+impl std::str::FromStr for Format {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "table" => Ok(Format::Table),
+            "json" => Ok(Format::Json),
+            "markdown" => Ok(Format::Markdown),
+            _ => Err("unknown format, expected table, json or markdown"),
+        }
+    }
+}
+
+/// The compile times of one repo in one pair of CompilerMode and ProfileMode on two versions.
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct Comparison {
+    pub(crate) machine: String,
+    pub(crate) repo: String,
+    pub(crate) compiler_mode: CompilerMode,
+    pub(crate) profile_mode: ProfileMode,
+    /// Median compile time in milliseconds.
+    pub(crate) baseline: f64,
+    pub(crate) candidate: f64,
+    /// Change of the median as a percentage of the baseline.
+    pub(crate) change: f64,
+    /// 95% bootstrap confidence interval of `change`.
+    pub(crate) change_ci: [f64; 2],
+    /// Of the Mann-Whitney U test.
+    pub(crate) p_value: f64,
+    pub(crate) verdict: Verdict,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize)]
+pub(crate) enum Verdict {
+    Regression,
+    Improvement,
+    Unchanged,
+    /// Too few samples for any change to be significant at the given alpha.
+    InsufficientSamples,
+}
+
+/// Bootstrap resamples for the confidence interval of each change. Seeded, so that the same
+/// results always give the same intervals.
+const RESAMPLES: usize = 10_000;
+const RESAMPLE_SEED: u64 = 0;
+
+/// Prints how the compile times of every repo changed from `baseline` to `candidate`, on each
+/// machine with results in `results_files`.
+pub(crate) fn compare(
+    results_files: &[PathBuf],
+    baseline: Version,
+    candidate: Version,
+    alpha: f64,
+    format: Format,
+) -> Result<()> {
+    let comparisons = comparisons(results_files, baseline, candidate, alpha)?;
+    if comparisons.is_empty() {
+        return Err(anyhow!(
+            "No repo has samples of both {} and {}",
+            baseline.get_string(),
+            candidate.get_string()
+        ));
+    }
+    match format {
+        Format::Json => println!("{}", serde_json::to_string_pretty(&comparisons)?),
+        Format::Table => print!("{}", table(&comparisons, false)),
+        Format::Markdown => print!("{}", table(&comparisons, true)),
+    }
+    Ok(())
+}
+
+/// Compares every repo with samples of both versions. A change is significant if its p-value
+/// is below `alpha`.
+pub(crate) fn comparisons(
+    results_files: &[PathBuf],
+    baseline: Version,
+    candidate: Version,
+    alpha: f64,
+) -> Result<Vec<Comparison>> {
+    let mut comparisons = Vec::new();
+    for path in results_files {
+        let (system_info, profiles) = store::read_results(path)?;
+        let machine = machine_label(path, &system_info)?;
+
+        for (repo, profile) in &profiles {
+            for compiler_mode in CompilerMode::into_enum_iter() {
+                for profile_mode in ProfileMode::into_enum_iter() {
+                    let before = profile.compile_times(baseline, compiler_mode, profile_mode);
+                    let after = profile.compile_times(candidate, compiler_mode, profile_mode);
+                    if let (Some(before), Some(after)) = (before, after) {
+                        comparisons.push(Comparison::new(
+                            machine.clone(),
+                            repo.clone(),
+                            compiler_mode,
+                            profile_mode,
+                            (before, after),
+                            alpha,
+                        ));
+                    }
+                }
+            }
+        }
+    }
+    Ok(comparisons)
+}
+
+/// The label of the machine a results file belongs to, from `machines.json` next to it, or its ID
+/// if it isn't registered.
+pub(crate) fn machine_label(results_file: &Path, system_info: &SystemInfo) -> Result<String> {
+    let id = store::machine_id(system_info);
+    let dir = results_file.parent().unwrap_or_else(|| Path::new("."));
+    Ok(match store::get_machines(dir)?.remove(&id) {
+        Some(machine) => machine.label,
+        None => id,
+    })
+}
+
+impl Comparison {
+    pub(crate) fn new(
+        machine: String,
+        repo: String,
+        compiler_mode: CompilerMode,
+        profile_mode: ProfileMode,
+        (before, after): (&[Milliseconds], &[Milliseconds]),
+        alpha: f64,
+    ) -> Comparison {
+        let before: Vec<f64> = before.iter().map(|ms| ms.as_f64()).collect();
+        let after: Vec<f64> = after.iter().map(|ms| ms.as_f64()).collect();
+        let baseline = stats::median(&before);
+        let candidate = stats::median(&after);
+        let change = if baseline == 0.0 {
+            0.0
+        } else {
+            (candidate - baseline) / baseline * 100.0
+        };
+        let change_ci = stats::median_change_ci(&before, &after, RESAMPLES, RESAMPLE_SEED);
+        let p_value = stats::mann_whitney(&before, &after);
+        let verdict = if stats::min_mann_whitney_p(before.len(), after.len()) >= alpha {
+            Verdict::InsufficientSamples
+        } else if p_value >= alpha || candidate == baseline {
+            Verdict::Unchanged
+        } else if candidate > baseline {
+            Verdict::Regression
+        } else {
+            Verdict::Improvement
+        };
+        Comparison {
+            machine,
+            repo,
+            compiler_mode,
+            profile_mode,
+            baseline,
+            candidate,
+            change,
+            change_ci,
+            p_value,
+            verdict,
+        }
+    }
+
+    pub(crate) fn mode(&self) -> String {
+        format!("{:?}/{:?}", self.compiler_mode, self.profile_mode)
+    }
+}
+
+/// Columns aligned with spaces, or a markdown table. Either way followed by the number of
+/// significant changes.
+fn table(comparisons: &[Comparison], markdown: bool) -> String {
+    let header = [
+        "machine",
+        "repo",
+        "mode",
+        "baseline",
+        "candidate",
+        "change",
+        "95% CI",
+        "p",
+        "",
+    ];
+    let rows: Vec<Vec<String>> = comparisons
+        .iter()
+        .map(|c| {
+            let verdict = match c.verdict {
+                Verdict::Regression => "regression",
+                Verdict::Improvement => "improvement",
+                Verdict::Unchanged => "",
+                Verdict::InsufficientSamples => "insufficient samples",
+            };
+            vec![
+                c.machine.clone(),
+                c.repo.clone(),
+                c.mode(),
+                format!("{:.2}s", c.baseline / 1000.0),
+                format!("{:.2}s", c.candidate / 1000.0),
+                format!("{:+.1}%", c.change),
+                format!("{:+.1}% to {:+.1}%", c.change_ci[0], c.change_ci[1]),
+                format!("{:.3}", c.p_value),
+                verdict.to_string(),
+            ]
+        })
+        .collect();
+    // Times, changes and p-values are right aligned.
+    let numeric = |column: usize| (3..8).contains(&column);
+
+    let mut output = String::new();
+    if markdown {
+        output.push_str(&format!("| {} |\n", header.join(" | ")));
+        let separators: Vec<&str> = (0..header.len())
+            .map(|column| if numeric(column) { "---:" } else { "---" })
+            .collect();
+        output.push_str(&format!("| {} |\n", separators.join(" | ")));
+        for row in &rows {
+            output.push_str(&format!("| {} |\n", row.join(" | ")));
+        }
+        output.push('\n');
+    } else {
+        let widths: Vec<usize> = (0..header.len())
+            .map(|column| {
+                rows.iter()
+                    .map(|row| row[column].len())
+                    .chain(std::iter::once(header[column].len()))
+                    .max()
+                    .unwrap_or(0)
+            })
+            .collect();
+        let header: Vec<String> = header.iter().map(|cell| cell.to_string()).collect();
+        for row in std::iter::once(&header).chain(&rows) {
+            let cells: Vec<String> = row
+                .iter()
+                .enumerate()
+                .map(|(column, cell)| {
+                    if numeric(column) {
+                        format!("{:>width$}", cell, width = widths[column])
+                    } else {
+                        format!("{:width$}", cell, width = widths[column])
+                    }
+                })
+                .collect();
+            output.push_str(cells.join("  ").trim_end());
+            output.push('\n');
+        }
+    }
+
+    let count = |verdict| comparisons.iter().filter(|c| c.verdict == verdict).count();
+    output.push_str(&format!(
+        "{} regressions, {} improvements, {} unchanged\n",
+        count(Verdict::Regression),
+        count(Verdict::Improvement),
+        count(Verdict::Unchanged)
+    ));
+    let insufficient = count(Verdict::InsufficientSamples);
+    if insufficient > 0 {
+        output.push_str(&format!(
+            "{} with too few samples to tell, take more with --times\n",
+            insufficient
+        ));
+    }
+    output
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use anyhow::Result;
+
+    #[test]
+    fn test_comparisons() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("arewefastyet-compare-{}", std::process::id()));
+        std::fs::create_dir_all(&dir)?;
+        let path = dir.join("results-2-3ca971dfaacb948d.json");
+        std::fs::write(
+            &path,
+            r#"{
+                "system_info": {
+                    "num_cores": 2,
+                    "num_physical_cores": 2,
+                    "cpu_model": "Intel(R) Xeon(R) Gold 6140 CPU @ 2.30GHz"
+                },
+                "profiles": {
+                    "syn": {
+                        "compile_times": {
+                            "1.51.0,Check,Clean": [1000, 1010, 990, 1005, 995],
+                            "1.52.0,Check,Clean": [1100, 1110, 1090, 1105, 1095],
+                            "1.51.0,Check,Incremental": [500, 510, 490],
+                            "1.52.0,Check,Incremental": [600, 610, 590],
+                            "1.51.0,Debug,Clean": [2000, 2010, 1990, 2005, 1995],
+                            "1.52.0,Debug,Clean": [1000, 2500, 1980, 2015, 1990],
+                            "1.52.0,Release,Clean": [3000, 3000, 3000, 3000, 3000]
+                        },
+                        "output_sizes": {}
+                    }
+                }
+            }"#,
+        )?;
+        let result = comparisons(&[path], Version::V1_51, Version::V1_52, 0.05);
+        std::fs::remove_dir_all(&dir)?;
+        let comparisons = result?;
+
+        assert_eq!(comparisons.len(), 3);
+        let check = &comparisons[0];
+        assert_eq!(check.machine, "3ca971dfaacb948d");
+        assert_eq!(check.compiler_mode, CompilerMode::Check);
+        assert_eq!(check.baseline, 1000.0);
+        assert_eq!(check.candidate, 1100.0);
+        assert!((check.change - 10.0).abs() < 1e-9);
+        assert_eq!(check.verdict, Verdict::Regression);
+        assert!(check.change_ci[0] <= 10.0 && 10.0 <= check.change_ci[1]);
+        // 3 samples of each can't give a p-value below 0.1
+        assert_eq!(comparisons[1].verdict, Verdict::InsufficientSamples);
+        assert_eq!(comparisons[2].verdict, Verdict::Unchanged);
+
+        let markdown = table(&comparisons, true);
+        assert!(markdown.contains("| 3ca971dfaacb948d | syn | Check/Clean | 1.00s | 1.10s | +10.0% | +8.5% to +11.6% | 0.008 | regression |"));
+        assert!(markdown.ends_with(
+            "1 regressions, 0 improvements, 1 unchanged\n\
+             1 with too few samples to tell, take more with --times\n"
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_format_from_str() {
+        assert_eq!("markdown".parse::<Format>(), Ok(Format::Markdown));
+        assert!("csv".parse::<Format>().is_err());
+    }
+}
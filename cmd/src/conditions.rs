@@ -0,0 +1,397 @@
+use std::fmt;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::time::Duration;
+
+use anyhow::{anyhow, Context, Result};
+use once_cell::sync::OnceCell;
+use serde::{Deserialize, Serialize};
+
+static SETTINGS: OnceCell<Settings> = OnceCell::new();
+/// Current `-j` for cargo, 0 for cargo's default. Starts as `Settings::jobs`.
+static JOBS: AtomicU32 = AtomicU32::new(0);
+/// Highest share of CPU time other processes may use right before a timed build.
+const MAX_BUSY: f64 = 0.1;
+const BUSY_WINDOW: Duration = Duration::from_secs(1);
+/// Windows to wait for the machine to go quiet before giving up.
+const BUSY_RETRIES: u32 = 30;
+
+/// How the environment builds run in is controlled. Set once from the command line.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Settings {
+    /// Checks the machine before every timed build and refuses to run if it isn't quiet.
+    pub(crate) quiet_machine: bool,
+    /// CPUs to pin arewefastyet, and so cargo and rustc, to.
+    pub(crate) cpus: Option<Vec<usize>>,
+    /// Passed to cargo as `-j`.
+    pub(crate) jobs: Option<u32>,
+    /// Highest 1 minute load average at which a run may start.
+    pub(crate) max_load: f64,
+}
+
+/// The conditions samples were taken under. `None` where the machine doesn't say, e.g. a VM
+/// without cpufreq.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(crate) struct Conditions {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) governor: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) turbo: Option<bool>,
+    /// Value of /proc/sys/kernel/randomize_va_space. 0 means disabled.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) aslr: Option<u8>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) cpus: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) jobs: Option<u32>,
+    /// Highest share of CPU time used by other processes right before any of the samples.
+    /// Only measured with --quiet-machine.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) busy: Option<f64>,
+}
+
+/// The machine isn't quiet enough to take samples on. Unlike a build error, this ends the run.
+#[derive(Debug)]
+pub(crate) struct NotQuietError(String);
+
+pub(crate) fn set_settings(settings: Settings) {
+    set_jobs(settings.jobs);
+    SETTINGS.get_or_init(|| settings);
+}
+
+pub(crate) fn jobs() -> Option<u32> {
+    match JOBS.load(Ordering::SeqCst) {
+        0 => None,
+        jobs => Some(jobs),
+    }
+}
+
+/// Changes `-j` for the cargo invocations that follow, e.g. for a scaling study.
+pub(crate) fn set_jobs(jobs: Option<u32>) {
+    JOBS.store(jobs.unwrap_or(0), Ordering::SeqCst);
+}
+
+/// Pins the process to the chosen CPUs and, in quiet machine mode, refuses to start unless the
+/// machine is quiet. Called once before anything is built.
+pub(crate) fn apply() -> Result<()> {
+    let settings = match SETTINGS.get() {
+        Some(settings) => settings,
+        None => return Ok(()),
+    };
+    if let Some(cpus) = &settings.cpus {
+        pin(cpus)?;
+        log::info!("Pinned to CPUs {}", format_cpus(cpus));
+    }
+    if settings.quiet_machine {
+        let conditions = check_machine(settings)?;
+        let load = load_average()?;
+        if load > settings.max_load {
+            return Err(NotQuietError(format!(
+                "load average is {}, more than {}",
+                load, settings.max_load
+            ))
+            .into());
+        }
+        log::info!("Machine is quiet - {:?}", conditions);
+    }
+    Ok(())
+}
+
+/// Checks the machine right before a timed build. Returns the conditions to record with the
+/// sample, or `None` unless the environment is controlled at all.
+pub(crate) fn check() -> Result<Option<Conditions>> {
+    let settings = match SETTINGS.get() {
+        Some(settings) if settings.quiet_machine || settings.cpus.is_some() || jobs().is_some() => {
+            settings
+        }
+        _ => return Ok(None),
+    };
+    if !settings.quiet_machine {
+        return Ok(Some(Conditions {
+            governor: None,
+            turbo: None,
+            aslr: None,
+            cpus: settings.cpus.as_deref().map(format_cpus),
+            jobs: jobs(),
+            busy: None,
+        }));
+    }
+
+    let mut conditions = check_machine(settings)?;
+    let mut last = 0.0;
+    for _ in 0..BUSY_RETRIES {
+        last = busy(settings.cpus.as_deref())?;
+        if last <= MAX_BUSY {
+            conditions.busy = Some(last);
+            return Ok(Some(conditions));
+        }
+        log::warn!(
+            "Other processes use {:.0}% of the CPU, waiting",
+            last * 100.0
+        );
+    }
+    Err(NotQuietError(format!(
+        "other processes used {:.0}% of the CPU for {:?}",
+        last * 100.0,
+        BUSY_WINDOW * BUSY_RETRIES
+    ))
+    .into())
+}
+
+impl Conditions {
+    /// Conditions covering the samples of both, which are the same apart from how busy the
+    /// machine was, since a change would have failed the checks.
+    pub(crate) fn merge(a: Option<Conditions>, b: Option<Conditions>) -> Option<Conditions> {
+        match (a, b) {
+            (Some(a), Some(b)) => Some(Conditions {
+                busy: match (a.busy, b.busy) {
+                    (Some(a), Some(b)) => Some(a.max(b)),
+                    (a, b) => a.or(b),
+                },
+                ..b
+            }),
+            (a, b) => a.or(b),
+        }
+    }
+}
+
+fn check_machine(settings: &Settings) -> Result<Conditions> {
+    let conditions = Conditions {
+        governor: governor(settings.cpus.as_deref()),
+        turbo: turbo(),
+        aslr: read_sys("/proc/sys/kernel/randomize_va_space").and_then(|s| s.parse().ok()),
+        cpus: settings.cpus.as_deref().map(format_cpus),
+        jobs: jobs(),
+        busy: None,
+    };
+    match conditions.governor.as_deref() {
+        Some("performance") => {}
+        Some(governor) => {
+            return Err(NotQuietError(format!(
+                "CPU frequency governor is {}, not performance",
+                governor
+            ))
+            .into())
+        }
+        None => log::warn!("CPU frequency governor is unknown"),
+    }
+    match conditions.turbo {
+        Some(true) => return Err(NotQuietError("turbo boost is enabled".to_string()).into()),
+        Some(false) => {}
+        None => log::warn!("Turbo boost state is unknown"),
+    }
+    if conditions.aslr != Some(0) {
+        return Err(NotQuietError(format!(
+            "ASLR is not disabled, randomize_va_space is {:?}",
+            conditions.aslr
+        ))
+        .into());
+    }
+    Ok(conditions)
+}
+
+/// The governors of `cpus`, or of all CPUs, joined with commas if they differ.
+fn governor(cpus: Option<&[usize]>) -> Option<String> {
+    let cpus: Vec<usize> = match cpus {
+        Some(cpus) => cpus.to_vec(),
+        None => (0..num_cpus::get()).collect(),
+    };
+    let mut governors = Vec::new();
+    for cpu in cpus {
+        governors.push(read_sys(&format!(
+            "/sys/devices/system/cpu/cpu{}/cpufreq/scaling_governor",
+            cpu
+        ))?);
+    }
+    governors.sort();
+    governors.dedup();
+    Some(governors.join(","))
+}
+
+fn turbo() -> Option<bool> {
+    if let Some(no_turbo) = read_sys("/sys/devices/system/cpu/intel_pstate/no_turbo") {
+        return Some(no_turbo == "0");
+    }
+    read_sys("/sys/devices/system/cpu/cpufreq/boost").map(|boost| boost == "1")
+}
+
+fn read_sys(path: &str) -> Option<String> {
+    std::fs::read_to_string(path)
+        .ok()
+        .map(|s| s.trim().to_string())
+}
+
+fn load_average() -> Result<f64> {
+    let loadavg =
+        std::fs::read_to_string("/proc/loadavg").with_context(|| "failed to read /proc/loadavg")?;
+    loadavg
+        .split_whitespace()
+        .next()
+        .and_then(|load| load.parse().ok())
+        .ok_or_else(|| anyhow!("failed to parse /proc/loadavg - {}", loadavg))
+}
+
+/// Share of the time `cpus`, or all CPUs, weren't idle during `BUSY_WINDOW`.
+fn busy(cpus: Option<&[usize]>) -> Result<f64> {
+    let before = cpu_times(cpus)?;
+    std::thread::sleep(BUSY_WINDOW);
+    let after = cpu_times(cpus)?;
+    let total = after.0.saturating_sub(before.0);
+    let idle = after.1.saturating_sub(before.1);
+    if total == 0 {
+        return Ok(0.0);
+    }
+    Ok(total.saturating_sub(idle) as f64 / total as f64)
+}
+
+/// Total and idle jiffies of `cpus`, or of all CPUs.
+fn cpu_times(cpus: Option<&[usize]>) -> Result<(u64, u64)> {
+    let stat =
+        std::fs::read_to_string("/proc/stat").with_context(|| "failed to read /proc/stat")?;
+    Ok(parse_cpu_times(&stat, cpus))
+}
+
+fn parse_cpu_times(stat: &str, cpus: Option<&[usize]>) -> (u64, u64) {
+    let mut total = 0;
+    let mut idle = 0;
+    for line in stat.lines() {
+        let mut fields = line.split_whitespace();
+        let name = fields.next().unwrap_or_default();
+        let selected = match cpus {
+            None => name == "cpu",
+            Some(cpus) => name
+                .strip_prefix("cpu")
+                .and_then(|n| n.parse::<usize>().ok())
+                .is_some_and(|n| cpus.contains(&n)),
+        };
+        if !selected {
+            continue;
+        }
+        let times: Vec<u64> = fields.filter_map(|field| field.parse().ok()).collect();
+        total += times.iter().sum::<u64>();
+        // idle and iowait
+        idle += times.iter().skip(3).take(2).sum::<u64>();
+    }
+    (total, idle)
+}
+
+/// Parses a CPU list like `taskset -c` takes, e.g. "2-5,7".
+pub(crate) fn parse_cpus(list: &str) -> Result<Vec<usize>> {
+    let mut cpus = Vec::new();
+    for range in list.split(',') {
+        let (start, end) = match range.split_once('-') {
+            Some((start, end)) => (start, end),
+            None => (range, range),
+        };
+        let start: usize = start
+            .trim()
+            .parse()
+            .with_context(|| anyhow!("invalid CPU list - {}", list))?;
+        let end: usize = end
+            .trim()
+            .parse()
+            .with_context(|| anyhow!("invalid CPU list - {}", list))?;
+        if start > end {
+            return Err(anyhow!("invalid CPU list - {}", list));
+        }
+        cpus.extend(start..=end);
+    }
+    cpus.sort_unstable();
+    cpus.dedup();
+    Ok(cpus)
+}
+
+fn format_cpus(cpus: &[usize]) -> String {
+    let mut ranges: Vec<String> = Vec::new();
+    let mut i = 0;
+    while i < cpus.len() {
+        let start = cpus[i];
+        while i + 1 < cpus.len() && cpus[i + 1] == cpus[i] + 1 {
+            i += 1;
+        }
+        if cpus[i] == start {
+            ranges.push(start.to_string());
+        } else {
+            ranges.push(format!("{}-{}", start, cpus[i]));
+        }
+        i += 1;
+    }
+    ranges.join(",")
+}
+
+/// Pins this process to `cpus`. The children it starts inherit the affinity.
+#[cfg(target_os = "linux")]
+fn pin(cpus: &[usize]) -> Result<()> {
+    unsafe {
+        let mut set: libc::cpu_set_t = std::mem::zeroed();
+        for cpu in cpus {
+            libc::CPU_SET(*cpu, &mut set);
+        }
+        if libc::sched_setaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &set) != 0 {
+            return Err(std::io::Error::last_os_error())
+                .with_context(|| anyhow!("failed to pin to CPUs {}", format_cpus(cpus)));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn pin(_cpus: &[usize]) -> Result<()> {
+    Err(anyhow!("Pinning to CPUs is only supported on Linux"))
+}
+
+impl fmt::Display for NotQuietError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Machine is not quiet - {}", self.0)
+    }
+}
+
+impl std::error::Error for NotQuietError {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use anyhow::Result;
+
+    #[test]
+    fn test_cpu_lists() -> Result<()> {
+        assert_eq!(parse_cpus("2-5,7")?, vec![2, 3, 4, 5, 7]);
+        assert_eq!(parse_cpus("3,1,1")?, vec![1, 3]);
+        assert!(parse_cpus("5-2").is_err());
+        assert!(parse_cpus("a").is_err());
+        assert_eq!(format_cpus(&[0, 1, 2, 3, 6, 8, 9]), "0-3,6,8-9");
+        assert_eq!(format_cpus(&[]), "");
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_cpu_times() {
+        let stat = "cpu  10 0 10 70 10 0 0 0 0 0\n\
+                    cpu0 5 0 5 30 0 0 0 0 0 0\n\
+                    cpu1 5 0 5 40 10 0 0 0 0 0\n\
+                    intr 1 2 3";
+        assert_eq!(parse_cpu_times(stat, None), (100, 80));
+        assert_eq!(parse_cpu_times(stat, Some(&[1])), (60, 50));
+        assert_eq!(parse_cpu_times(stat, Some(&[0, 1])), (100, 80));
+    }
+
+    #[test]
+    fn test_merge_conditions() {
+        let conditions = |busy| Conditions {
+            governor: Some("performance".to_string()),
+            turbo: Some(false),
+            aslr: Some(0),
+            cpus: Some("2-5".to_string()),
+            jobs: Some(4),
+            busy: Some(busy),
+        };
+        assert_eq!(
+            Conditions::merge(Some(conditions(0.05)), Some(conditions(0.02))),
+            Some(conditions(0.05))
+        );
+        assert_eq!(
+            Conditions::merge(None, Some(conditions(0.02))),
+            Some(conditions(0.02))
+        );
+        assert_eq!(Conditions::merge(None, None), None);
+    }
+}
@@ -0,0 +1,209 @@
+use std::fs::File;
+use std::path::Path;
+
+use anyhow::{anyhow, Context, Result};
+use serde::Serialize;
+
+use crate::cargo::{CompilerMode, ProfileMode};
+use crate::compare;
+use crate::stats::Summary;
+use crate::store;
+
+/// Bumped whenever the dashboard changes in a way the site has to be updated for. The site
+/// refuses to build from a dashboard with a different version.
+const SCHEMA_VERSION: u32 = 1;
+
+/// Everything the site shows, summarised from the results files of every machine.
+#[derive(Debug, Serialize)]
+struct Dashboard {
+    schema_version: u32,
+    /// Fewest cores first.
+    machines: Vec<Machine>,
+    /// In the order of the repos file.
+    repos: Vec<RepoData>,
+}
+
+#[derive(Debug, Serialize)]
+struct Machine {
+    id: String,
+    label: String,
+    num_cores: usize,
+}
+
+#[derive(Debug, Serialize)]
+struct RepoData {
+    name: String,
+    url: String,
+    commit: String,
+    compile_times: Vec<CompileTime>,
+    sizes: Vec<Size>,
+    failures: Vec<Failure>,
+}
+
+/// Summary of the compile times of one pair of CompilerMode and ProfileMode, in milliseconds.
+#[derive(Debug, Serialize)]
+struct CompileTime {
+    machine: String,
+    version: &'static str,
+    compiler_mode: CompilerMode,
+    profile_mode: ProfileMode,
+    #[serde(flatten)]
+    summary: Summary,
+}
+
+#[derive(Debug, Serialize)]
+struct Size {
+    machine: String,
+    version: &'static str,
+    compiler_mode: CompilerMode,
+    bytes: u64,
+}
+
+#[derive(Debug, Serialize)]
+struct Failure {
+    machine: String,
+    version: &'static str,
+    kind: &'static str,
+}
+
+/// Writes the dashboard the site is built from, for the repos in the repos file. It is
+/// generated, and large, so it is not pretty printed.
+pub(crate) fn dashboard(repos_file: &Path, results_dir: &Path, output: &Path) -> Result<()> {
+    let repos = store::get_repos(repos_file, &Default::default())?;
+    let mut machines = Vec::new();
+    let mut results = Vec::new();
+    for path in store::get_result_files(results_dir)? {
+        let (system_info, profiles) = store::read_results(&path)?;
+        let id = store::machine_id(&system_info);
+        machines.push(Machine {
+            id: id.clone(),
+            label: compare::machine_label(&path, &system_info)?,
+            num_cores: system_info.num_cores,
+        });
+        results.push((id, profiles));
+    }
+    machines.sort_by(|a, b| (a.num_cores, &a.label).cmp(&(b.num_cores, &b.label)));
+
+    let repos = repos
+        .iter()
+        .map(|repo| {
+            let mut data = RepoData {
+                name: repo.name.clone(),
+                url: repo.url().to_string(),
+                commit: repo.commit().to_string(),
+                compile_times: Vec::new(),
+                sizes: Vec::new(),
+                failures: Vec::new(),
+            };
+            for (machine, profiles) in &results {
+                if let Some(profile) = profiles.get(&repo.name) {
+                    data.add(machine, profile);
+                }
+            }
+            data
+        })
+        .collect();
+
+    let dashboard = Dashboard {
+        schema_version: SCHEMA_VERSION,
+        machines,
+        repos,
+    };
+    let file = File::create(output)
+        .with_context(|| anyhow!("Failed to create dashboard - {:?}", output))?;
+    log::info!("Writing to {:?}", output);
+    serde_json::to_writer(file, &dashboard)?;
+    Ok(())
+}
+
+impl RepoData {
+    fn add(&mut self, machine: &str, profile: &crate::profile::Profile) {
+        for (version, compiler_mode, profile_mode, times) in profile.all_compile_times() {
+            let times: Vec<f64> = times.iter().map(|ms| ms.as_f64()).collect();
+            self.compile_times.push(CompileTime {
+                machine: machine.to_string(),
+                version: version.get_string(),
+                compiler_mode,
+                profile_mode,
+                summary: rounded(Summary::new(&times)),
+            });
+        }
+        for (version, compiler_mode, bytes) in profile.all_output_sizes() {
+            self.sizes.push(Size {
+                machine: machine.to_string(),
+                version: version.get_string(),
+                compiler_mode,
+                bytes: bytes.as_u64(),
+            });
+        }
+        for (version, failure) in profile.all_failures() {
+            self.failures.push(Failure {
+                machine: machine.to_string(),
+                version: version.get_string(),
+                kind: failure.kind(),
+            });
+        }
+    }
+}
+
+/// To a tenth of a millisecond, since the samples themselves are whole milliseconds.
+fn rounded(summary: Summary) -> Summary {
+    let round = |x: f64| (x * 10.0).round() / 10.0;
+    Summary {
+        mean: round(summary.mean),
+        median: round(summary.median),
+        ci: summary.ci.map(|[low, high]| [round(low), round(high)]),
+        ..summary
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use anyhow::Result;
+
+    #[test]
+    fn test_repo_data() -> Result<()> {
+        let profile: crate::profile::Profile = serde_json::from_str(
+            r#"{
+                "compile_times": {
+                    "1.51.0,Check,Clean": [1000, 1010, 1011]
+                },
+                "output_sizes": { "1.51.0,Release": 4096 },
+                "failures": {
+                    "1.52.0": { "kind": "Timeout", "attempts": 1, "last_attempt": 10 }
+                }
+            }"#,
+        )?;
+        let mut data = RepoData {
+            name: "syn".to_string(),
+            url: String::new(),
+            commit: String::new(),
+            compile_times: Vec::new(),
+            sizes: Vec::new(),
+            failures: Vec::new(),
+        };
+        data.add("3ca971dfaacb948d", &profile);
+
+        let value = serde_json::to_value(&data)?;
+        assert_eq!(
+            value["compile_times"][0],
+            serde_json::json!({
+                "machine": "3ca971dfaacb948d",
+                "version": "1.51.0",
+                "compiler_mode": "Check",
+                "profile_mode": "Clean",
+                "samples": 3,
+                "mean": 1007.0,
+                "median": 1010.0,
+                "ci": [991.9, 1022.1],
+                "min": 1000.0,
+                "max": 1011.0
+            })
+        );
+        assert_eq!(value["sizes"][0]["bytes"], 4096);
+        assert_eq!(value["failures"][0]["kind"], "Timeout");
+        assert_eq!(value["failures"][0]["version"], "1.52.0");
+        Ok(())
+    }
+}
@@ -0,0 +1,350 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Context, Result};
+
+use crate::{compare, store};
+
+/// One table of the export, with a row per sample, size or machine.
+struct Table {
+    name: &'static str,
+    /// Names and SQLite types.
+    columns: &'static [(&'static str, &'static str)],
+    /// Columns of each SQLite index.
+    #[cfg_attr(not(feature = "sqlite"), allow(dead_code))]
+    indexes: &'static [&'static str],
+    rows: Vec<Vec<Value>>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Value {
+    Text(String),
+    Integer(i64),
+    Null,
+}
+
+const SAMPLES: &[(&str, &str)] = &[
+    ("machine", "TEXT"),
+    ("repo", "TEXT"),
+    ("toolchain", "TEXT"),
+    ("compiler_mode", "TEXT"),
+    ("profile_mode", "TEXT"),
+    ("sample", "INTEGER"),
+    ("ms", "INTEGER"),
+];
+
+const SIZES: &[(&str, &str)] = &[
+    ("machine", "TEXT"),
+    ("repo", "TEXT"),
+    ("toolchain", "TEXT"),
+    ("compiler_mode", "TEXT"),
+    ("bytes", "INTEGER"),
+];
+
+/// `label` comes from `machines.json`, the rest from `system_info`.
+const MACHINES: &[(&str, &str)] = &[
+    ("machine", "TEXT"),
+    ("label", "TEXT"),
+    ("num_cores", "INTEGER"),
+    ("num_physical_cores", "INTEGER"),
+    ("cpu_model", "TEXT"),
+    ("memory_gib", "INTEGER"),
+    ("kernel", "TEXT"),
+    ("os", "TEXT"),
+    ("filesystem", "TEXT"),
+    ("rotational", "INTEGER"),
+    ("rustup_host", "TEXT"),
+];
+
+/// Flattens `results_files` into long tables of samples, sizes and machines. A path ending in
+/// `.sqlite` or `.db` is replaced with a SQLite database, anything else is a directory that
+/// `samples.csv`, `sizes.csv` and `machines.csv` are written to.
+pub(crate) fn export(results_files: &[PathBuf], output: &Path) -> Result<()> {
+    let tables = tables(results_files)?;
+    let is_sqlite = matches!(
+        output.extension().and_then(|extension| extension.to_str()),
+        Some("sqlite") | Some("db")
+    );
+    if is_sqlite {
+        write_sqlite(output, &tables)
+    } else {
+        write_csv(output, &tables)
+    }
+}
+
+fn tables(results_files: &[PathBuf]) -> Result<Vec<Table>> {
+    let mut samples = Vec::new();
+    let mut sizes = Vec::new();
+    let mut machines = Vec::new();
+    for path in results_files {
+        let (system_info, profiles) = store::read_results(path)?;
+        let id = store::machine_id(&system_info);
+        let label = compare::machine_label(path, &system_info)?;
+
+        let system_info = serde_json::to_value(&system_info)?;
+        let mut machine = vec![Value::Text(id.clone()), Value::Text(label)];
+        machine.extend(
+            MACHINES[2..]
+                .iter()
+                .map(|(name, _)| Value::from_json(&system_info[name])),
+        );
+        machines.push(machine);
+
+        for (repo, profile) in &profiles {
+            for (version, compiler_mode, profile_mode, times) in profile.all_compile_times() {
+                for (i, ms) in times.iter().enumerate() {
+                    samples.push(vec![
+                        Value::Text(id.clone()),
+                        Value::Text(repo.clone()),
+                        Value::Text(version.get_string().to_string()),
+                        Value::Text(format!("{:?}", compiler_mode)),
+                        Value::Text(format!("{:?}", profile_mode)),
+                        Value::Integer(i as i64),
+                        Value::Integer(ms.as_u64() as i64),
+                    ]);
+                }
+            }
+            for (version, compiler_mode, bytes) in profile.all_output_sizes() {
+                sizes.push(vec![
+                    Value::Text(id.clone()),
+                    Value::Text(repo.clone()),
+                    Value::Text(version.get_string().to_string()),
+                    Value::Text(format!("{:?}", compiler_mode)),
+                    Value::Integer(bytes.as_u64() as i64),
+                ]);
+            }
+        }
+    }
+
+    Ok(vec![
+        Table {
+            name: "samples",
+            columns: SAMPLES,
+            indexes: &["machine", "repo, toolchain, compiler_mode, profile_mode"],
+            rows: samples,
+        },
+        Table {
+            name: "sizes",
+            columns: SIZES,
+            indexes: &["repo, toolchain, compiler_mode"],
+            rows: sizes,
+        },
+        Table {
+            name: "machines",
+            columns: MACHINES,
+            indexes: &[],
+            rows: machines,
+        },
+    ])
+}
+
+fn write_csv(dir: &Path, tables: &[Table]) -> Result<()> {
+    std::fs::create_dir_all(dir)
+        .with_context(|| anyhow!("Failed to create export directory - {:?}", dir))?;
+    for table in tables {
+        let path = dir.join(format!("{}.csv", table.name));
+        log::info!("Writing to {:?}", path);
+        let mut writer = csv::Writer::from_path(&path)
+            .with_context(|| anyhow!("Failed to create {:?}", path))?;
+        writer.write_record(table.columns.iter().map(|(name, _)| name))?;
+        for row in &table.rows {
+            writer.write_record(row.iter().map(|value| value.to_string()))?;
+        }
+        writer.flush()?;
+    }
+    Ok(())
+}
+
+#[cfg(feature = "sqlite")]
+fn write_sqlite(path: &Path, tables: &[Table]) -> Result<()> {
+    if path.exists() {
+        std::fs::remove_file(path)?;
+    }
+    log::info!("Writing to {:?}", path);
+    let mut connection = rusqlite::Connection::open(path)
+        .with_context(|| anyhow!("Failed to create database - {:?}", path))?;
+    let transaction = connection.transaction()?;
+    for table in tables {
+        transaction.execute_batch(&table.schema())?;
+        let placeholders = vec!["?"; table.columns.len()].join(", ");
+        let mut insert = transaction.prepare(&format!(
+            "INSERT INTO {} VALUES ({})",
+            table.name, placeholders
+        ))?;
+        for row in &table.rows {
+            insert.execute(rusqlite::params_from_iter(row))?;
+        }
+    }
+    transaction.commit()?;
+    Ok(())
+}
+
+#[cfg(not(feature = "sqlite"))]
+fn write_sqlite(_path: &Path, _tables: &[Table]) -> Result<()> {
+    Err(anyhow!(
+        "Exporting to SQLite needs arewefastyet to be built with --features sqlite"
+    ))
+}
+
+#[cfg(feature = "sqlite")]
+impl Table {
+    fn schema(&self) -> String {
+        let columns: Vec<String> = self
+            .columns
+            .iter()
+            .map(|(name, kind)| format!("{} {}", name, kind))
+            .collect();
+        let mut schema = format!("CREATE TABLE {} ({});\n", self.name, columns.join(", "));
+        for (i, columns) in self.indexes.iter().enumerate() {
+            schema.push_str(&format!(
+                "CREATE INDEX {}_{} ON {} ({});\n",
+                self.name, i, self.name, columns
+            ));
+        }
+        schema
+    }
+}
+
+impl Value {
+    /// Booleans become 0 or 1, as in SQLite.
+    fn from_json(value: &serde_json::Value) -> Value {
+        match value {
+            serde_json::Value::String(s) => Value::Text(s.clone()),
+            serde_json::Value::Number(n) => match n.as_i64() {
+                Some(n) => Value::Integer(n),
+                None => Value::Text(n.to_string()),
+            },
+            serde_json::Value::Bool(b) => Value::Integer(*b as i64),
+            _ => Value::Null,
+        }
+    }
+}
+
+/// As written to CSV, with NULL as an empty field.
+impl std::fmt::Display for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Value::Text(s) => write!(f, "{}", s),
+            Value::Integer(n) => write!(f, "{}", n),
+            Value::Null => Ok(()),
+        }
+    }
+}
+
+#[cfg(feature = "sqlite")]
+impl rusqlite::ToSql for Value {
+    fn to_sql(&self) -> rusqlite::Result<rusqlite::types::ToSqlOutput<'_>> {
+        use rusqlite::types::{ToSqlOutput, ValueRef};
+        Ok(match self {
+            Value::Text(s) => ToSqlOutput::Borrowed(ValueRef::Text(s.as_bytes())),
+            Value::Integer(n) => ToSqlOutput::Borrowed(ValueRef::Integer(*n)),
+            Value::Null => ToSqlOutput::Borrowed(ValueRef::Null),
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use anyhow::Result;
+
+    /// A temporary directory for a test, removed when dropped, so that a failed assertion
+    /// doesn't leave it behind.
+    struct TestDir(PathBuf);
+
+    impl TestDir {
+        fn new(name: &str) -> Result<TestDir> {
+            let dir =
+                std::env::temp_dir().join(format!("arewefastyet-{}-{}", name, std::process::id()));
+            if dir.exists() {
+                std::fs::remove_dir_all(&dir)?;
+            }
+            std::fs::create_dir_all(&dir)?;
+            Ok(TestDir(dir))
+        }
+
+        fn path(&self) -> &Path {
+            &self.0
+        }
+    }
+
+    impl Drop for TestDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    /// With a quote in the CPU model, which the CSV has to escape.
+    fn write_results(name: &str) -> Result<(TestDir, PathBuf)> {
+        let dir = TestDir::new(name)?;
+        let path = dir.path().join("results-2-f31d1a4bc5c78b96.json");
+        std::fs::write(
+            &path,
+            r#"{
+                "system_info": {
+                    "num_cores": 2,
+                    "num_physical_cores": 2,
+                    "cpu_model": "Intel(R) Xeon(R) Gold 6140 CPU @ 2.30GHz, \"rev 4\""
+                },
+                "profiles": {
+                    "syn": {
+                        "compile_times": {
+                            "1.51.0,Check,Clean": [1000, 1010],
+                            "1.52.0,Debug,Incremental": [2000]
+                        },
+                        "output_sizes": {
+                            "1.51.0,Debug": 4096
+                        }
+                    }
+                }
+            }"#,
+        )?;
+        Ok((dir, path))
+    }
+
+    #[test]
+    fn test_export_csv() -> Result<()> {
+        let (dir, path) = write_results("export")?;
+        let output = dir.path().join("csv");
+        export(&[path], &output)?;
+        let samples = std::fs::read_to_string(output.join("samples.csv"));
+        let sizes = std::fs::read_to_string(output.join("sizes.csv"));
+        let machines = std::fs::read_to_string(output.join("machines.csv"));
+
+        assert_eq!(
+            samples?,
+            "machine,repo,toolchain,compiler_mode,profile_mode,sample,ms\n\
+             f31d1a4bc5c78b96,syn,1.51.0,Check,Clean,0,1000\n\
+             f31d1a4bc5c78b96,syn,1.51.0,Check,Clean,1,1010\n\
+             f31d1a4bc5c78b96,syn,1.52.0,Debug,Incremental,0,2000\n"
+        );
+        assert_eq!(
+            sizes?,
+            "machine,repo,toolchain,compiler_mode,bytes\n\
+             f31d1a4bc5c78b96,syn,1.51.0,Debug,4096\n"
+        );
+        assert!(machines?.ends_with(
+            "f31d1a4bc5c78b96,f31d1a4bc5c78b96,2,2,\
+             \"Intel(R) Xeon(R) Gold 6140 CPU @ 2.30GHz, \"\"rev 4\"\"\",,,,,,\n"
+        ));
+        Ok(())
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[test]
+    fn test_export_sqlite() -> Result<()> {
+        let (dir, path) = write_results("sqlite")?;
+        let output = dir.path().join("results.sqlite");
+        export(&[path], &output)?;
+        let connection = rusqlite::Connection::open(&output)?;
+        let total: i64 = connection.query_row(
+            "SELECT SUM(ms) FROM samples WHERE compiler_mode = 'Check'",
+            [],
+            |row| row.get(0),
+        )?;
+        let rotational: Option<i64> =
+            connection.query_row("SELECT rotational FROM machines", [], |row| row.get(0))?;
+
+        assert_eq!((total, rotational), (2010, None));
+        Ok(())
+    }
+}
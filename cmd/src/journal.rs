@@ -0,0 +1,211 @@
+use std::collections::BTreeMap;
+use std::fs::OpenOptions;
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::profile::Profile;
+use crate::rustup::Version;
+use crate::store;
+use crate::system::SystemInfo;
+
+/// Versions profiled since the results file was last written, one JSON fragment per line. The
+/// results file is only written after each repo, so if a run is killed, the versions of the
+/// repo it was on are recovered from here by the next run, or merged with `merge`. A version is recorded again whenever more of
+/// it has been profiled, and each fragment has everything recorded about it at the time.
+pub(crate) struct Journal {
+    path: PathBuf,
+    system_info: SystemInfo,
+}
+
+/// What was recorded about one version of one repo.
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct Fragment {
+    pub(crate) system_info: SystemInfo,
+    pub(crate) repo: String,
+    /// Seconds since the Unix epoch.
+    pub(crate) recorded: u64,
+    pub(crate) profile: Profile,
+}
+
+impl Journal {
+    /// The journal of this machine in `results_dir`.
+    pub(crate) fn new(results_dir: &Path) -> Result<Journal> {
+        let system_info = SystemInfo::current()?;
+        let path = results_dir.join(format!(
+            "journal-{}.ndjson",
+            store::machine_id(&system_info)
+        ));
+        Ok(Journal { path, system_info })
+    }
+
+    /// Merges what an interrupted run left in the journal into `profiles`, which were read from
+    /// the results file. The journal is newer, so its versions replace theirs. Returns whether
+    /// there was anything to recover, after which the results file should be written and the
+    /// journal cleared.
+    pub(crate) fn recover(&self, profiles: &mut BTreeMap<String, Profile>) -> Result<bool> {
+        if !self.path.exists() {
+            return Ok(false);
+        }
+        let id = store::machine_id(&self.system_info);
+        let fragments = replay(read_journal(&self.path)?);
+        if let Some(other) = fragments
+            .iter()
+            .find(|fragment| store::machine_id(&fragment.system_info) != id)
+        {
+            return Err(anyhow!(
+                "{:?} has samples of another machine, {}. Merge it with `merge {}`, or move it aside",
+                &self.path,
+                store::machine_id(&other.system_info),
+                self.path.display()
+            ));
+        }
+        for fragment in fragments {
+            log::warn!(
+                "Recovering the versions of {} profiled by an interrupted run from {:?}",
+                &fragment.repo,
+                &self.path
+            );
+            profiles
+                .entry(fragment.repo)
+                .or_insert_with(Profile::new)
+                .replace_versions(fragment.profile);
+        }
+        Ok(true)
+    }
+
+    /// Appends everything `profile` has recorded about `version`.
+    pub(crate) fn record(&self, repo: &str, profile: &Profile, version: Version) -> Result<()> {
+        let fragment = Fragment {
+            system_info: self.system_info.clone(),
+            repo: repo.to_string(),
+            recorded: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_secs()),
+            profile: profile.only(version),
+        };
+        let mut line = serde_json::to_string(&fragment)?;
+        line.push('\n');
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .with_context(|| anyhow!("Failed to open journal - {:?}", &self.path))?;
+        file.write_all(line.as_bytes())?;
+        Ok(())
+    }
+
+    /// Call once the results file has everything in the journal.
+    pub(crate) fn clear(&self) -> Result<()> {
+        if self.path.exists() {
+            std::fs::remove_file(&self.path)
+                .with_context(|| anyhow!("Failed to remove journal - {:?}", &self.path))?;
+        }
+        Ok(())
+    }
+}
+
+/// Whether `path` is a journal rather than a results file.
+pub(crate) fn is_journal(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| ext == "ndjson")
+}
+
+/// Folds the fragments of a journal into one per machine and repo. A later fragment replaces
+/// what an earlier one recorded about the same version.
+pub(crate) fn replay(fragments: Vec<Fragment>) -> Vec<Fragment> {
+    let mut replayed: Vec<Fragment> = Vec::new();
+    for fragment in fragments {
+        let existing = replayed.iter_mut().find(|existing| {
+            existing.repo == fragment.repo
+                && store::machine_id(&existing.system_info)
+                    == store::machine_id(&fragment.system_info)
+        });
+        match existing {
+            Some(existing) => {
+                existing.recorded = existing.recorded.max(fragment.recorded);
+                existing.profile.replace_versions(fragment.profile);
+            }
+            None => replayed.push(fragment),
+        }
+    }
+    replayed
+}
+
+/// Reads every fragment of a journal. A truncated last line, from a run killed while writing
+/// it, is skipped.
+pub(crate) fn read_journal(path: &Path) -> Result<Vec<Fragment>> {
+    let file = std::fs::File::open(path)
+        .with_context(|| anyhow!("Failed to open journal - {:?}", path))?;
+    let lines: Vec<String> = BufReader::new(file).lines().collect::<Result<_, _>>()?;
+    let mut fragments = Vec::new();
+    for (i, line) in lines.iter().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        match serde_json::from_str(line) {
+            Ok(fragment) => fragments.push(fragment),
+            Err(e) if i + 1 == lines.len() => {
+                log::warn!("Skipping the truncated last line of {:?} - {}", path, e)
+            }
+            Err(e) => {
+                return Err(e)
+                    .with_context(|| anyhow!("Failed to parse line {} of {:?}", i + 1, path))
+            }
+        }
+    }
+    Ok(fragments)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cargo::{CompilerMode, ProfileMode};
+
+    #[test]
+    fn test_recover() -> Result<()> {
+        let path = std::env::temp_dir().join(format!(
+            "arewefastyet-journal-{}.ndjson",
+            std::process::id()
+        ));
+        let journal = Journal {
+            path,
+            system_info: serde_json::from_str(
+                r#"{
+                    "num_cores": 2,
+                    "num_physical_cores": 2,
+                    "cpu_model": "Intel(R) Xeon(R) Gold 6140 CPU @ 2.30GHz"
+                }"#,
+            )?,
+        };
+        let mut profiles = BTreeMap::new();
+        assert!(!journal.recover(&mut profiles)?);
+
+        let profiled: Profile = serde_json::from_str(
+            r#"{ "compile_times": { "1.51.0,Check,Clean": [1000, 1010, 990] }, "output_sizes": {} }"#,
+        )?;
+        journal.record("syn", &profiled, Version::V1_51)?;
+        let results: Profile = serde_json::from_str(
+            r#"{
+                "compile_times": { "1.50.0,Check,Clean": [700], "1.51.0,Check,Clean": [500] },
+                "output_sizes": {}
+            }"#,
+        )?;
+        profiles.insert("syn".to_string(), results);
+
+        let recovered = journal.recover(&mut profiles);
+        journal.clear()?;
+
+        assert!(recovered?);
+        let samples = |version| {
+            profiles["syn"]
+                .compile_times(version, CompilerMode::Check, ProfileMode::Clean)
+                .map(|times| times.iter().map(|ms| ms.as_u64()).collect::<Vec<_>>())
+        };
+        assert_eq!(samples(Version::V1_50), Some(vec![700]));
+        assert_eq!(samples(Version::V1_51), Some(vec![1000, 1010, 990]));
+        Ok(())
+    }
+}
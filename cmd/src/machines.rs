@@ -0,0 +1,103 @@
+use std::collections::BTreeSet;
+use std::path::Path;
+
+use anyhow::Result;
+
+use crate::rustup::Version;
+use crate::store;
+
+/// Prints every machine with results, and for each repo in the repos file the versions
+/// profiled on it.
+pub(crate) fn machines(repos_file: &Path, results_dir: &Path) -> Result<()> {
+    let repos = store::get_repos(repos_file, &Default::default())?;
+    let machines = store::get_machines(results_dir)?;
+    let name_width = repos.iter().map(|repo| repo.name.len()).max().unwrap_or(0);
+
+    let mut seen = BTreeSet::new();
+    for path in store::get_result_files(results_dir)? {
+        let (system_info, profiles) = store::read_results(&path)?;
+        let id = store::machine_id(&system_info);
+        let label = match machines.get(&id) {
+            Some(machine) => match &machine.instance_type {
+                Some(instance_type) => format!("{} ({})", machine.label, instance_type),
+                None => machine.label.clone(),
+            },
+            None => "unregistered".to_string(),
+        };
+        println!("{} - {}", &id, label);
+        seen.insert(id);
+
+        for repo in &repos {
+            let coverage = match profiles.get(&repo.name) {
+                Some(profile) => {
+                    let mut coverage = format_versions(&profile.profiled_versions());
+                    let failed = profile.failed_versions();
+                    if !failed.is_empty() {
+                        coverage.push_str(&format!(" (failed {})", format_versions(&failed)));
+                    }
+                    coverage
+                }
+                None => format_versions(&[]),
+            };
+            println!(
+                "    {:width$}  {}",
+                &repo.name,
+                coverage,
+                width = name_width
+            );
+        }
+    }
+
+    for (id, machine) in machines {
+        if !seen.contains(&id) {
+            println!("{} - {} - no results", id, machine.label);
+        }
+    }
+    Ok(())
+}
+
+/// Versions as ranges of consecutive releases, e.g. "1.34.0-1.40.0, 1.45.0".
+fn format_versions(versions: &[Version]) -> String {
+    if versions.is_empty() {
+        return "none".to_string();
+    }
+    let mut ranges: Vec<(Version, Version)> = Vec::new();
+    for version in versions {
+        match ranges.last_mut() {
+            Some((_, end)) if *end as u8 + 1 == *version as u8 => *end = *version,
+            _ => ranges.push((*version, *version)),
+        }
+    }
+    ranges
+        .into_iter()
+        .map(|(start, end)| {
+            if start == end {
+                start.get_string().to_string()
+            } else {
+                format!("{}-{}", start.get_string(), end.get_string())
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_format_versions() {
+        assert_eq!(format_versions(&[]), "none");
+        assert_eq!(
+            format_versions(&[
+                Version::V1_34,
+                Version::V1_35,
+                Version::V1_36,
+                Version::V1_40,
+                Version::V1_52,
+                Version::V1_53
+            ]),
+            "1.34.0-1.36.0, 1.40.0, 1.52.0-1.53.0"
+        );
+    }
+}
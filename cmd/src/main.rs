@@ -1,88 +1,573 @@
+mod cache;
 mod cargo;
+mod compare;
+mod conditions;
+mod dashboard;
+mod export;
+mod journal;
+mod machines;
+mod merge;
+mod metrics;
+mod migrate;
+mod process;
 mod profile;
+mod progress;
+mod regressions;
 mod repo;
+mod report;
 mod rustup;
+mod scaling;
+mod schedule;
+mod serve;
+mod show;
+mod stats;
 mod store;
 mod system;
+mod validate;
 
+use std::collections::BTreeMap;
 use std::path::PathBuf;
+use std::time::Duration;
 
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use structopt::StructOpt;
 
+use profile::FailureReason;
+
 #[derive(Debug, StructOpt)]
 #[structopt(name = "arewefastyet", about = "Benchmark the rust compiler")]
 struct Opt {
-    #[structopt(short, long, default_value = "5")]
-    times: u32,
+    /// Samples per CompilerMode and ProfileMode, unless the repo sets its own [default: 5]
+    #[structopt(short, long)]
+    times: Option<u32>,
     #[structopt(short, long, default_value = "/tmp/prof", parse(from_os_str))]
     working_directory: PathBuf,
-    #[structopt(long, default_value = "../data/repos.json", parse(from_os_str))]
+    #[structopt(long, default_value = "../data/repos.toml", parse(from_os_str))]
     repos_file: PathBuf,
     #[structopt(long, default_value = "../data/", parse(from_os_str))]
     results_dir: PathBuf,
+    /// Results file of this machine from before a kernel or OS update, to continue under the
+    /// machine's new name instead of starting a new one
+    #[structopt(long, parse(from_os_str))]
+    continue_from: Option<PathBuf>,
+    /// Untimed builds of each CompilerMode and ProfileMode before the first sample, unless the
+    /// repo sets its own [default: 0]
+    #[structopt(long)]
+    warmup: Option<u32>,
+    /// What to do to the page cache before every timed build - Unmanaged, Cold (drop it, needs
+    /// root) or Warm (read the sources of the repo and its dependencies) [default: Unmanaged]
+    #[structopt(long)]
+    cache: Option<cache::CacheMode>,
+    /// Number of failed attempts after which a version is only retried once --retry-after has
+    /// passed since the last one
+    #[structopt(long, default_value = "1")]
+    max_attempts: u32,
+    /// Time after which a version that failed --max-attempts times is tried again, e.g. "30d"
+    #[structopt(long, default_value = "30d", parse(try_from_str = parse_duration::parse))]
+    retry_after: Duration,
+    /// Samples all versions of a repo in randomly interleaved rounds instead of one after another
+    #[structopt(long)]
+    interleave: bool,
+    /// Seed for the order of --interleave. Recorded with the samples
+    #[structopt(long)]
+    seed: Option<u64>,
+    /// Refuses to take samples unless the CPU governor is performance, turbo boost and ASLR are
+    /// off and nothing else is running. Needs the machine to be set up beforehand
+    #[structopt(long)]
+    quiet_machine: bool,
+    /// Highest 1 minute load average at which --quiet-machine starts a run
+    #[structopt(long, default_value = "0.5")]
+    max_load: f64,
+    /// CPUs to pin cargo to, e.g. "2-5"
+    #[structopt(long)]
+    cpus: Option<String>,
+    /// Passed to cargo as -j
+    #[structopt(long)]
+    jobs: Option<u32>,
+    /// Time after which a cargo invocation is killed, e.g. "90m"
+    #[structopt(long, default_value = "2h", parse(try_from_str = parse_duration::parse))]
+    cargo_timeout: Duration,
+    /// Time after which a rustup invocation is killed
+    #[structopt(long, default_value = "30m", parse(try_from_str = parse_duration::parse))]
+    rustup_timeout: Duration,
+    /// Time after which a git invocation is killed
+    #[structopt(long, default_value = "30m", parse(try_from_str = parse_duration::parse))]
+    git_timeout: Duration,
+    /// How to show the progress of a run, with an estimate of the time left - bar, ndjson (one
+    /// JSON event per line on stdout), off, or auto (a bar if stderr is a terminal)
+    #[structopt(long, default_value = "auto")]
+    progress: progress::Output,
+    #[structopt(subcommand)]
+    command: Option<Command>,
+}
+
+/// Without a subcommand, arewefastyet collects samples for every repo.
+#[derive(Debug, StructOpt)]
+enum Command {
+    /// Checks the repos file and builds each repo once on its min_version, without recording samples
+    Validate,
+    /// Converts the repos file to the format of the output file's extension, json or toml
+    Convert {
+        #[structopt(short, long, parse(from_os_str))]
+        output: PathBuf,
+    },
+    /// Compares the compile times of two versions on every machine, flagging significant
+    /// regressions and improvements
+    Compare {
+        /// Version to compare against, e.g. "1.51.0"
+        baseline: rustup::Version,
+        candidate: rustup::Version,
+        /// Results file to read, can be repeated [default: every results file in --results-dir]
+        #[structopt(long = "results-file", number_of_values = 1, parse(from_os_str))]
+        results_files: Vec<PathBuf>,
+        /// Highest p-value of the Mann-Whitney U test at which a change is significant
+        #[structopt(long, default_value = "0.05")]
+        alpha: f64,
+        /// table, json or markdown
+        #[structopt(long, default_value = "table")]
+        format: compare::Format,
+    },
+    /// Fails, with exit code 2, if the newest version's compile times are significantly slower
+    /// than those of the versions before it. Prints a JSON report of the regressions
+    CheckRegressions {
+        /// Version to check, e.g. "1.53.0" [default: the newest with samples on each machine]
+        #[structopt(long)]
+        version: Option<rustup::Version>,
+        /// Number of earlier versions with samples whose samples are pooled into the baseline
+        #[structopt(long, default_value = "1")]
+        window: usize,
+        /// Largest acceptable increase of a median compile time, as a percentage
+        #[structopt(long, default_value = "5")]
+        threshold: f64,
+        /// Threshold for a CompilerMode or a pair of modes, e.g. "Check=3" or
+        /// "Release/PatchIncremental=10". Can be repeated
+        #[structopt(long = "mode-threshold", number_of_values = 1)]
+        mode_thresholds: Vec<regressions::ModeThreshold>,
+        /// Highest p-value of the Mann-Whitney U test at which a change is significant
+        #[structopt(long, default_value = "0.05")]
+        alpha: f64,
+        /// Results file to read, can be repeated [default: every results file in --results-dir]
+        #[structopt(long = "results-file", number_of_values = 1, parse(from_os_str))]
+        results_files: Vec<PathBuf>,
+    },
+    /// Summarises every results file into the dashboard the site is built from
+    Dashboard {
+        /// [default: dashboard.json in --results-dir]
+        #[structopt(short, long, parse(from_os_str))]
+        output: Option<PathBuf>,
+    },
+    /// Flattens every results file into long tables of samples, sizes and machines
+    Export {
+        /// Directory to write samples.csv, sizes.csv and machines.csv to, or a SQLite database
+        /// ending in .sqlite or .db, which is replaced. SQLite needs the sqlite feature
+        #[structopt(short, long, parse(from_os_str))]
+        output: PathBuf,
+        /// Results file to read, can be repeated [default: every results file in --results-dir]
+        #[structopt(long = "results-file", number_of_values = 1, parse(from_os_str))]
+        results_files: Vec<PathBuf>,
+    },
+    /// Lists the machines with results, and which versions of each repo they cover
+    Machines,
+    /// Writes the latest median compile times and output sizes, and the progress of runs in
+    /// progress, as OpenMetrics gauges
+    Metrics {
+        /// File to replace [default: stdout]
+        #[structopt(short, long, parse(from_os_str))]
+        output: Option<PathBuf>,
+    },
+    /// Merges results files and journals of interrupted runs into the results files in
+    /// --results-dir, one per machine
+    Merge {
+        /// Results files (.json) or journals (.ndjson)
+        #[structopt(required = true, parse(from_os_str))]
+        inputs: Vec<PathBuf>,
+        /// How to resolve different samples of the same key - keep-both, newer or more-samples.
+        /// Without one, nothing is merged if there are any
+        #[structopt(long)]
+        policy: Option<profile::MergePolicy>,
+        /// Prints what would be merged without writing anything
+        #[structopt(long)]
+        dry_run: bool,
+    },
+    /// Writes a self-contained HTML report with charts of every repo, and the biggest changes
+    /// between the two most recent versions
+    Report {
+        #[structopt(short, long, default_value = "report.html", parse(from_os_str))]
+        output: PathBuf,
+        /// Highest p-value of the Mann-Whitney U test at which a change is significant
+        #[structopt(long, default_value = "0.05")]
+        alpha: f64,
+    },
+    /// Renames results files to names that don't depend on the Rust release arewefastyet was
+    /// built with
+    Migrate {
+        /// Prints what would be renamed without renaming anything
+        #[structopt(long)]
+        dry_run: bool,
+        /// Renames files even if their name doesn't match the system_info inside them
+        #[structopt(long)]
+        force: bool,
+    },
+    /// Serves the results as JSON over HTTP, re-reading them whenever they change. Also a Grafana
+    /// JSON datasource, with a health check on / and POST /search and /query
+    Serve {
+        /// Address to listen on. Anything but localhost exposes the results to the network
+        #[structopt(long, default_value = "127.0.0.1:8000")]
+        address: String,
+    },
+    /// Prints sparklines, or line charts, of a repo's median compile times across versions, and
+    /// a table of its output sizes
+    Show {
+        repo: String,
+        /// Only show this CompilerMode, e.g. "Check"
+        #[structopt(long)]
+        compiler_mode: Option<cargo::CompilerMode>,
+        /// Only show this ProfileMode, e.g. "Clean"
+        #[structopt(long)]
+        profile_mode: Option<cargo::ProfileMode>,
+        /// Draws a line chart of each pair of modes instead of a sparkline
+        #[structopt(long)]
+        chart: bool,
+        /// Orders the sizes by version, or largest first by a CompilerMode, e.g. "Release"
+        #[structopt(long, default_value = "version")]
+        sort: show::SortBy,
+        /// Results file to read [default: this machine's results file in --results-dir]
+        #[structopt(long, parse(from_os_str))]
+        results_file: Option<PathBuf>,
+    },
+    /// Profiles each repo on one version at -j 1, 2, 4 and so on up to the number of cores
+    Scaling {
+        /// Version to profile, e.g. "1.53.0" [default: latest]
+        #[structopt(long)]
+        version: Option<rustup::Version>,
+    },
 }
 
-fn main() -> Result<()> {
+fn main() {
     pretty_env_logger::init();
 
     match exec() {
         Ok(_) => log::info!("Completed successfully"),
-        Err(e) => log::error!("Error executing process - {}", e),
+        Err(e) => {
+            log::error!("Error executing process - {}", e);
+            let code = if e.is::<regressions::RegressionError>() {
+                2
+            } else {
+                1
+            };
+            std::process::exit(code);
+        }
     };
-
-    Ok(())
 }
 
 fn exec() -> Result<()> {
+    let opt = Opt::from_args();
+    process::set_timeouts(process::Timeouts {
+        cargo: Some(opt.cargo_timeout),
+        rustup: Some(opt.rustup_timeout),
+        git: Some(opt.git_timeout),
+    });
+    conditions::set_settings(conditions::Settings {
+        quiet_machine: opt.quiet_machine,
+        cpus: opt
+            .cpus
+            .as_deref()
+            .map(conditions::parse_cpus)
+            .transpose()?,
+        jobs: opt.jobs,
+        max_load: opt.max_load,
+    });
+
+    match opt.command {
+        Some(Command::Validate) => {
+            repo::create_working_directory(opt.working_directory.clone())?;
+            validate::validate(&opt.repos_file)
+        }
+        Some(Command::Convert { ref output }) => {
+            let repos = store::get_repos(&opt.repos_file, &Default::default())?;
+            store::write_repos(output, repos)
+        }
+        Some(Command::Compare {
+            baseline,
+            candidate,
+            ref results_files,
+            alpha,
+            format,
+        }) => {
+            let results_files = result_files(&opt, results_files)?;
+            compare::compare(&results_files, baseline, candidate, alpha, format)
+        }
+        Some(Command::CheckRegressions {
+            version,
+            window,
+            threshold,
+            ref mode_thresholds,
+            alpha,
+            ref results_files,
+        }) => {
+            let thresholds = regressions::Thresholds {
+                default: threshold,
+                modes: mode_thresholds.clone(),
+            };
+            let results_files = result_files(&opt, results_files)?;
+            regressions::check_regressions(&results_files, version, window, &thresholds, alpha)
+        }
+        Some(Command::Dashboard { ref output }) => {
+            let output = output
+                .clone()
+                .unwrap_or_else(|| opt.results_dir.join("dashboard.json"));
+            dashboard::dashboard(&opt.repos_file, &opt.results_dir, &output)
+        }
+        Some(Command::Export {
+            ref output,
+            ref results_files,
+        }) => export::export(&result_files(&opt, results_files)?, output),
+        Some(Command::Machines) => machines::machines(&opt.repos_file, &opt.results_dir),
+        Some(Command::Metrics { ref output }) => {
+            metrics::metrics(&opt.results_dir, output.as_deref())
+        }
+        Some(Command::Merge {
+            ref inputs,
+            policy,
+            dry_run,
+        }) => merge::merge(inputs, &opt.results_dir, policy, dry_run),
+        Some(Command::Migrate { dry_run, force }) => {
+            migrate::migrate(&opt.results_dir, dry_run, force)
+        }
+        Some(Command::Report { ref output, alpha }) => {
+            report::report(&opt.repos_file, &opt.results_dir, output, alpha)
+        }
+        Some(Command::Serve { ref address }) => {
+            serve::serve(&opt.repos_file, &opt.results_dir, address)
+        }
+        Some(Command::Show {
+            ref repo,
+            compiler_mode,
+            profile_mode,
+            chart,
+            sort,
+            ref results_file,
+        }) => {
+            // This machine's results file is named after the working directory's disk.
+            repo::create_working_directory(opt.working_directory.clone())?;
+            let profiles = show::profiles(&opt.results_dir, results_file.as_deref())?;
+            show::show(&profiles, repo, compiler_mode, profile_mode, chart, sort)
+        }
+        Some(Command::Scaling { version }) => {
+            prepare_to_profile(&opt)?;
+            scaling::scaling(
+                &opt.repos_file,
+                &opt.results_dir,
+                &overrides(&opt),
+                version,
+                opt.continue_from.as_deref(),
+            )
+        }
+        None => {
+            prepare_to_profile(&opt)?;
+            collect(&opt)
+        }
+    }
+}
+
+/// Set up for the subcommands that take samples. Only they stop cleanly on Ctrl-C, so other
+/// subcommands keep the default of exiting at once, and only they change rustup's settings.
+fn prepare_to_profile(opt: &Opt) -> Result<()> {
+    process::handle_ctrlc()?;
     rustup::set_profile_minimal()?;
+    repo::create_working_directory(opt.working_directory.clone())
+}
 
-    let opt = Opt::from_args();
-    repo::create_working_directory(opt.working_directory)?;
+/// The results files given to a subcommand, or all of them.
+fn result_files(opt: &Opt, results_files: &[PathBuf]) -> Result<Vec<PathBuf>> {
+    if results_files.is_empty() {
+        store::get_result_files(&opt.results_dir)
+    } else {
+        Ok(results_files.to_vec())
+    }
+}
+
+/// Repo options set on the command line, which replace those in the repos file's `[defaults]`.
+fn overrides(opt: &Opt) -> repo::RepoOptions {
+    repo::RepoOptions {
+        times: opt.times,
+        warmup: opt.warmup,
+        cache: opt.cache,
+        ..Default::default()
+    }
+}
 
-    let repos = store::get_repos(&opt.repos_file)?;
-    let mut profiles = store::get_profiles(&opt.results_dir)?;
+fn retry(opt: &Opt) -> profile::RetryPolicy {
+    profile::RetryPolicy {
+        max_attempts: opt.max_attempts,
+        after: opt.retry_after,
+    }
+}
+
+fn collect(opt: &Opt) -> Result<()> {
+    let repos = store::get_repos(&opt.repos_file, &overrides(opt))?;
+    let mut profiles = store::get_profiles(&opt.results_dir, opt.continue_from.as_deref())?;
+    let journal = journal::Journal::new(&opt.results_dir)?;
+    if journal.recover(&mut profiles)? {
+        store::overwrite_profiles(&opt.results_dir, &profiles)?;
+        journal.clear()?;
+    }
+    check_cache_modes(&repos, &profiles, retry(opt))?;
+    let planned = repos
+        .iter()
+        .map(|repo| {
+            let new = profile::Profile::new();
+            let profile = profiles.get(&repo.name).unwrap_or(&new);
+            let versions = profile.versions_to_profile(repo.min_version, retry(opt));
+            progress::RepoProgress {
+                name: repo.name.clone(),
+                cells_planned: versions.len() * progress::cells_per_version(),
+                cells_done: 0,
+                seconds_per_cell: progress::seconds_per_cell(profile, &repo.sampling()),
+                seconds: 0.0,
+            }
+        })
+        .collect();
+    let mut progress = progress::Progress::new(&opt.results_dir, planned, opt.progress)?;
+    conditions::apply()?;
 
     for repo in repos {
+        progress.start_repo(&repo.name);
         repo.clone_repo()?;
 
         let profile = profiles
             .entry(repo.name.clone())
             .or_insert_with(profile::Profile::new);
+        let versions = profile.versions_to_profile(repo.min_version, retry(opt));
 
-        for version in profile.versions_to_profile(repo.min_version) {
-            rustup::set_version(version)?;
+        let result = if opt.interleave {
+            let seed = opt.seed.unwrap_or_else(schedule::random_seed);
+            schedule::profile_interleaved(&repo, profile, versions, seed, &journal, &mut progress)
+        } else {
+            profile_sequential(&repo, profile, versions, &journal, &mut progress)
+        };
 
-            match cargo::compile_time_profile(&repo, opt.times) {
-                Ok(compile_time_profile) => {
-                    profile.add_compile_times(version, compile_time_profile)
-                }
-                Err(e) => {
-                    log::error!(
-                        "Failed to profile times {} on version {}. Error - {}",
-                        &repo.name,
-                        version.get_string(),
-                        e
-                    );
-                }
-            };
+        store::overwrite_profiles(&opt.results_dir, &profiles)?;
+        journal.clear()?;
+        if result.is_err() {
+            repo.git_reset()?;
+        }
+        result?;
+        if process::cancelled() {
+            repo.git_reset()?;
+            log::warn!("Cancelled. Results of the versions profiled so far have been saved");
+            break;
+        }
+    }
+    progress.finish()
+}
 
-            match cargo::size_profile(&repo) {
-                Ok((debug_size, release_size)) => {
-                    profile.add_output_sizes(version, debug_size, release_size)
-                }
-                Err(e) => {
-                    log::error!(
-                        "Failed to profile sizes {} on version {}. Error - {}",
-                        &repo.name,
-                        version.get_string(),
-                        e
-                    );
-                }
-            };
+/// Fails if any repo with versions left to profile has samples taken with another cache mode,
+/// since they would be mixed with those of this run.
+fn check_cache_modes(
+    repos: &[repo::Repo],
+    profiles: &BTreeMap<String, profile::Profile>,
+    retry: profile::RetryPolicy,
+) -> Result<()> {
+    let conflicts: Vec<String> = repos
+        .iter()
+        .filter_map(|repo| {
+            let profile = profiles.get(&repo.name)?;
+            let cache_mode = repo.sampling().cache;
+            let cache_modes = profile.cache_modes();
+            let conflicting = cache_modes.iter().any(|mode| *mode != cache_mode)
+                && !profile
+                    .versions_to_profile(repo.min_version, retry)
+                    .is_empty();
+            conflicting.then(|| {
+                format!(
+                    "{} has samples with cache mode {:?}, not {:?}",
+                    repo.name, cache_modes, cache_mode
+                )
+            })
+        })
+        .collect();
+    if !conflicts.is_empty() {
+        return Err(anyhow!(
+            "Samples with different cache modes would be mixed. Set --cache, or the repo's cache, to match - {}",
+            conflicts.join("; ")
+        ));
+    }
+    Ok(())
+}
+
+fn profile_sequential(
+    repo: &repo::Repo,
+    profile: &mut profile::Profile,
+    versions: Vec<rustup::Version>,
+    journal: &journal::Journal,
+    progress: &mut progress::Progress,
+) -> Result<()> {
+    for version in versions {
+        if process::cancelled() {
+            break;
+        }
+        progress.start_version(version);
+        if let Err(e) = rustup::set_version(version) {
+            if process::cancelled() {
+                break;
+            }
+            log::error!(
+                "Failed to install version {}. Error - {}",
+                version.get_string(),
+                e
+            );
+            profile.add_failure(version, FailureReason::toolchain_install(&e));
+            journal.record(&repo.name, profile, version)?;
+            progress.done(progress::cells_per_version());
+            continue;
         }
 
-        store::overwrite_profiles(&opt.results_dir, &profiles)?;
+        // Every cell of the version is profiled at once.
+        let result = cargo::compile_time_profile(repo, &repo.sampling());
+        if !process::cancelled() {
+            progress.done(progress::cells_per_version());
+        }
+        match result {
+            Ok(compile_time_profile) => {
+                profile.add_compile_times(version, compile_time_profile);
+                profile.set_feature_set(version, repo.feature_set());
+            }
+            Err(e) => {
+                if process::cancelled() {
+                    break;
+                }
+                let reason = match FailureReason::build_error(&e) {
+                    Some(reason) => reason,
+                    None => return Err(e),
+                };
+                log::error!(
+                    "Failed to profile times {} on version {}. Error - {}",
+                    &repo.name,
+                    version.get_string(),
+                    e
+                );
+                profile.add_failure(version, reason);
+                journal.record(&repo.name, profile, version)?;
+                repo.git_reset()?;
+                continue;
+            }
+        };
+
+        match cargo::size_profile(repo) {
+            Ok((debug_sizes, release_sizes)) => {
+                profile.add_output_sizes(version, debug_sizes, release_sizes)
+            }
+            Err(e) => {
+                log::error!(
+                    "Failed to profile sizes {} on version {}. Error - {}",
+                    &repo.name,
+                    version.get_string(),
+                    e
+                );
+            }
+        };
+        journal.record(&repo.name, profile, version)?;
     }
     Ok(())
 }
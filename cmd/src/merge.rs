@@ -0,0 +1,276 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Result};
+
+use crate::journal;
+use crate::profile::{MergePolicy, Profile};
+use crate::store;
+use crate::system::SystemInfo;
+
+/// Profiles read from a results file, or those of one machine and repo in a journal.
+struct Source {
+    path: PathBuf,
+    system_info: SystemInfo,
+    profiles: BTreeMap<String, Profile>,
+    /// Seconds since the Unix epoch. When the results file was last added to, or the journal's
+    /// last fragment recorded. Results files written before that was recorded have none.
+    time: Option<u64>,
+}
+
+/// The results file of one machine, with everything merged into it.
+struct Merged {
+    path: PathBuf,
+    system_info: SystemInfo,
+    profiles: BTreeMap<String, Profile>,
+    time: Option<u64>,
+    sources: usize,
+    /// "<repo> <key>" of every conflicting sample list.
+    conflicts: Vec<String>,
+}
+
+/// Merges results files and journals into the results files in `results_dir`, one per machine.
+/// Sources are merged oldest first. If any have different samples of the same key, nothing is
+/// written unless `policy` resolves them. `MergePolicy::PreferNewer` needs every results file to
+/// record when it was last added to.
+pub(crate) fn merge(
+    inputs: &[PathBuf],
+    results_dir: &Path,
+    policy: Option<MergePolicy>,
+    dry_run: bool,
+) -> Result<()> {
+    let mut sources = Vec::new();
+    for path in inputs {
+        sources.extend(read_sources(path)?);
+    }
+    sources.sort_by_key(|source| source.time);
+    if policy == Some(MergePolicy::PreferNewer) {
+        if let Some(source) = sources.iter().find(|source| source.time.is_none()) {
+            return Err(untimed(&source.path));
+        }
+    }
+
+    let mut machines: BTreeMap<String, Merged> = BTreeMap::new();
+    for source in sources {
+        let id = store::machine_id(&source.system_info);
+        if !machines.contains_key(&id) {
+            let merged = Merged::new(results_dir, &source.system_info)?;
+            if policy == Some(MergePolicy::PreferNewer)
+                && merged.time.is_none()
+                && merged.path.exists()
+            {
+                return Err(untimed(&merged.path));
+            }
+            machines.insert(id.clone(), merged);
+        }
+        let merged = machines.get_mut(&id).expect("inserted above");
+        if is_same_file(&source.path, &merged.path) {
+            continue;
+        }
+        merged.add(source, policy);
+    }
+
+    let mut unresolved = 0;
+    for (id, merged) in &machines {
+        println!(
+            "{} - {} sources into {}",
+            id,
+            merged.sources,
+            merged.path.display()
+        );
+        for conflict in &merged.conflicts {
+            println!("    CONFLICT {}", conflict);
+        }
+        if policy.is_none() {
+            unresolved += merged.conflicts.len();
+        }
+    }
+    if unresolved > 0 {
+        return Err(anyhow!(
+            "{} sample lists conflict, rerun with --policy to resolve them",
+            unresolved
+        ));
+    }
+
+    if !dry_run {
+        for merged in machines.into_values() {
+            store::write_results(
+                results_dir,
+                merged.system_info,
+                &merged.profiles,
+                merged.time,
+            )?;
+        }
+    }
+    Ok(())
+}
+
+fn read_sources(path: &Path) -> Result<Vec<Source>> {
+    if journal::is_journal(path) {
+        return Ok(journal::replay(journal::read_journal(path)?)
+            .into_iter()
+            .map(|fragment| Source {
+                path: path.to_path_buf(),
+                system_info: fragment.system_info,
+                profiles: std::iter::once((fragment.repo, fragment.profile)).collect(),
+                time: Some(fragment.recorded),
+            })
+            .collect());
+    }
+    let (system_info, profiles, updated) = store::read_timed_results(path)?;
+    Ok(vec![Source {
+        path: path.to_path_buf(),
+        system_info,
+        profiles,
+        time: updated,
+    }])
+}
+
+fn untimed(path: &Path) -> anyhow::Error {
+    anyhow!(
+        "{:?} doesn't record when its samples were taken, so --policy newer can't tell which are newer. Use keep-both or more-samples, or collect into it once to record the time",
+        path
+    )
+}
+
+impl Merged {
+    /// Starts from the machine's results file in `results_dir`, if it has one.
+    fn new(results_dir: &Path, system_info: &SystemInfo) -> Result<Merged> {
+        let path = store::get_result_file_path(results_dir, system_info);
+        let (system_info, profiles, time) = if path.exists() {
+            store::read_timed_results(&path)?
+        } else {
+            (system_info.clone(), BTreeMap::new(), None)
+        };
+        Ok(Merged {
+            path,
+            system_info,
+            profiles,
+            time,
+            sources: 0,
+            conflicts: Vec::new(),
+        })
+    }
+
+    fn add(&mut self, source: Source, policy: Option<MergePolicy>) {
+        let is_newer = source.time > self.time;
+        for (repo, profile) in source.profiles {
+            let conflicts = self
+                .profiles
+                .entry(repo.clone())
+                .or_insert_with(Profile::new)
+                .merge(profile, is_newer, policy);
+            self.conflicts
+                .extend(conflicts.into_iter().map(|key| format!("{} {}", repo, key)));
+        }
+        self.time = self.time.max(source.time);
+        self.sources += 1;
+    }
+}
+
+fn is_same_file(a: &Path, b: &Path) -> bool {
+    match (a.canonicalize(), b.canonicalize()) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use anyhow::Result;
+
+    const SYSTEM_INFO: &str = r#"{
+        "num_cores": 2,
+        "num_physical_cores": 2,
+        "cpu_model": "Intel(R) Xeon(R) Gold 6140 CPU @ 2.30GHz"
+    }"#;
+
+    fn results(profiles: &str) -> String {
+        format!(
+            r#"{{ "system_info": {}, "profiles": {} }}"#,
+            SYSTEM_INFO, profiles
+        )
+    }
+
+    fn fragment(recorded: u64, profile: &str) -> String {
+        let fragment = format!(
+            r#"{{ "system_info": {}, "repo": "syn", "recorded": {}, "profile": {} }}"#,
+            SYSTEM_INFO, recorded, profile
+        );
+        serde_json::to_string(&serde_json::from_str::<serde_json::Value>(&fragment).unwrap())
+            .unwrap()
+    }
+
+    #[test]
+    fn test_merge() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("arewefastyet-merge-{}", std::process::id()));
+        let results_dir = dir.join("data");
+        std::fs::create_dir_all(&results_dir)?;
+        let target = results_dir.join("results-2-3ca971dfaacb948d.json");
+        std::fs::write(
+            &target,
+            results(
+                r#"{ "syn": { "compile_times": { "1.50.0,Check,Clean": [1000] }, "output_sizes": {} } }"#,
+            ),
+        )?;
+        let copy = dir.join("copy.json");
+        std::fs::write(
+            &copy,
+            results(
+                r#"{ "serde": { "compile_times": { "1.50.0,Check,Clean": [500] }, "output_sizes": {} } }"#,
+            ),
+        )?;
+        let journal = dir.join("journal-3ca971dfaacb948d.ndjson");
+        std::fs::write(
+            &journal,
+            format!(
+                "{}\n{}\n{{\"system_info\": {{",
+                fragment(
+                    4_000_000_000,
+                    r#"{ "compile_times": { "1.51.0,Check,Clean": [900] }, "output_sizes": {} }"#
+                ),
+                fragment(
+                    4_000_000_001,
+                    r#"{ "compile_times": { "1.50.0,Check,Clean": [1100, 1200] }, "output_sizes": {} }"#
+                ),
+            ),
+        )?;
+
+        let inputs = vec![target.clone(), copy, journal];
+        let conflicting = merge(&inputs, &results_dir, None, false);
+        let untimed = merge(&inputs, &results_dir, Some(MergePolicy::PreferNewer), false);
+        let unchanged = store::read_results(&target)?.1;
+        let resolved = merge(
+            &inputs,
+            &results_dir,
+            Some(MergePolicy::PreferMoreSamples),
+            false,
+        );
+        let (_, merged, updated) = store::read_timed_results(&target)?;
+        std::fs::remove_dir_all(&dir)?;
+
+        assert!(conflicting.is_err());
+        // Neither results file records when it was written.
+        assert!(untimed.is_err());
+        assert_eq!(unchanged.len(), 1);
+        resolved?;
+        assert_eq!(merged.keys().collect::<Vec<_>>(), vec!["serde", "syn"]);
+        let samples = |version| {
+            merged["syn"]
+                .compile_times(
+                    version,
+                    crate::cargo::CompilerMode::Check,
+                    crate::cargo::ProfileMode::Clean,
+                )
+                .map(|times| times.iter().map(|ms| ms.as_u64()).collect::<Vec<_>>())
+        };
+        assert_eq!(
+            samples(crate::rustup::Version::V1_50),
+            Some(vec![1100, 1200])
+        );
+        assert_eq!(samples(crate::rustup::Version::V1_51), Some(vec![900]));
+        assert_eq!(updated, Some(4_000_000_001));
+        Ok(())
+    }
+}
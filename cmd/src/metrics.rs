@@ -0,0 +1,309 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::path::Path;
+
+use anyhow::{anyhow, Context, Result};
+
+use crate::profile::Profile;
+use crate::progress::{self, RunState};
+use crate::{compare, stats, store};
+
+/// What the metrics are derived from for one results file.
+pub(crate) struct Machine<'a> {
+    pub(crate) id: &'a str,
+    pub(crate) label: &'a str,
+    pub(crate) num_cores: usize,
+    pub(crate) profiles: &'a BTreeMap<String, Profile>,
+}
+
+/// Writes the OpenMetrics exposition of every results file and run in progress in
+/// `results_dir` to `output`, or prints it. The file is replaced in one go, so that nothing
+/// reading it sees half of it.
+pub(crate) fn metrics(results_dir: &Path, output: Option<&Path>) -> Result<()> {
+    let mut results = Vec::new();
+    for path in store::get_result_files(results_dir)? {
+        let (system_info, profiles) = store::read_results(&path)?;
+        let id = store::machine_id(&system_info);
+        let label = compare::machine_label(&path, &system_info)?;
+        results.push((id, label, system_info.num_cores, profiles));
+    }
+    let machines: Vec<Machine> = results
+        .iter()
+        .map(|(id, label, num_cores, profiles)| Machine {
+            id,
+            label,
+            num_cores: *num_cores,
+            profiles,
+        })
+        .collect();
+    let text = render(&machines, &progress::read_progress(results_dir)?);
+
+    match output {
+        Some(output) => {
+            let temporary = output.with_extension("tmp");
+            log::info!("Writing to {:?}", output);
+            std::fs::write(&temporary, text)
+                .with_context(|| anyhow!("Failed to write metrics - {:?}", temporary))?;
+            std::fs::rename(&temporary, output)
+                .with_context(|| anyhow!("Failed to write metrics - {:?}", output))
+        }
+        None => {
+            print!("{}", text);
+            Ok(())
+        }
+    }
+}
+
+/// Gauges of the median compile time and the output size of every repo, toolchain and mode on
+/// each machine, and the progress of each run.
+pub(crate) fn render(machines: &[Machine], runs: &[RunState]) -> String {
+    let mut text = String::new();
+
+    family(
+        &mut text,
+        "arewefastyet_machine",
+        "info",
+        None,
+        "A machine with results.",
+    );
+    for machine in machines {
+        sample(
+            &mut text,
+            "arewefastyet_machine_info",
+            &[
+                ("machine", machine.id),
+                ("label", machine.label),
+                ("num_cores", &machine.num_cores.to_string()),
+            ],
+            1,
+        );
+    }
+
+    family(
+        &mut text,
+        "arewefastyet_compile_time_seconds",
+        "gauge",
+        Some("seconds"),
+        "Median compile time.",
+    );
+    for machine in machines {
+        for (repo, profile) in machine.profiles {
+            for (version, compiler_mode, profile_mode, times) in profile.all_compile_times() {
+                let times: Vec<f64> = times.iter().map(|ms| ms.as_f64()).collect();
+                sample(
+                    &mut text,
+                    "arewefastyet_compile_time_seconds",
+                    &[
+                        ("machine", machine.id),
+                        ("repo", repo),
+                        ("toolchain", version.get_string()),
+                        ("compiler_mode", &format!("{:?}", compiler_mode)),
+                        ("profile_mode", &format!("{:?}", profile_mode)),
+                    ],
+                    stats::median(&times) / 1000.0,
+                );
+            }
+        }
+    }
+
+    family(
+        &mut text,
+        "arewefastyet_output_size_bytes",
+        "gauge",
+        Some("bytes"),
+        "Size of the build output.",
+    );
+    for machine in machines {
+        for (repo, profile) in machine.profiles {
+            for (version, compiler_mode, bytes) in profile.all_output_sizes() {
+                sample(
+                    &mut text,
+                    "arewefastyet_output_size_bytes",
+                    &[
+                        ("machine", machine.id),
+                        ("repo", repo),
+                        ("toolchain", version.get_string()),
+                        ("compiler_mode", &format!("{:?}", compiler_mode)),
+                    ],
+                    bytes.as_u64(),
+                );
+            }
+        }
+    }
+
+    family(
+        &mut text,
+        "arewefastyet_run",
+        "info",
+        None,
+        "The repo and toolchain a run in progress is profiling.",
+    );
+    for run in runs {
+        sample(
+            &mut text,
+            "arewefastyet_run_info",
+            &[
+                ("machine", &run.machine),
+                ("repo", run.repo.as_deref().unwrap_or("")),
+                ("toolchain", run.version.as_deref().unwrap_or("")),
+            ],
+            1,
+        );
+    }
+    family(
+        &mut text,
+        "arewefastyet_run_cells_planned",
+        "gauge",
+        None,
+        "Cells a run in progress will profile, each one version of a repo in one pair of modes.",
+    );
+    for run in runs {
+        let labels = [("machine", run.machine.as_str())];
+        sample(
+            &mut text,
+            "arewefastyet_run_cells_planned",
+            &labels,
+            run.cells_planned,
+        );
+    }
+    family(
+        &mut text,
+        "arewefastyet_run_cells_done",
+        "counter",
+        None,
+        "Cells a run in progress has profiled, or failed to.",
+    );
+    for run in runs {
+        let labels = [("machine", run.machine.as_str())];
+        sample(
+            &mut text,
+            "arewefastyet_run_cells_done_total",
+            &labels,
+            run.cells_done,
+        );
+        sample(
+            &mut text,
+            "arewefastyet_run_cells_done_created",
+            &labels,
+            run.started,
+        );
+    }
+    family(
+        &mut text,
+        "arewefastyet_run_last_update_timestamp_seconds",
+        "gauge",
+        Some("seconds"),
+        "When a run in progress last reported progress. A run that has stopped keeps its last one.",
+    );
+    for run in runs {
+        let labels = [("machine", run.machine.as_str())];
+        sample(
+            &mut text,
+            "arewefastyet_run_last_update_timestamp_seconds",
+            &labels,
+            run.updated,
+        );
+    }
+
+    family(
+        &mut text,
+        "arewefastyet_run_eta_seconds",
+        "gauge",
+        Some("seconds"),
+        "Estimated time until a run in progress is done.",
+    );
+    for run in runs {
+        if let Some(eta) = run.eta_seconds {
+            let labels = [("machine", run.machine.as_str())];
+            sample(&mut text, "arewefastyet_run_eta_seconds", &labels, eta);
+        }
+    }
+
+    text.push_str("# EOF\n");
+    text
+}
+
+fn family(text: &mut String, name: &str, kind: &str, unit: Option<&str>, help: &str) {
+    let _ = writeln!(text, "# TYPE {} {}", name, kind);
+    if let Some(unit) = unit {
+        let _ = writeln!(text, "# UNIT {} {}", name, unit);
+    }
+    let _ = writeln!(text, "# HELP {} {}", name, help);
+}
+
+fn sample(text: &mut String, name: &str, labels: &[(&str, &str)], value: impl std::fmt::Display) {
+    let labels: Vec<String> = labels
+        .iter()
+        .map(|(name, value)| format!("{}=\"{}\"", name, escape(value)))
+        .collect();
+    let _ = writeln!(text, "{}{{{}}} {}", name, labels.join(","), value);
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use anyhow::Result;
+
+    #[test]
+    fn test_render() -> Result<()> {
+        let profile: Profile = serde_json::from_str(
+            r#"{
+                "compile_times": { "1.51.0,Check,Clean": [1000, 1500, 1010] },
+                "output_sizes": { "1.51.0,Release": 4096 }
+            }"#,
+        )?;
+        let profiles = std::iter::once(("syn".to_string(), profile)).collect();
+        let machines = [Machine {
+            id: "3ca971dfaacb948d",
+            label: "2 \"cores\"",
+            num_cores: 2,
+            profiles: &profiles,
+        }];
+        let runs = [RunState {
+            machine: "3ca971dfaacb948d".to_string(),
+            started: 1_600_000_000,
+            updated: 1_600_000_100,
+            cells_planned: 90,
+            cells_done: 9,
+            repo: Some("serde".to_string()),
+            version: Some("1.52.0".to_string()),
+            eta_seconds: Some(3600),
+        }];
+        let text = render(&machines, &runs);
+
+        let lines: Vec<&str> = text.lines().collect();
+        assert!(lines.contains(
+            &"arewefastyet_machine_info{machine=\"3ca971dfaacb948d\",label=\"2 \\\"cores\\\"\",num_cores=\"2\"} 1"
+        ));
+        assert!(lines.contains(
+            &"arewefastyet_compile_time_seconds{machine=\"3ca971dfaacb948d\",repo=\"syn\",toolchain=\"1.51.0\",compiler_mode=\"Check\",profile_mode=\"Clean\"} 1.01"
+        ));
+        assert!(lines.contains(
+            &"arewefastyet_output_size_bytes{machine=\"3ca971dfaacb948d\",repo=\"syn\",toolchain=\"1.51.0\",compiler_mode=\"Release\"} 4096"
+        ));
+        assert!(lines.contains(
+            &"arewefastyet_run_info{machine=\"3ca971dfaacb948d\",repo=\"serde\",toolchain=\"1.52.0\"} 1"
+        ));
+        assert!(
+            lines.contains(&"arewefastyet_run_cells_done_total{machine=\"3ca971dfaacb948d\"} 9")
+        );
+        assert!(lines.contains(&"arewefastyet_run_cells_planned{machine=\"3ca971dfaacb948d\"} 90"));
+        assert!(lines.contains(&"arewefastyet_run_eta_seconds{machine=\"3ca971dfaacb948d\"} 3600"));
+        assert_eq!(lines.last(), Some(&"# EOF"));
+        assert_eq!(
+            lines
+                .iter()
+                .filter(|line| line.starts_with("# TYPE"))
+                .count(),
+            8
+        );
+        Ok(())
+    }
+}
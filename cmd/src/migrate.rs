@@ -0,0 +1,96 @@
+use std::path::Path;
+
+use anyhow::{anyhow, Result};
+
+use crate::store;
+
+/// Renames results files named with `DefaultHasher` to their stable names. A file is only
+/// renamed if its name matches the `system_info` inside it, unless `force` is set, since
+/// otherwise it may have been copied or edited by hand.
+pub(crate) fn migrate(results_dir: &Path, dry_run: bool, force: bool) -> Result<()> {
+    let entries = store::get_result_files(results_dir)?;
+
+    let mut failures = 0;
+    for path in &entries {
+        let name = file_name(path);
+        match migrate_file(path, dry_run, force) {
+            Ok(None) => println!("OK {}", name),
+            Ok(Some(new_path)) => println!("RENAME {} -> {}", name, file_name(&new_path)),
+            Err(e) => {
+                failures += 1;
+                println!("FAIL {} - {:#}", name, e);
+            }
+        }
+    }
+
+    if failures > 0 {
+        return Err(anyhow!(
+            "{} of {} results files were not migrated",
+            failures,
+            entries.len()
+        ));
+    }
+    Ok(())
+}
+
+/// Returns the new path if the file needs renaming.
+fn migrate_file(path: &Path, dry_run: bool, force: bool) -> Result<Option<std::path::PathBuf>> {
+    let dir = path.parent().unwrap_or_else(|| Path::new("."));
+    let (system_info, _) = store::read_results(path)?;
+    let new_path = store::get_result_file_path(dir, &system_info);
+    if new_path == path {
+        return Ok(None);
+    }
+    if !force && store::get_legacy_result_file_path(dir, &system_info) != path {
+        return Err(anyhow!(
+            "name doesn't match its system_info, rerun with --force to rename it anyway"
+        ));
+    }
+    if new_path.exists() {
+        return Err(anyhow!("{} already exists", file_name(&new_path)));
+    }
+    if !dry_run {
+        std::fs::rename(path, &new_path)?;
+    }
+    Ok(Some(new_path))
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod test {
+    use anyhow::Result;
+
+    #[test]
+    fn test_migrate() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("arewefastyet-migrate-{}", std::process::id()));
+        std::fs::create_dir_all(&dir)?;
+        let contents = r#"{
+            "system_info": {
+                "num_cores": 2,
+                "num_physical_cores": 2,
+                "cpu_model": "Intel(R) Xeon(R) Gold 6140 CPU @ 2.30GHz"
+            },
+            "profiles": {}
+        }"#;
+        std::fs::write(dir.join("results-2-4397104837942437864.json"), contents)?;
+        std::fs::write(dir.join("results-2-1.json"), contents)?;
+
+        let result = super::migrate(&dir, false, false);
+        let renamed = dir.join("results-2-3ca971dfaacb948d.json").exists();
+        let mismatched = dir.join("results-2-1.json").exists();
+        let again = super::migrate(&dir, false, false);
+        std::fs::remove_dir_all(&dir)?;
+
+        // The misnamed file fails, and its stable name is then taken.
+        assert!(result.is_err());
+        assert!(renamed);
+        assert!(mismatched);
+        assert!(again.is_err());
+        Ok(())
+    }
+}
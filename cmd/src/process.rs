@@ -0,0 +1,224 @@
+use std::fmt;
+use std::io::Read;
+use std::os::unix::process::CommandExt;
+use std::process::{Child, Command, Output, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Context, Result};
+use once_cell::sync::OnceCell;
+
+static TIMEOUTS: OnceCell<Timeouts> = OnceCell::new();
+static CANCELLED: AtomicBool = AtomicBool::new(false);
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// How long each kind of command may run before it is killed. `None` means no limit.
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct Timeouts {
+    pub(crate) cargo: Option<Duration>,
+    pub(crate) rustup: Option<Duration>,
+    pub(crate) git: Option<Duration>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum CommandKind {
+    Cargo,
+    Rustup,
+    Git,
+    /// Restores the working tree. Still runs after Ctrl-C.
+    GitReset,
+    Touch,
+}
+
+/// An external command that ran but exited unsuccessfully.
+#[derive(Debug)]
+pub(crate) struct CommandError {
+    pub(crate) command: &'static str,
+    pub(crate) stderr: String,
+}
+
+/// An external command that was killed because it ran for longer than its timeout.
+#[derive(Debug)]
+pub(crate) struct TimeoutError {
+    pub(crate) command: String,
+    pub(crate) timeout: Duration,
+}
+
+/// An external command that was killed, or never started, because of Ctrl-C.
+#[derive(Debug)]
+pub(crate) struct CancelledError;
+
+pub(crate) fn set_timeouts(timeouts: Timeouts) {
+    TIMEOUTS.get_or_init(|| timeouts);
+}
+
+/// After the first Ctrl-C, running commands are killed and new ones fail with
+/// `CancelledError`, so that the caller can save its results and exit. A second Ctrl-C exits
+/// immediately.
+pub(crate) fn handle_ctrlc() -> Result<()> {
+    ctrlc::set_handler(|| {
+        if CANCELLED.swap(true, Ordering::SeqCst) {
+            log::error!("Interrupted twice, exiting without cleaning up");
+            std::process::exit(130);
+        }
+        log::warn!("Interrupted, stopping after saving results. Press Ctrl-C again to exit now");
+    })
+    .with_context(|| "failed to set Ctrl-C handler")
+}
+
+pub(crate) fn cancelled() -> bool {
+    CANCELLED.load(Ordering::SeqCst)
+}
+
+/// Runs the command to completion, like `Command::output`, subject to the timeout for its
+/// kind and to Ctrl-C.
+pub(crate) fn run(command: &mut Command, kind: CommandKind) -> Result<Output> {
+    let timeouts = TIMEOUTS.get().copied().unwrap_or_default();
+    let timeout = match kind {
+        CommandKind::Cargo => timeouts.cargo,
+        CommandKind::Rustup => timeouts.rustup,
+        CommandKind::Git | CommandKind::GitReset => timeouts.git,
+        CommandKind::Touch => None,
+    };
+    let cancellable = !matches!(kind, CommandKind::GitReset);
+    run_with_timeout(command, timeout, cancellable)
+}
+
+fn run_with_timeout(
+    command: &mut Command,
+    timeout: Option<Duration>,
+    cancellable: bool,
+) -> Result<Output> {
+    if cancellable && cancelled() {
+        return Err(CancelledError.into());
+    }
+    // The child leads its own process group, so that killing the group takes rustc and
+    // anything else it started down with it. It also keeps Ctrl-C from reaching the child.
+    let mut child = command
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .process_group(0)
+        .spawn()
+        .with_context(|| anyhow!("failed to execute {:?}", command.get_program()))?;
+    let stdout = read_in_background(child.stdout.take());
+    let stderr = read_in_background(child.stderr.take());
+
+    let start = Instant::now();
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        if cancellable && cancelled() {
+            kill_process_group(&mut child);
+            return Err(CancelledError.into());
+        }
+        if let Some(timeout) = timeout {
+            if start.elapsed() > timeout {
+                kill_process_group(&mut child);
+                return Err(TimeoutError {
+                    command: format!("{:?}", command),
+                    timeout,
+                }
+                .into());
+            }
+        }
+        std::thread::sleep(POLL_INTERVAL);
+    };
+
+    Ok(Output {
+        status,
+        stdout: stdout.join().unwrap_or_default(),
+        stderr: stderr.join().unwrap_or_default(),
+    })
+}
+
+fn read_in_background<R: Read + Send + 'static>(pipe: Option<R>) -> JoinHandle<Vec<u8>> {
+    std::thread::spawn(move || {
+        let mut buffer = Vec::new();
+        if let Some(mut pipe) = pipe {
+            let _ = pipe.read_to_end(&mut buffer);
+        }
+        buffer
+    })
+}
+
+fn kill_process_group(child: &mut Child) {
+    // The child's pid is also its process group id.
+    unsafe {
+        libc::killpg(child.id() as libc::pid_t, libc::SIGKILL);
+    }
+    let _ = child.wait();
+}
+
+impl CommandError {
+    pub(crate) fn new(command: &'static str, stderr: &[u8]) -> CommandError {
+        CommandError {
+            command,
+            stderr: String::from_utf8_lossy(stderr).into_owned(),
+        }
+    }
+}
+
+impl fmt::Display for CommandError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Failed to execute {}. Stderr - {}",
+            self.command, self.stderr
+        )
+    }
+}
+
+impl std::error::Error for CommandError {}
+
+impl fmt::Display for TimeoutError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} timed out after {:?}", self.command, self.timeout)
+    }
+}
+
+impl std::error::Error for TimeoutError {}
+
+impl fmt::Display for CancelledError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Cancelled by Ctrl-C")
+    }
+}
+
+impl std::error::Error for CancelledError {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use anyhow::Result;
+
+    #[test]
+    fn test_run_output() -> Result<()> {
+        let output = run_with_timeout(
+            Command::new("sh").args(["-c", "echo out; echo err >&2"]),
+            Some(Duration::from_secs(10)),
+            true,
+        )?;
+        assert!(output.status.success());
+        assert_eq!(output.stdout, b"out\n");
+        assert_eq!(output.stderr, b"err\n");
+        Ok(())
+    }
+
+    #[test]
+    fn test_timeout_kills_process_group() -> Result<()> {
+        let start = Instant::now();
+        // The background sleep holds stdout open, so this only returns if it is killed too.
+        let result = run_with_timeout(
+            Command::new("sh").args(["-c", "sleep 30 & sleep 30"]),
+            Some(Duration::from_millis(200)),
+            true,
+        );
+        let e = result.err().ok_or_else(|| anyhow!("expected a timeout"))?;
+        assert!(e.downcast_ref::<TimeoutError>().is_some());
+        assert!(start.elapsed() < Duration::from_secs(10));
+        Ok(())
+    }
+}
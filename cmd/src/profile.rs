@@ -1,15 +1,73 @@
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use enum_iterator::IntoEnumIterator;
 use serde::{de::Visitor, Deserialize, Deserializer, Serialize, Serializer};
 
-use crate::cargo::{Bytes, CompilerMode, Milliseconds, ProfileMode};
+use crate::cache::CacheMode;
+use crate::cargo::{
+    Bytes, CompilerMode, FeatureSet, Milliseconds, OutputSizes, ProfileMode, SampleInfo, Samples,
+};
+use crate::process::{CommandError, TimeoutError};
 use crate::rustup::Version;
 
 #[derive(Debug, Serialize, Clone, Deserialize)]
 pub(crate) struct Profile {
     compile_times: BTreeMap<CompileTimeProfileKey, Vec<Milliseconds>>,
+    /// How the samples in `compile_times` were taken, and which ones are outliers.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    sample_info: BTreeMap<CompileTimeProfileKey, SampleInfo>,
     output_sizes: BTreeMap<SizeProfileKey, Bytes>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    artifact_sizes: BTreeMap<SizeProfileKey, BTreeMap<String, Bytes>>,
+    /// Feature sets of versions that weren't built with the crate's default features.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    feature_sets: BTreeMap<VersionKey, FeatureSet>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    failures: BTreeMap<VersionKey, Failure>,
+    /// Compile times at each job count of a scaling study, all taken on this machine.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    scaling: BTreeMap<ScalingKey, Vec<Milliseconds>>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    scaling_info: BTreeMap<ScalingKey, SampleInfo>,
+}
+
+/// A version that couldn't be profiled, and how many times we tried.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct Failure {
+    #[serde(flatten)]
+    reason: FailureReason,
+    attempts: u32,
+    /// Seconds since the Unix epoch.
+    last_attempt: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind")]
+pub(crate) enum FailureReason {
+    BuildError { stderr: String },
+    Timeout,
+    ToolchainInstall { stderr: String },
+}
+
+/// When a version that failed to be profiled is tried again.
+#[derive(Debug, Copy, Clone)]
+pub(crate) struct RetryPolicy {
+    /// Failed attempts after which a version is only retried once `after` has passed since the
+    /// last one.
+    pub(crate) max_attempts: u32,
+    pub(crate) after: Duration,
+}
+
+/// Which samples `Profile::merge` keeps when both profiles have different samples of a key.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) enum MergePolicy {
+    /// All of them, ours first.
+    KeepBoth,
+    /// Those of the newer profile.
+    PreferNewer,
+    /// Whichever has more, ours if it's a tie.
+    PreferMoreSamples,
 }
 
 #[derive(Debug, Copy, Clone, PartialOrd, Ord, PartialEq, Eq)]
@@ -18,44 +76,277 @@ struct CompileTimeProfileKey(Version, CompilerMode, ProfileMode);
 #[derive(Debug, Copy, Clone, PartialOrd, Ord, PartialEq, Eq)]
 struct SizeProfileKey(Version, CompilerMode);
 
+#[derive(Debug, Copy, Clone, PartialOrd, Ord, PartialEq, Eq)]
+struct VersionKey(Version);
+
+/// A CompileTimeProfileKey with the number of jobs cargo was limited to.
+#[derive(Debug, Copy, Clone, PartialOrd, Ord, PartialEq, Eq)]
+struct ScalingKey(Version, CompilerMode, ProfileMode, u32);
+
 impl Profile {
     pub(crate) fn new() -> Profile {
         Profile {
             compile_times: BTreeMap::new(),
+            sample_info: BTreeMap::new(),
             output_sizes: BTreeMap::new(),
+            artifact_sizes: BTreeMap::new(),
+            feature_sets: BTreeMap::new(),
+            failures: BTreeMap::new(),
+            scaling: BTreeMap::new(),
+            scaling_info: BTreeMap::new(),
         }
     }
 
     pub(crate) fn add_compile_times(
         &mut self,
         version: Version,
-        compile_times: BTreeMap<(CompilerMode, ProfileMode), Vec<Milliseconds>>,
+        compile_times: BTreeMap<(CompilerMode, ProfileMode), Samples>,
     ) {
-        for ((compiler_mode, profile_mode), timings) in compile_times {
+        for ((compiler_mode, profile_mode), samples) in compile_times {
             let key = CompileTimeProfileKey(version, compiler_mode, profile_mode);
-            self.compile_times.insert(key, timings);
+            self.compile_times.insert(key, samples.times);
+            self.sample_info.insert(key, samples.info);
         }
+        self.failures.remove(&VersionKey(version));
+    }
+
+    pub(crate) fn compile_times(
+        &self,
+        version: Version,
+        compiler_mode: CompilerMode,
+        profile_mode: ProfileMode,
+    ) -> Option<&[Milliseconds]> {
+        self.compile_times
+            .get(&CompileTimeProfileKey(version, compiler_mode, profile_mode))
+            .map(|samples| samples.as_slice())
+    }
+
+    /// Every recorded sample list, in key order.
+    pub(crate) fn all_compile_times(
+        &self,
+    ) -> impl Iterator<Item = (Version, CompilerMode, ProfileMode, &[Milliseconds])> {
+        self.compile_times
+            .iter()
+            .map(|(key, samples)| (key.0, key.1, key.2, samples.as_slice()))
+    }
+
+    pub(crate) fn all_output_sizes(
+        &self,
+    ) -> impl Iterator<Item = (Version, CompilerMode, Bytes)> + '_ {
+        self.output_sizes
+            .iter()
+            .map(|(key, size)| (key.0, key.1, *size))
+    }
+
+    /// Whether any compile times of `version` were recorded.
+    pub(crate) fn has_compile_times(&self, version: Version) -> bool {
+        self.compile_times.keys().any(|key| key.0 == version)
+    }
+
+    pub(crate) fn add_scaling(
+        &mut self,
+        version: Version,
+        jobs: u32,
+        compile_times: BTreeMap<(CompilerMode, ProfileMode), Samples>,
+    ) {
+        for ((compiler_mode, profile_mode), samples) in compile_times {
+            let key = ScalingKey(version, compiler_mode, profile_mode, jobs);
+            self.scaling.insert(key, samples.times);
+            self.scaling_info.insert(key, samples.info);
+        }
+    }
+
+    /// Whether every CompilerMode and ProfileMode has been measured at `jobs`.
+    pub(crate) fn scaling_measured(&self, version: Version, jobs: u32) -> bool {
+        CompilerMode::into_enum_iter().all(|compiler_mode| {
+            ProfileMode::into_enum_iter().all(|profile_mode| {
+                let key = ScalingKey(version, compiler_mode, profile_mode, jobs);
+                self.scaling.contains_key(&key)
+            })
+        })
+    }
+
+    pub(crate) fn add_failure(&mut self, version: Version, reason: FailureReason) {
+        let last_attempt = now();
+        let failure = self
+            .failures
+            .entry(VersionKey(version))
+            .or_insert_with(|| Failure {
+                reason: reason.clone(),
+                attempts: 0,
+                last_attempt,
+            });
+        failure.reason = reason;
+        failure.attempts += 1;
+        failure.last_attempt = last_attempt;
     }
 
     pub(crate) fn add_output_sizes(
         &mut self,
         version: Version,
-        debug_size: Bytes,
-        release_size: Bytes,
+        debug_sizes: OutputSizes,
+        release_sizes: OutputSizes,
     ) {
-        self.output_sizes
-            .insert(SizeProfileKey(version, CompilerMode::Debug), debug_size);
-        self.output_sizes
-            .insert(SizeProfileKey(version, CompilerMode::Release), release_size);
+        for (compiler_mode, sizes) in [
+            (CompilerMode::Debug, debug_sizes),
+            (CompilerMode::Release, release_sizes),
+        ] {
+            let key = SizeProfileKey(version, compiler_mode);
+            self.output_sizes.insert(key, sizes.output);
+            self.artifact_sizes.insert(key, sizes.artifacts);
+        }
+    }
+
+    pub(crate) fn set_feature_set(&mut self, version: Version, feature_set: FeatureSet) {
+        if feature_set.is_default() {
+            self.feature_sets.remove(&VersionKey(version));
+        } else {
+            self.feature_sets.insert(VersionKey(version), feature_set);
+        }
     }
 
-    pub(crate) fn versions_to_profile(self: &Profile, min_version: Version) -> Vec<Version> {
+    /// Versions that still need profiling. Versions that have already failed
+    /// `retry.max_attempts` times are skipped, since they'll most likely fail again, until
+    /// `retry.after` has passed.
+    pub(crate) fn versions_to_profile(
+        self: &Profile,
+        min_version: Version,
+        retry: RetryPolicy,
+    ) -> Vec<Version> {
+        let now = now();
         Version::into_enum_iter()
             .filter(|v| *v as u8 >= min_version as u8)
             .filter(|v| !self.version_profiled(v))
+            .filter(|v| {
+                self.failures
+                    .get(&VersionKey(*v))
+                    .is_none_or(|failure| failure.retry_due(retry, now))
+            })
             .collect()
     }
 
+    /// Cache modes of the compile times recorded so far. Samples recorded before cache modes
+    /// existed count as `Unmanaged`.
+    pub(crate) fn cache_modes(&self) -> BTreeSet<CacheMode> {
+        self.compile_times
+            .keys()
+            .map(|key| {
+                self.sample_info
+                    .get(key)
+                    .map_or_else(CacheMode::default, |info| info.cache)
+            })
+            .collect()
+    }
+
+    /// Versions with every compile time and output size recorded.
+    pub(crate) fn profiled_versions(&self) -> Vec<Version> {
+        Version::into_enum_iter()
+            .filter(|v| self.version_profiled(v))
+            .collect()
+    }
+
+    pub(crate) fn all_failures(&self) -> impl Iterator<Item = (Version, &Failure)> {
+        self.failures.iter().map(|(key, failure)| (key.0, failure))
+    }
+
+    pub(crate) fn failed_versions(&self) -> Vec<Version> {
+        self.failures.keys().map(|key| key.0).collect()
+    }
+
+    /// Everything recorded about `version`, except scaling studies.
+    pub(crate) fn only(&self, version: Version) -> Profile {
+        fn filter<K: Copy + Ord, V: Clone>(
+            map: &BTreeMap<K, V>,
+            f: impl Fn(&K) -> bool,
+        ) -> BTreeMap<K, V> {
+            map.iter()
+                .filter(|(key, _)| f(key))
+                .map(|(key, value)| (*key, value.clone()))
+                .collect()
+        }
+        Profile {
+            compile_times: filter(&self.compile_times, |key| key.0 == version),
+            sample_info: filter(&self.sample_info, |key| key.0 == version),
+            output_sizes: filter(&self.output_sizes, |key| key.0 == version),
+            artifact_sizes: filter(&self.artifact_sizes, |key| key.0 == version),
+            feature_sets: filter(&self.feature_sets, |key| key.0 == version),
+            failures: filter(&self.failures, |key| key.0 == version),
+            scaling: BTreeMap::new(),
+            scaling_info: BTreeMap::new(),
+        }
+    }
+
+    /// Replaces everything recorded about the versions `other` has anything about, except
+    /// scaling studies, with what `other` has.
+    pub(crate) fn replace_versions(&mut self, other: Profile) {
+        let versions: BTreeSet<Version> = other
+            .compile_times
+            .keys()
+            .map(|key| key.0)
+            .chain(other.output_sizes.keys().map(|key| key.0))
+            .chain(other.feature_sets.keys().map(|key| key.0))
+            .chain(other.failures.keys().map(|key| key.0))
+            .collect();
+        self.compile_times
+            .retain(|key, _| !versions.contains(&key.0));
+        self.sample_info.retain(|key, _| !versions.contains(&key.0));
+        self.output_sizes
+            .retain(|key, _| !versions.contains(&key.0));
+        self.artifact_sizes
+            .retain(|key, _| !versions.contains(&key.0));
+        self.feature_sets
+            .retain(|key, _| !versions.contains(&key.0));
+        self.failures.retain(|key, _| !versions.contains(&key.0));
+        self.merge(other, true, None);
+    }
+
+    /// Adds what `other` recorded and this profile didn't. Keys whose samples differ are
+    /// returned, and resolved by `policy`, or left as they are without one. Sizes and feature
+    /// sets are only replaced with `PreferNewer`, and of two failures of a version the later one
+    /// is kept.
+    pub(crate) fn merge(
+        &mut self,
+        other: Profile,
+        other_is_newer: bool,
+        policy: Option<MergePolicy>,
+    ) -> Vec<String> {
+        let mut conflicts = merge_samples(
+            &mut self.compile_times,
+            &mut self.sample_info,
+            other.compile_times,
+            other.sample_info,
+            other_is_newer,
+            policy,
+        );
+        conflicts.extend(merge_samples(
+            &mut self.scaling,
+            &mut self.scaling_info,
+            other.scaling,
+            other.scaling_info,
+            other_is_newer,
+            policy,
+        ));
+
+        let replace = other_is_newer && policy == Some(MergePolicy::PreferNewer);
+        merge_values(&mut self.output_sizes, other.output_sizes, replace);
+        merge_values(&mut self.artifact_sizes, other.artifact_sizes, replace);
+        merge_values(&mut self.feature_sets, other.feature_sets, replace);
+
+        for (key, failure) in other.failures {
+            let later = self
+                .failures
+                .get(&key)
+                .is_none_or(|existing| failure.last_attempt > existing.last_attempt);
+            if later {
+                self.failures.insert(key, failure);
+            }
+        }
+        let compile_times = &self.compile_times;
+        self.failures
+            .retain(|key, _| !compile_times.keys().any(|times| times.0 == key.0));
+        conflicts
+    }
+
     fn version_profiled(&self, version: &Version) -> bool {
         for compiler_mode in CompilerMode::into_enum_iter() {
             for profile_mode in ProfileMode::into_enum_iter() {
@@ -78,6 +369,172 @@ impl Profile {
     }
 }
 
+/// Adds the sample lists of `theirs` that `ours` doesn't have, with how they were taken.
+/// Returns the keys of those that both have with different samples. Sample lists kept together
+/// are described afresh.
+fn merge_samples<K: Copy + Ord + Serialize>(
+    ours: &mut BTreeMap<K, Vec<Milliseconds>>,
+    our_info: &mut BTreeMap<K, SampleInfo>,
+    theirs: BTreeMap<K, Vec<Milliseconds>>,
+    mut their_info: BTreeMap<K, SampleInfo>,
+    theirs_newer: bool,
+    policy: Option<MergePolicy>,
+) -> Vec<String> {
+    let mut conflicts = Vec::new();
+    for (key, samples) in theirs {
+        let mut info = their_info.remove(&key);
+        let replace = match ours.get_mut(&key) {
+            None => true,
+            Some(existing) if *existing == samples => false,
+            Some(existing) => {
+                conflicts.push(key_name(&key));
+                match policy {
+                    None => false,
+                    Some(MergePolicy::KeepBoth) => {
+                        existing.extend(samples.iter().copied());
+                        if let Some(kept) = our_info.remove(&key).or_else(|| info.take()) {
+                            our_info.insert(key, kept.merged(existing));
+                        }
+                        false
+                    }
+                    Some(MergePolicy::PreferNewer) => theirs_newer,
+                    Some(MergePolicy::PreferMoreSamples) => samples.len() > existing.len(),
+                }
+            }
+        };
+        if replace {
+            ours.insert(key, samples);
+            match info {
+                Some(info) => our_info.insert(key, info),
+                None => our_info.remove(&key),
+            };
+        }
+    }
+    conflicts
+}
+
+fn merge_values<K: Ord, V>(ours: &mut BTreeMap<K, V>, theirs: BTreeMap<K, V>, replace: bool) {
+    for (key, value) in theirs {
+        if replace || !ours.contains_key(&key) {
+            ours.insert(key, value);
+        }
+    }
+}
+
+/// The key as it's written in results files, e.g. "1.45.0,Check,Clean".
+fn key_name<K: Serialize>(key: &K) -> String {
+    match serde_json::to_value(key) {
+        Ok(serde_json::Value::String(name)) => name,
+        _ => String::new(),
+    }
+}
+
+impl std::str::FromStr for MergePolicy {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "keep-both" => Ok(MergePolicy::KeepBoth),
+            "newer" => Ok(MergePolicy::PreferNewer),
+            "more-samples" => Ok(MergePolicy::PreferMoreSamples),
+            _ => Err("unknown policy, expected keep-both, newer or more-samples"),
+        }
+    }
+}
+
+impl Failure {
+    fn retry_due(&self, retry: RetryPolicy, now: u64) -> bool {
+        self.attempts < retry.max_attempts
+            || now.saturating_sub(self.last_attempt) >= retry.after.as_secs()
+    }
+
+    /// The kind of failure, as it's tagged in results files.
+    pub(crate) fn kind(&self) -> &'static str {
+        match self.reason {
+            FailureReason::BuildError { .. } => "BuildError",
+            FailureReason::Timeout => "Timeout",
+            FailureReason::ToolchainInstall { .. } => "ToolchainInstall",
+        }
+    }
+}
+
+impl FailureReason {
+    /// The failure to record if building the repo failed with `e`. Only a build that cargo
+    /// itself rejected, or that timed out, is the version's fault. That includes `cargo fetch`
+    /// rejecting the manifest or lock file, as an old toolchain does with a newer edition, but
+    /// not failing to download. Anything else, like an I/O error, is `None`, and fails the run
+    /// instead.
+    pub(crate) fn build_error(e: &anyhow::Error) -> Option<FailureReason> {
+        if e.is::<TimeoutError>() {
+            return Some(FailureReason::Timeout);
+        }
+        let is_build_error = match e.downcast_ref::<CommandError>() {
+            Some(command_error) if command_error.command == "cargo" => true,
+            Some(command_error) if command_error.command == "cargo fetch" => {
+                !is_network_error(&command_error.stderr)
+            }
+            _ => false,
+        };
+        is_build_error.then(|| FailureReason::BuildError {
+            stderr: stderr_excerpt(e),
+        })
+    }
+
+    pub(crate) fn toolchain_install(e: &anyhow::Error) -> FailureReason {
+        if e.is::<TimeoutError>() {
+            return FailureReason::Timeout;
+        }
+        FailureReason::ToolchainInstall {
+            stderr: stderr_excerpt(e),
+        }
+    }
+}
+
+/// Seconds since the Unix epoch.
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+/// Whether cargo failed because it couldn't reach a registry or git repo.
+fn is_network_error(stderr: &str) -> bool {
+    const NETWORK_ERRORS: [&str; 7] = [
+        "failed to download",
+        "failed to fetch",
+        "failed to update registry",
+        "failed to query replaced source registry",
+        "spurious network error",
+        "network failure",
+        "resolve host",
+    ];
+    let stderr = stderr.to_lowercase();
+    NETWORK_ERRORS.iter().any(|error| stderr.contains(error))
+}
+
+/// The part of a failed command's stderr worth keeping, starting at the first error.
+fn stderr_excerpt(e: &anyhow::Error) -> String {
+    const MAX_LINES: usize = 20;
+    const MAX_CHARS: usize = 2000;
+
+    let stderr = match e.downcast_ref::<CommandError>() {
+        Some(command_error) => command_error.stderr.clone(),
+        None => format!("{:#}", e),
+    };
+    let lines: Vec<&str> = stderr.lines().collect();
+    let start = lines
+        .iter()
+        .position(|line| line.starts_with("error"))
+        .unwrap_or_else(|| lines.len().saturating_sub(MAX_LINES));
+    let excerpt = lines[start..]
+        .iter()
+        .take(MAX_LINES)
+        .copied()
+        .collect::<Vec<_>>()
+        .join("\n");
+    excerpt.chars().take(MAX_CHARS).collect()
+}
+
 struct CKeyVisitor;
 
 impl<'de> Visitor<'de> for CKeyVisitor {
@@ -121,6 +578,53 @@ impl<'de> Visitor<'de> for SKeyVisitor {
     }
 }
 
+struct ScalingKeyVisitor;
+
+impl<'de> Visitor<'de> for ScalingKeyVisitor {
+    type Value = ScalingKey;
+
+    fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+        formatter.write_str("a comma separated string like '1.34.0,Check,Incremental,4'")
+    }
+
+    fn visit_str<E>(self, value: &str) -> Result<Self::Value, E>
+    where
+        E: serde::de::Error,
+    {
+        let parts: Vec<&str> = value.split(',').collect();
+        if parts.len() != 4 {
+            return Err(serde::de::Error::invalid_value(
+                serde::de::Unexpected::Str(value),
+                &self,
+            ));
+        }
+        let version: Version = parts[0].parse().map_err(serde::de::Error::custom)?;
+        let compiler_mode: CompilerMode = parts[1].parse().map_err(serde::de::Error::custom)?;
+        let profile_mode: ProfileMode = parts[2].parse().map_err(serde::de::Error::custom)?;
+        let jobs: u32 = parts[3].parse().map_err(serde::de::Error::custom)?;
+
+        Ok(ScalingKey(version, compiler_mode, profile_mode, jobs))
+    }
+}
+
+struct VKeyVisitor;
+
+impl<'de> Visitor<'de> for VKeyVisitor {
+    type Value = VersionKey;
+
+    fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+        formatter.write_str("a version like '1.34.0'")
+    }
+
+    fn visit_str<E>(self, value: &str) -> Result<Self::Value, E>
+    where
+        E: serde::de::Error,
+    {
+        let version: Version = value.parse().map_err(serde::de::Error::custom)?;
+        Ok(VersionKey(version))
+    }
+}
+
 impl<'de> Deserialize<'de> for CompileTimeProfileKey {
     fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
     where
@@ -139,6 +643,24 @@ impl<'de> Deserialize<'de> for SizeProfileKey {
     }
 }
 
+impl<'de> Deserialize<'de> for ScalingKey {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_str(ScalingKeyVisitor)
+    }
+}
+
+impl<'de> Deserialize<'de> for VersionKey {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_str(VKeyVisitor)
+    }
+}
+
 impl Serialize for CompileTimeProfileKey {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
     where
@@ -158,10 +680,52 @@ impl Serialize for SizeProfileKey {
         serializer.serialize_str(&v)
     }
 }
+impl Serialize for ScalingKey {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let v = format!(
+            "{},{:?},{:?},{}",
+            self.0.get_string(),
+            self.1,
+            self.2,
+            self.3
+        );
+        serializer.serialize_str(&v)
+    }
+}
+
+impl Serialize for VersionKey {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(self.0.get_string())
+    }
+}
+
 #[cfg(test)]
 mod test {
+    use crate::cache::CacheMode;
+    use crate::cargo::{CompilerMode, ProfileMode};
     use crate::rustup::Version;
     use anyhow::Result;
+    use enum_iterator::IntoEnumIterator;
+
+    fn retry(max_attempts: u32) -> super::RetryPolicy {
+        super::RetryPolicy {
+            max_attempts,
+            after: std::time::Duration::from_secs(30 * 24 * 60 * 60),
+        }
+    }
+
+    fn versions_from(min_version: Version) -> Vec<Version> {
+        Version::into_enum_iter()
+            .filter(|v| *v >= min_version)
+            .collect()
+    }
+
     #[test]
     fn test_versions_to_profile() -> Result<()> {
         let profile: super::Profile = serde_json::from_str(
@@ -172,15 +736,8 @@ mod test {
             }"#,
         )?;
         assert_eq!(
-            profile.versions_to_profile(Version::V1_43),
-            vec![
-                Version::V1_43,
-                Version::V1_44,
-                Version::V1_45,
-                Version::V1_46,
-                Version::V1_47,
-                Version::V1_48,
-            ]
+            profile.versions_to_profile(Version::V1_43, retry(1)),
+            versions_from(Version::V1_43)
         );
 
         let profile: super::Profile = serde_json::from_str(
@@ -204,17 +761,288 @@ mod test {
             }"#,
         )?;
         assert_eq!(
-            profile.versions_to_profile(Version::V1_43),
-            vec![
-                Version::V1_43,
-                Version::V1_44,
-                Version::V1_45,
-                Version::V1_46,
-                Version::V1_47,
-                Version::V1_48,
-            ]
+            profile.versions_to_profile(Version::V1_43, retry(1)),
+            versions_from(Version::V1_44)
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_feature_sets() -> Result<()> {
+        let mut profile = super::Profile::new();
+        profile.set_feature_set(Version::V1_52, Default::default());
+        let feature_set = crate::cargo::FeatureSet {
+            features: vec!["full".to_string()],
+            ..Default::default()
+        };
+        profile.set_feature_set(Version::V1_53, feature_set.clone());
+
+        let json = serde_json::to_string(&profile)?;
+        assert!(json.contains(r#""feature_sets":{"1.53.0":{"features":["full"]}}"#));
+        let profile: super::Profile = serde_json::from_str(&json)?;
+        assert_eq!(
+            profile.feature_sets.get(&super::VersionKey(Version::V1_53)),
+            Some(&feature_set)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_failures() -> Result<()> {
+        let mut profile = super::Profile::new();
+        let error = anyhow::Error::new(crate::process::CommandError::new(
+            "cargo",
+            b"   Compiling syn v1.0.42\nerror[E0658]: use of unstable library feature\n  --> src/lib.rs:1:1\n",
+        ));
+        let reason = super::FailureReason::build_error(&error)
+            .ok_or_else(|| anyhow::anyhow!("expected a build error"))?;
+        profile.add_failure(Version::V1_34, reason);
+        assert_eq!(
+            profile.failures[&super::VersionKey(Version::V1_34)].reason,
+            super::FailureReason::BuildError {
+                stderr: "error[E0658]: use of unstable library feature\n  --> src/lib.rs:1:1"
+                    .to_string()
+            }
+        );
+        assert!(!profile
+            .versions_to_profile(Version::V1_34, retry(1))
+            .contains(&Version::V1_34));
+        assert!(profile
+            .versions_to_profile(Version::V1_34, retry(2))
+            .contains(&Version::V1_34));
+
+        profile.add_failure(Version::V1_34, super::FailureReason::Timeout);
+        let json = serde_json::to_string(&profile)?;
+        assert!(json.contains(r#""failures":{"1.34.0":{"kind":"Timeout","attempts":2,"#));
+        let mut profile: super::Profile = serde_json::from_str(&json)?;
+        assert!(!profile
+            .versions_to_profile(Version::V1_34, retry(2))
+            .contains(&Version::V1_34));
+
+        // Retried anyway once enough time has passed since the last attempt.
+        profile
+            .failures
+            .get_mut(&super::VersionKey(Version::V1_34))
+            .ok_or_else(|| anyhow::anyhow!("expected a failure"))?
+            .last_attempt = 10;
+        assert!(profile
+            .versions_to_profile(Version::V1_34, retry(2))
+            .contains(&Version::V1_34));
+
+        profile.add_compile_times(Version::V1_34, Default::default());
+        assert!(profile.failures.is_empty());
+
+        // Not the version's fault, so not recorded.
+        let error = anyhow::Error::new(crate::process::CommandError::new(
+            "cargo fetch",
+            b"error: failed to download from `https://crates.io/api/v1/crates/syn/1.0.42/download`",
+        ));
+        assert_eq!(super::FailureReason::build_error(&error), None);
+        let error = anyhow::anyhow!("No space left on device");
+        assert_eq!(super::FailureReason::build_error(&error), None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_fetch_failures() {
+        // An old toolchain can't read the manifest, which is the version's fault.
+        let error = anyhow::Error::new(crate::process::CommandError::new(
+            "cargo fetch",
+            b"error: failed to parse manifest at `/tmp/prof/syn/Cargo.toml`\n\n\
+              Caused by:\n  feature `edition2021` is required",
+        ));
+        assert!(matches!(
+            super::FailureReason::build_error(&error),
+            Some(super::FailureReason::BuildError { stderr }) if stderr.contains("edition2021")
+        ));
+
+        let network_errors: [&[u8]; 3] = [
+            b"error: failed to download from `https://crates.io/api/v1/crates/syn/1.0.42/download`",
+            b"warning: spurious network error (2 tries remaining): [6] Couldn't resolve host name",
+            b"error: failed to fetch `https://github.com/rust-lang/crates.io-index`",
+        ];
+        for stderr in network_errors {
+            let error =
+                anyhow::Error::new(crate::process::CommandError::new("cargo fetch", stderr));
+            assert_eq!(super::FailureReason::build_error(&error), None);
+        }
+    }
+
+    #[test]
+    fn test_cache_modes() -> Result<()> {
+        let profile: super::Profile = serde_json::from_str(
+            r#"
+            {
+                "compile_times": {
+                    "1.43.0,Check,Clean" : [1000],
+                    "1.44.0,Check,Clean" : [1000]
+                },
+                "sample_info": {
+                    "1.44.0,Check,Clean" : {
+                        "cv": 0.0,
+                        "stop_reason": "Times",
+                        "warmup": 1,
+                        "cache": "Warm"
+                    }
+                },
+                "output_sizes": {}
+            }"#,
+        )?;
+        let modes: Vec<CacheMode> = profile.cache_modes().into_iter().collect();
+        assert_eq!(modes, vec![CacheMode::Unmanaged, CacheMode::Warm]);
+        assert!(super::Profile::new().cache_modes().is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_scaling() -> Result<()> {
+        let mut profile = super::Profile::new();
+        let samples = crate::cargo::Samples {
+            times: serde_json::from_str("[1000]")?,
+            info: serde_json::from_str(r#"{"cv": 0.0, "stop_reason": "Times"}"#)?,
+        };
+        let compile_times = CompilerMode::into_enum_iter()
+            .flat_map(|c| ProfileMode::into_enum_iter().map(move |p| (c, p)))
+            .map(|key| (key, samples.clone()))
+            .collect();
+        profile.add_scaling(Version::V1_45, 4, compile_times);
+        assert!(profile.scaling_measured(Version::V1_45, 4));
+        assert!(!profile.scaling_measured(Version::V1_45, 2));
+
+        let json = serde_json::to_string(&profile)?;
+        assert!(json.contains(r#""scaling":{"1.45.0,Check,Clean,4":[1000],"#));
+        let profile: super::Profile = serde_json::from_str(&json)?;
+        assert!(profile.scaling_measured(Version::V1_45, 4));
+        assert!(serde_json::from_str::<super::ScalingKey>(r#""1.45.0,Check,Clean""#).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_merge() -> Result<()> {
+        let ours: super::Profile = serde_json::from_str(
+            r#"
+            {
+                "compile_times": {
+                    "1.50.0,Check,Clean": [1000, 1010],
+                    "1.51.0,Check,Clean": [900]
+                },
+                "sample_info": {
+                    "1.51.0,Check,Clean": { "cv": 0.0, "stop_reason": "Times", "seed": 7, "warmup": 1 }
+                },
+                "output_sizes": { "1.50.0,Debug": 100 },
+                "failures": {
+                    "1.52.0": { "kind": "Timeout", "attempts": 1, "last_attempt": 10 }
+                }
+            }"#,
+        )?;
+        let theirs: super::Profile = serde_json::from_str(
+            r#"
+            {
+                "compile_times": {
+                    "1.50.0,Check,Clean": [1000, 1010],
+                    "1.51.0,Check,Clean": [950, 960],
+                    "1.52.0,Check,Clean": [800]
+                },
+                "output_sizes": { "1.50.0,Debug": 200 }
+            }"#,
+        )?;
+        let samples = |profile: &super::Profile, version| {
+            profile
+                .compile_times(version, CompilerMode::Check, ProfileMode::Clean)
+                .map(|times| times.iter().map(|ms| ms.as_u64()).collect::<Vec<_>>())
+        };
+
+        let mut unresolved = ours.clone();
+        let conflicts = unresolved.merge(theirs.clone(), true, None);
+        assert_eq!(conflicts, vec!["1.51.0,Check,Clean"]);
+        assert_eq!(samples(&unresolved, Version::V1_51), Some(vec![900]));
+        assert_eq!(samples(&unresolved, Version::V1_52), Some(vec![800]));
+        // 1.52.0 has samples now.
+        assert!(unresolved.failed_versions().is_empty());
+
+        let mut both = ours.clone();
+        both.merge(theirs.clone(), false, Some(super::MergePolicy::KeepBoth));
+        assert_eq!(samples(&both, Version::V1_51), Some(vec![900, 950, 960]));
+        let info = &both.sample_info[&super::CompileTimeProfileKey(
+            Version::V1_51,
+            CompilerMode::Check,
+            ProfileMode::Clean,
+        )];
+        assert!((info.cv - 0.0343).abs() < 0.001);
+        // 900 is an outlier only next to their samples.
+        assert_eq!(info.outliers, vec![0]);
+        assert_eq!((info.seed, info.warmup), (None, 1));
+
+        let mut newer = ours.clone();
+        newer.merge(theirs.clone(), false, Some(super::MergePolicy::PreferNewer));
+        assert_eq!(samples(&newer, Version::V1_51), Some(vec![900]));
+        let mut newer = ours.clone();
+        newer.merge(theirs.clone(), true, Some(super::MergePolicy::PreferNewer));
+        assert_eq!(samples(&newer, Version::V1_51), Some(vec![950, 960]));
+        assert_eq!(
+            newer
+                .only(Version::V1_50)
+                .output_sizes
+                .values()
+                .next()
+                .map(|b| b.as_u64()),
+            Some(200)
+        );
+
+        let mut more = ours.clone();
+        more.merge(theirs, false, Some(super::MergePolicy::PreferMoreSamples));
+        assert_eq!(samples(&more, Version::V1_51), Some(vec![950, 960]));
+        assert_eq!(
+            more.only(Version::V1_50)
+                .output_sizes
+                .values()
+                .next()
+                .map(|b| b.as_u64()),
+            Some(100)
         );
 
+        let only = ours.only(Version::V1_52);
+        assert!(only.compile_times.is_empty());
+        assert_eq!(only.failed_versions(), vec![Version::V1_52]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_replace_versions() -> Result<()> {
+        let mut profile: super::Profile = serde_json::from_str(
+            r#"
+            {
+                "compile_times": {
+                    "1.50.0,Check,Clean": [1000],
+                    "1.51.0,Check,Clean": [900],
+                    "1.51.0,Debug,Clean": [1900]
+                },
+                "output_sizes": { "1.51.0,Debug": 100 }
+            }"#,
+        )?;
+        let later: super::Profile = serde_json::from_str(
+            r#"
+            {
+                "compile_times": { "1.51.0,Check,Clean": [950, 960, 970] },
+                "output_sizes": {}
+            }"#,
+        )?;
+        profile.replace_versions(later);
+
+        assert!(profile
+            .compile_times(Version::V1_50, CompilerMode::Check, ProfileMode::Clean)
+            .is_some());
+        assert_eq!(
+            profile
+                .compile_times(Version::V1_51, CompilerMode::Check, ProfileMode::Clean)
+                .map(<[_]>::len),
+            Some(3)
+        );
+        assert!(profile
+            .compile_times(Version::V1_51, CompilerMode::Debug, ProfileMode::Clean)
+            .is_none());
+        assert_eq!(profile.all_output_sizes().count(), 0);
         Ok(())
     }
 }
@@ -0,0 +1,505 @@
+use std::io::{IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use anyhow::{anyhow, Context, Result};
+use enum_iterator::IntoEnumIterator;
+use serde::{Deserialize, Serialize};
+
+use crate::cargo::{CompilerMode, ProfileMode, Sampling};
+use crate::profile::Profile;
+use crate::rustup::Version;
+use crate::system::SystemInfo;
+use crate::{show, stats, store};
+
+/// How far a collection run has got, rewritten to `progress-<machine ID>.json` in the results
+/// directory whenever it changes, so that other processes can report on it. A cell is one
+/// version of one repo in one CompilerMode and ProfileMode.
+pub(crate) struct Progress {
+    path: PathBuf,
+    state: RunState,
+    repos: Vec<RepoProgress>,
+    current: Option<usize>,
+    output: Output,
+    /// When the time since was last added to the current repo.
+    last: Instant,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(crate) struct RunState {
+    pub(crate) machine: String,
+    /// Seconds since the Unix epoch.
+    pub(crate) started: u64,
+    pub(crate) updated: u64,
+    pub(crate) cells_planned: usize,
+    /// Including cells that failed.
+    pub(crate) cells_done: usize,
+    pub(crate) repo: Option<String>,
+    pub(crate) version: Option<String>,
+    /// Estimated seconds until every planned cell is done, once there is anything to estimate
+    /// it from.
+    #[serde(default)]
+    pub(crate) eta_seconds: Option<u64>,
+}
+
+/// The cells planned for one repo, and what they're expected to take.
+#[derive(Debug, Clone)]
+pub(crate) struct RepoProgress {
+    pub(crate) name: String,
+    pub(crate) cells_planned: usize,
+    pub(crate) cells_done: usize,
+    /// From the repo's previous samples, if it has any.
+    pub(crate) seconds_per_cell: Option<f64>,
+    /// Spent on the repo so far.
+    pub(crate) seconds: f64,
+}
+
+/// Where progress is shown, besides the progress file.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub(crate) enum Output {
+    /// A bar if stderr is a terminal, otherwise nothing.
+    Auto,
+    /// A bar redrawn on stderr.
+    Bar,
+    /// One JSON event per line on stdout.
+    Ndjson,
+    Off,
+}
+
+/// What happened, as printed with `--progress ndjson`.
+#[derive(Debug, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+enum Event<'a> {
+    RunStarted {
+        cells_planned: usize,
+    },
+    RepoStarted {
+        repo: &'a str,
+        cells_planned: usize,
+    },
+    VersionStarted {
+        repo: &'a str,
+        version: &'a str,
+    },
+    CellsDone {
+        repo: &'a str,
+        cells: usize,
+        cells_done: usize,
+        cells_planned: usize,
+        eta_seconds: Option<u64>,
+    },
+    RunFinished {
+        cells_done: usize,
+        elapsed_seconds: u64,
+    },
+}
+
+#[derive(Debug, Serialize)]
+struct Line<'a> {
+    /// Seconds since the Unix epoch.
+    time: u64,
+    #[serde(flatten)]
+    event: Event<'a>,
+}
+
+/// Cells of each version of a repo.
+pub(crate) fn cells_per_version() -> usize {
+    CompilerMode::into_enum_iter().count() * ProfileMode::into_enum_iter().count()
+}
+
+/// Seconds a cell of the repo is expected to take with `sampling`, from the medians of its most
+/// recently profiled version. Warm-up builds take about as long as samples.
+pub(crate) fn seconds_per_cell(profile: &Profile, sampling: &Sampling) -> Option<f64> {
+    let version = Version::into_enum_iter()
+        .filter(|v| profile.has_compile_times(*v))
+        .last()?;
+    let medians: Vec<f64> = profile
+        .all_compile_times()
+        .filter(|(v, _, _, _)| *v == version)
+        .map(|(_, _, _, times)| {
+            let times: Vec<f64> = times.iter().map(|ms| ms.as_f64()).collect();
+            stats::median(&times) / 1000.0
+        })
+        .collect();
+    Some(stats::mean(&medians) * (sampling.times + sampling.warmup) as f64)
+}
+
+impl Progress {
+    /// Starts a run of the cells planned for `repos` on this machine.
+    pub(crate) fn new(
+        results_dir: &Path,
+        repos: Vec<RepoProgress>,
+        output: Output,
+    ) -> Result<Progress> {
+        let system_info = SystemInfo::current()?;
+        let machine = store::machine_id(&system_info);
+        let path = results_dir.join(format!("progress-{}.json", machine));
+        let output = match output {
+            Output::Auto if std::io::stderr().is_terminal() => Output::Bar,
+            Output::Auto => Output::Off,
+            output => output,
+        };
+        let now = now();
+        let progress = Progress {
+            path,
+            state: RunState {
+                machine,
+                started: now,
+                updated: now,
+                cells_planned: repos.iter().map(|r| r.cells_planned).sum(),
+                cells_done: 0,
+                repo: None,
+                version: None,
+                eta_seconds: eta(&repos).map(|eta| eta.round() as u64),
+            },
+            repos,
+            current: None,
+            output,
+            last: Instant::now(),
+        };
+        progress.emit(Event::RunStarted {
+            cells_planned: progress.state.cells_planned,
+        });
+        progress.write();
+        Ok(progress)
+    }
+
+    pub(crate) fn start_repo(&mut self, repo: &str) {
+        self.account();
+        self.current = self.repos.iter().position(|r| r.name == repo);
+        self.state.repo = Some(repo.to_string());
+        self.state.version = None;
+        let cells_planned = self.current.map_or(0, |i| self.repos[i].cells_planned);
+        self.emit(Event::RepoStarted {
+            repo,
+            cells_planned,
+        });
+        self.update();
+    }
+
+    /// Does nothing if the version is already the current one.
+    pub(crate) fn start_version(&mut self, version: Version) {
+        let version = version.get_string();
+        if self.state.version.as_deref() == Some(version) {
+            return;
+        }
+        self.state.version = Some(version.to_string());
+        self.emit(Event::VersionStarted {
+            repo: self.state.repo.as_deref().unwrap_or(""),
+            version,
+        });
+        self.update();
+    }
+
+    pub(crate) fn done(&mut self, cells: usize) {
+        if cells == 0 {
+            return;
+        }
+        self.account();
+        if let Some(i) = self.current {
+            self.repos[i].cells_done += cells;
+        }
+        self.state.cells_done += cells;
+        self.state.eta_seconds = eta(&self.repos).map(|eta| eta.round() as u64);
+        self.emit(Event::CellsDone {
+            repo: self.state.repo.as_deref().unwrap_or(""),
+            cells,
+            cells_done: self.state.cells_done,
+            cells_planned: self.state.cells_planned,
+            eta_seconds: self.state.eta_seconds,
+        });
+        self.update();
+    }
+
+    /// Removes the progress file. One left behind by a run that failed keeps its last update
+    /// time, so it can be told apart from a run in progress.
+    pub(crate) fn finish(self) -> Result<()> {
+        self.emit(Event::RunFinished {
+            cells_done: self.state.cells_done,
+            elapsed_seconds: now().saturating_sub(self.state.started),
+        });
+        if self.output == Output::Bar {
+            eprintln!();
+        }
+        if self.path.exists() {
+            std::fs::remove_file(&self.path)
+                .with_context(|| anyhow!("Failed to remove progress file - {:?}", &self.path))?;
+        }
+        Ok(())
+    }
+
+    /// Adds the time since the last call to the current repo.
+    fn account(&mut self) {
+        let now = Instant::now();
+        if let Some(i) = self.current {
+            self.repos[i].seconds += now.duration_since(self.last).as_secs_f64();
+        }
+        self.last = now;
+    }
+
+    fn update(&mut self) {
+        self.state.updated = now();
+        self.write();
+        if self.output == Output::Bar {
+            self.draw();
+        }
+    }
+
+    fn emit(&self, event: Event) {
+        if self.output != Output::Ndjson {
+            return;
+        }
+        let line = match serde_json::to_string(&Line { time: now(), event }) {
+            Ok(line) => line,
+            Err(e) => return log::warn!("Failed to serialize progress event - {}", e),
+        };
+        let stdout = std::io::stdout();
+        let mut stdout = stdout.lock();
+        let _ = writeln!(stdout, "{}", line);
+        let _ = stdout.flush();
+    }
+
+    /// Redraws the bar in place. It's left at the start of the line, so that log lines written
+    /// in between overwrite it.
+    fn draw(&self) {
+        let width = show::terminal_width(libc::STDERR_FILENO).unwrap_or(80);
+        let line = bar(&self.state, width);
+        let mut stderr = std::io::stderr();
+        let _ = write!(stderr, "\r\x1b[K{}\r", line);
+        let _ = stderr.flush();
+    }
+
+    /// Written to a temporary file first, so that readers never see half of it. Failing to
+    /// write it isn't worth stopping a run for.
+    fn write(&self) {
+        let temporary = self.path.with_extension("json.tmp");
+        let result = serde_json::to_vec(&self.state)
+            .map_err(anyhow::Error::from)
+            .and_then(|contents| Ok(std::fs::write(&temporary, contents)?))
+            .and_then(|_| Ok(std::fs::rename(&temporary, &self.path)?));
+        if let Err(e) = result {
+            log::warn!("Failed to write progress file - {:?} - {}", &self.path, e);
+        }
+    }
+}
+
+/// Seconds until every planned cell is done. Cells of repos with previous samples are expected
+/// to take as long as those did, scaled by how far off that has been for the cells done so far.
+/// Cells of the others are expected to take as long as theirs have so far. None until there is
+/// something to estimate every remaining cell from.
+fn eta(repos: &[RepoProgress]) -> Option<f64> {
+    let (spent, expected) = repos
+        .iter()
+        .filter_map(|r| Some((r.seconds, r.cells_done as f64 * r.seconds_per_cell?)))
+        .filter(|(_, expected)| *expected > 0.0)
+        .fold((0.0, 0.0), |(s, e), (spent, expected)| {
+            (s + spent, e + expected)
+        });
+    let scale = if expected > 0.0 {
+        spent / expected
+    } else {
+        1.0
+    };
+
+    let (spent, done) = repos
+        .iter()
+        .filter(|r| r.seconds_per_cell.is_none() && r.cells_done > 0)
+        .fold((0.0, 0), |(s, d), r| (s + r.seconds, d + r.cells_done));
+    let (spent, done) = if done > 0 {
+        (spent, done)
+    } else {
+        let done = repos.iter().map(|r| r.cells_done).sum();
+        (repos.iter().map(|r| r.seconds).sum(), done)
+    };
+    let observed = (done > 0).then(|| spent / done as f64);
+
+    repos
+        .iter()
+        .map(|r| {
+            let remaining = r.cells_planned.saturating_sub(r.cells_done) as f64;
+            if remaining == 0.0 {
+                return Some(0.0);
+            }
+            match r.seconds_per_cell {
+                Some(seconds) => Some(remaining * seconds * scale),
+                None => Some(remaining * observed?),
+            }
+        })
+        .sum()
+}
+
+/// `[#####-----] 123/900 cells 13% syn 1.45.0 ETA 2d 3h`, cut to `width`.
+fn bar(state: &RunState, width: usize) -> String {
+    let fraction = if state.cells_planned > 0 {
+        state.cells_done as f64 / state.cells_planned as f64
+    } else {
+        1.0
+    };
+    let eta = match state.eta_seconds {
+        Some(eta) => format!("ETA {}", format_duration(eta)),
+        None => "ETA unknown".to_string(),
+    };
+    let status = format!(
+        " {}/{} cells {:>3.0}% {} {} {}",
+        state.cells_done,
+        state.cells_planned,
+        fraction * 100.0,
+        state.repo.as_deref().unwrap_or(""),
+        state.version.as_deref().unwrap_or(""),
+        eta
+    );
+    // Short of the last column, so that the terminal doesn't wrap it.
+    let bar_width = width.saturating_sub(status.chars().count() + 3).min(40);
+    let filled = ((fraction * bar_width as f64).round() as usize).min(bar_width);
+    let line = format!(
+        "[{}{}]{}",
+        "#".repeat(filled),
+        "-".repeat(bar_width - filled),
+        status
+    );
+    line.chars().take(width.saturating_sub(1)).collect()
+}
+
+/// The two largest units, e.g. "2d 3h", "3h 12m" or "4m 10s".
+fn format_duration(seconds: u64) -> String {
+    let (days, hours, minutes, seconds) = (
+        seconds / 86400,
+        seconds / 3600 % 24,
+        seconds / 60 % 60,
+        seconds % 60,
+    );
+    if days > 0 {
+        format!("{}d {}h", days, hours)
+    } else if hours > 0 {
+        format!("{}h {}m", hours, minutes)
+    } else {
+        format!("{}m {}s", minutes, seconds)
+    }
+}
+
+/// The state of every run with a progress file in `results_dir`.
+pub(crate) fn read_progress(results_dir: &Path) -> Result<Vec<RunState>> {
+    let mut paths: Vec<PathBuf> = std::fs::read_dir(results_dir)
+        .with_context(|| anyhow!("Failed to read results directory - {:?}", results_dir))?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| {
+            path.file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| name.starts_with("progress-") && name.ends_with(".json"))
+        })
+        .collect();
+    paths.sort();
+    paths
+        .iter()
+        .map(|path| {
+            let contents = std::fs::read(path)
+                .with_context(|| anyhow!("Failed to open progress file - {:?}", path))?;
+            serde_json::from_slice(&contents)
+                .with_context(|| anyhow!("Failed to parse progress file - {:?}", path))
+        })
+        .collect()
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+impl std::str::FromStr for Output {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "auto" => Ok(Output::Auto),
+            "bar" => Ok(Output::Bar),
+            "ndjson" => Ok(Output::Ndjson),
+            "off" => Ok(Output::Off),
+            _ => Err("expected auto, bar, ndjson or off"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn repo(planned: usize, done: usize, per_cell: Option<f64>, seconds: f64) -> RepoProgress {
+        RepoProgress {
+            name: String::new(),
+            cells_planned: planned,
+            cells_done: done,
+            seconds_per_cell: per_cell,
+            seconds,
+        }
+    }
+
+    #[test]
+    fn test_eta() {
+        // Nothing done yet, so only the repo with previous samples can be estimated.
+        assert_eq!(eta(&[repo(9, 0, Some(10.0), 0.0)]), Some(90.0));
+        assert_eq!(
+            eta(&[repo(9, 0, Some(10.0), 0.0), repo(9, 0, None, 0.0)]),
+            None
+        );
+
+        // Cells have taken twice as long as expected, so the rest will too.
+        assert_eq!(
+            eta(&[repo(18, 9, Some(10.0), 180.0), repo(9, 0, Some(1.0), 0.0)]),
+            Some(180.0 + 18.0)
+        );
+
+        // Cells without previous samples take as long as theirs have so far.
+        assert_eq!(
+            eta(&[repo(9, 9, Some(10.0), 90.0), repo(18, 9, None, 45.0)]),
+            Some(45.0)
+        );
+        assert_eq!(eta(&[repo(9, 9, Some(10.0), 90.0)]), Some(0.0));
+    }
+
+    #[test]
+    fn test_bar() {
+        let state = RunState {
+            machine: String::new(),
+            started: 0,
+            updated: 0,
+            cells_planned: 900,
+            cells_done: 225,
+            repo: Some("syn".to_string()),
+            version: Some("1.45.0".to_string()),
+            eta_seconds: Some(2 * 86400 + 3 * 3600 + 59),
+        };
+        assert_eq!(
+            bar(&state, 60),
+            "[####-------------] 225/900 cells  25% syn 1.45.0 ETA 2d 3h"
+        );
+        assert_eq!(bar(&state, 20), "[] 225/900 cells  2");
+    }
+
+    #[test]
+    fn test_format_duration() {
+        assert_eq!(format_duration(59), "0m 59s");
+        assert_eq!(format_duration(3 * 3600 + 12 * 60 + 5), "3h 12m");
+    }
+
+    #[test]
+    fn test_event() {
+        let event = Event::CellsDone {
+            repo: "syn",
+            cells: 9,
+            cells_done: 18,
+            cells_planned: 90,
+            eta_seconds: None,
+        };
+        assert_eq!(
+            serde_json::to_string(&Line { time: 1, event }).unwrap(),
+            r#"{"time":1,"event":"cells_done","repo":"syn","cells":9,"cells_done":18,"cells_planned":90,"eta_seconds":null}"#
+        );
+    }
+
+    #[test]
+    fn test_output_from_str() {
+        assert_eq!("ndjson".parse(), Ok(Output::Ndjson));
+        assert!("json".parse::<Output>().is_err());
+    }
+}
@@ -0,0 +1,332 @@
+use std::fmt;
+use std::path::PathBuf;
+
+use anyhow::{anyhow, Result};
+use enum_iterator::IntoEnumIterator;
+use serde::Serialize;
+
+use crate::cargo::{CompilerMode, Milliseconds, ProfileMode};
+use crate::compare::{self, Comparison, Verdict};
+use crate::rustup::Version;
+use crate::store;
+
+/// Largest acceptable increase of the median compile time, as a percentage, for one
+/// CompilerMode or one pair of CompilerMode and ProfileMode, e.g. "Check=3" or
+/// "Release/PatchIncremental=10".
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub(crate) struct ModeThreshold {
+    compiler_mode: CompilerMode,
+    profile_mode: Option<ProfileMode>,
+    percent: f64,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct Thresholds {
+    pub(crate) default: f64,
+    /// Later ones take precedence, and those for a pair over those for a CompilerMode.
+    pub(crate) modes: Vec<ModeThreshold>,
+}
+
+/// A significant increase of a median compile time above its threshold.
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct Regression {
+    version: &'static str,
+    /// Versions whose samples were pooled into the baseline.
+    baseline_versions: Vec<&'static str>,
+    threshold: f64,
+    #[serde(flatten)]
+    comparison: Comparison,
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct Report {
+    /// Number of pairs of CompilerMode and ProfileMode compared, over all repos and machines.
+    checked: usize,
+    /// Number of pairs with too few samples for any change to be significant, which weren't
+    /// checked. A larger --window or more samples of each version fixes them.
+    underpowered: usize,
+    regressions: Vec<Regression>,
+}
+
+/// Some compile times regressed. Returned so that the process exits with its own code.
+#[derive(Debug)]
+pub(crate) struct RegressionError(usize);
+
+/// Prints a JSON report of the compile times of the newest version (or `version`) that are
+/// significantly slower than those of the `window` versions before it, and fails if there are
+/// any.
+pub(crate) fn check_regressions(
+    results_files: &[PathBuf],
+    version: Option<Version>,
+    window: usize,
+    thresholds: &Thresholds,
+    alpha: f64,
+) -> Result<()> {
+    let report = check(results_files, version, window, thresholds, alpha)?;
+    println!("{}", serde_json::to_string_pretty(&report)?);
+    if report.checked == 0 && report.underpowered > 0 {
+        return Err(anyhow!(
+            "None of the {} compile times to check has enough samples to tell a regression. Increase --window, or take more samples with --times",
+            report.underpowered
+        ));
+    }
+    if report.checked == 0 {
+        return Err(anyhow!("No compile times to check for regressions"));
+    }
+    if report.underpowered > 0 {
+        log::warn!(
+            "{} compile times have too few samples to tell a regression and weren't checked. Increase --window, or take more samples with --times",
+            report.underpowered
+        );
+    }
+    for regression in &report.regressions {
+        let comparison = &regression.comparison;
+        log::warn!(
+            "{} - {} - {} regressed by {:.1}% on {} (threshold {}%)",
+            comparison.machine,
+            comparison.repo,
+            comparison.mode(),
+            comparison.change,
+            regression.version,
+            regression.threshold
+        );
+    }
+    if !report.regressions.is_empty() {
+        return Err(RegressionError(report.regressions.len()).into());
+    }
+    Ok(())
+}
+
+fn check(
+    results_files: &[PathBuf],
+    version: Option<Version>,
+    window: usize,
+    thresholds: &Thresholds,
+    alpha: f64,
+) -> Result<Report> {
+    let mut report = Report {
+        checked: 0,
+        underpowered: 0,
+        regressions: Vec::new(),
+    };
+    for path in results_files {
+        let (system_info, profiles) = store::read_results(path)?;
+        let machine = compare::machine_label(path, &system_info)?;
+        let version = match version {
+            Some(version) => version,
+            None => match Version::into_enum_iter()
+                .filter(|v| {
+                    profiles
+                        .values()
+                        .any(|profile| profile.has_compile_times(*v))
+                })
+                .last()
+            {
+                Some(version) => version,
+                None => continue,
+            },
+        };
+        log::info!("{} - Checking version {}", &machine, version.get_string());
+
+        for (repo, profile) in &profiles {
+            for compiler_mode in CompilerMode::into_enum_iter() {
+                for profile_mode in ProfileMode::into_enum_iter() {
+                    let after = match profile.compile_times(version, compiler_mode, profile_mode) {
+                        Some(after) => after,
+                        None => continue,
+                    };
+                    let mut baseline_versions: Vec<Version> = Version::into_enum_iter()
+                        .filter(|v| {
+                            *v < version
+                                && profile
+                                    .compile_times(*v, compiler_mode, profile_mode)
+                                    .is_some()
+                        })
+                        .collect();
+                    let skip = baseline_versions.len().saturating_sub(window);
+                    baseline_versions.drain(..skip);
+                    if baseline_versions.is_empty() {
+                        continue;
+                    }
+                    let before: Vec<Milliseconds> = baseline_versions
+                        .iter()
+                        .filter_map(|v| profile.compile_times(*v, compiler_mode, profile_mode))
+                        .flatten()
+                        .copied()
+                        .collect();
+
+                    let comparison = Comparison::new(
+                        machine.clone(),
+                        repo.clone(),
+                        compiler_mode,
+                        profile_mode,
+                        (&before, after),
+                        alpha,
+                    );
+                    if comparison.verdict == Verdict::InsufficientSamples {
+                        log::warn!(
+                            "{} - {} - {} has too few samples to tell a regression on {}",
+                            comparison.machine,
+                            comparison.repo,
+                            comparison.mode(),
+                            version.get_string()
+                        );
+                        report.underpowered += 1;
+                        continue;
+                    }
+                    let threshold = thresholds.get(compiler_mode, profile_mode);
+                    report.checked += 1;
+                    if comparison.verdict == Verdict::Regression && comparison.change > threshold {
+                        report.regressions.push(Regression {
+                            version: version.get_string(),
+                            baseline_versions: baseline_versions
+                                .iter()
+                                .map(|v| v.get_string())
+                                .collect(),
+                            threshold,
+                            comparison,
+                        });
+                    }
+                }
+            }
+        }
+    }
+    Ok(report)
+}
+
+impl Thresholds {
+    fn get(&self, compiler_mode: CompilerMode, profile_mode: ProfileMode) -> f64 {
+        let matching = |pair: bool| {
+            self.modes.iter().rev().find(|threshold| {
+                threshold.compiler_mode == compiler_mode
+                    && threshold.profile_mode == pair.then_some(profile_mode)
+            })
+        };
+        matching(true)
+            .or_else(|| matching(false))
+            .map(|threshold| threshold.percent)
+            .unwrap_or(self.default)
+    }
+}
+
+impl std::str::FromStr for ModeThreshold {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        const EXPECTED: &str = "expected a threshold like Check=3 or Check/Clean=3";
+        let (modes, percent) = s.split_once('=').ok_or(EXPECTED)?;
+        let percent = percent.trim().parse().map_err(|_| EXPECTED)?;
+        let (compiler_mode, profile_mode) = match modes.split_once('/') {
+            Some((compiler_mode, profile_mode)) => (
+                compiler_mode,
+                Some(parse_mode(profile_mode).ok_or(EXPECTED)?),
+            ),
+            None => (modes, None),
+        };
+        Ok(ModeThreshold {
+            compiler_mode: parse_mode(compiler_mode).ok_or(EXPECTED)?,
+            profile_mode,
+            percent,
+        })
+    }
+}
+
+fn parse_mode<T: IntoEnumIterator + fmt::Debug>(s: &str) -> Option<T> {
+    T::into_enum_iter().find(|mode| format!("{:?}", mode) == s.trim())
+}
+
+impl fmt::Display for RegressionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} compile times regressed", self.0)
+    }
+}
+
+impl std::error::Error for RegressionError {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use anyhow::Result;
+
+    #[test]
+    fn test_thresholds() {
+        let thresholds = Thresholds {
+            default: 5.0,
+            modes: vec![
+                "Check/Clean=1".parse().unwrap(),
+                "Check=3".parse().unwrap(),
+                "Check=4".parse().unwrap(),
+            ],
+        };
+        assert_eq!(thresholds.get(CompilerMode::Check, ProfileMode::Clean), 1.0);
+        assert_eq!(
+            thresholds.get(CompilerMode::Check, ProfileMode::Incremental),
+            4.0
+        );
+        assert_eq!(thresholds.get(CompilerMode::Debug, ProfileMode::Clean), 5.0);
+        assert!("Check/Slow=1".parse::<ModeThreshold>().is_err());
+        assert!("Check".parse::<ModeThreshold>().is_err());
+    }
+
+    #[test]
+    fn test_check() -> Result<()> {
+        let dir =
+            std::env::temp_dir().join(format!("arewefastyet-regressions-{}", std::process::id()));
+        std::fs::create_dir_all(&dir)?;
+        let path = dir.join("results-2-3ca971dfaacb948d.json");
+        std::fs::write(
+            &path,
+            r#"{
+                "system_info": {
+                    "num_cores": 2,
+                    "num_physical_cores": 2,
+                    "cpu_model": "Intel(R) Xeon(R) Gold 6140 CPU @ 2.30GHz"
+                },
+                "profiles": {
+                    "syn": {
+                        "compile_times": {
+                            "1.50.0,Check,Clean": [1090, 1100, 1110, 1095, 1105],
+                            "1.51.0,Check,Clean": [1000, 1010, 990, 1005, 995],
+                            "1.52.0,Check,Clean": [1060, 1070, 1050, 1065, 1055],
+                            "1.51.0,Debug,Clean": [2000, 2010, 1990, 2005, 1995],
+                            "1.52.0,Debug,Clean": [2000, 2010, 1990, 2005, 1995],
+                            "1.51.0,Release,Clean": [3000, 3010, 2990],
+                            "1.52.0,Release,Clean": [3600, 3610, 3590]
+                        },
+                        "output_sizes": {}
+                    }
+                }
+            }"#,
+        )?;
+        let files = [path];
+        let thresholds = |default| Thresholds {
+            default,
+            modes: Vec::new(),
+        };
+        let previous = check(&files, None, 1, &thresholds(5.0), 0.05);
+        let lenient = check(&files, None, 1, &thresholds(10.0), 0.05);
+        let window = check(&files, None, 2, &thresholds(5.0), 0.05);
+        let older = check(&files, Some(Version::V1_51), 1, &thresholds(5.0), 0.05);
+        std::fs::remove_dir_all(&dir)?;
+
+        let previous = previous?;
+        assert_eq!(previous.checked, 2);
+        // 3 samples of each can't show the 20% regression of Release/Clean.
+        assert_eq!(previous.underpowered, 1);
+        assert_eq!(previous.regressions.len(), 1);
+        let regression = &previous.regressions[0];
+        assert_eq!(regression.version, "1.52.0");
+        assert_eq!(regression.baseline_versions, vec!["1.51.0"]);
+        assert_eq!(regression.comparison.compiler_mode, CompilerMode::Check);
+        assert!((regression.comparison.change - 6.0).abs() < 1e-9);
+
+        assert!(lenient?.regressions.is_empty());
+        // 1.50.0 was slower, which hides the regression from 1.51.0.
+        assert!(window?.regressions.is_empty());
+        let older = older?;
+        assert_eq!(older.checked, 1);
+        assert_eq!(older.underpowered, 0);
+        assert!(older.regressions.is_empty());
+        Ok(())
+    }
+}
@@ -1,15 +1,23 @@
-use std::path::PathBuf;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
 use std::process::Command;
 
 use anyhow::{anyhow, Context, Result};
-use once_cell::sync::OnceCell;
-use once_cell::unsync::Lazy;
+use once_cell::sync::{Lazy, OnceCell};
+use regex::Regex;
 use serde::{Deserialize, Serialize};
 
+use crate::cache::CacheMode;
+use crate::cargo::{FeatureSet, Sampling};
+use crate::process::{self, CommandKind};
 use crate::rustup::Version;
 
 static ARE_WE_FAST_YET: &str = "arewefastyet-dir";
+const DEFAULT_TIMES: u32 = 5;
+const DEFAULT_CV_THRESHOLD: f64 = 0.05;
+pub(crate) const DEFAULT_OUTLIER_FENCE: f64 = 3.0;
 static WORKING_DIRECTORY: OnceCell<PathBuf> = OnceCell::new();
+static PRINTLN_RE: Lazy<Regex> = Lazy::new(|| Regex::new("((fn main.*)|(pub fn.*))").unwrap());
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub(crate) struct Repo {
@@ -17,9 +25,53 @@ pub(crate) struct Repo {
     sub_directory: String,
     url: String,
     touch_file: String,
-    output: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output: Option<String>,
     commit: String,
     pub min_version: Version,
+    #[serde(flatten)]
+    pub options: RepoOptions,
+}
+
+/// Knobs that can be set for every repo in the `[defaults]` table of the repos file and
+/// overridden by each repo.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub(crate) struct RepoOptions {
+    /// Number of samples to collect for each pair of CompilerMode and ProfileMode.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub times: Option<u32>,
+    /// Most samples to take while they vary by more than `cv_threshold`. Defaults to twice
+    /// `times`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_times: Option<u32>,
+    /// Highest acceptable coefficient of variation of samples, e.g. 0.05 for 5%.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cv_threshold: Option<f64>,
+    /// Samples further than this many scaled MADs from the median are flagged as outliers.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub outlier_fence: Option<f64>,
+    /// Untimed builds of each pair of CompilerMode and ProfileMode before the first sample.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub warmup: Option<u32>,
+    /// What to do to the page cache before every timed build - Unmanaged, Cold or Warm.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cache: Option<CacheMode>,
+    /// Features to enable, passed to cargo as `--features`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub features: Option<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub all_features: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub no_default_features: Option<bool>,
+    /// Workspace member to build, passed to cargo as `-p`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub package: Option<String>,
+    /// Extra arguments appended to every cargo invocation.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cargo_args: Option<Vec<String>>,
+    /// Environment variables set for every cargo invocation.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub env: BTreeMap<String, String>,
 }
 
 enum GitCommand {
@@ -28,7 +80,60 @@ enum GitCommand {
     Reset,
 }
 
+impl RepoOptions {
+    /// Fills in everything not set here from `defaults`.
+    pub(crate) fn inherit(mut self, defaults: &RepoOptions) -> RepoOptions {
+        self.times = self.times.or(defaults.times);
+        self.max_times = self.max_times.or(defaults.max_times);
+        self.cv_threshold = self.cv_threshold.or(defaults.cv_threshold);
+        self.outlier_fence = self.outlier_fence.or(defaults.outlier_fence);
+        self.warmup = self.warmup.or(defaults.warmup);
+        self.cache = self.cache.or(defaults.cache);
+        self.features = self.features.or_else(|| defaults.features.clone());
+        self.all_features = self.all_features.or(defaults.all_features);
+        self.no_default_features = self.no_default_features.or(defaults.no_default_features);
+        self.package = self.package.or_else(|| defaults.package.clone());
+        self.cargo_args = self.cargo_args.or_else(|| defaults.cargo_args.clone());
+        let mut env = defaults.env.clone();
+        env.append(&mut self.env);
+        self.env = env;
+        self
+    }
+}
+
 impl Repo {
+    pub(crate) fn sampling(self: &Repo) -> Sampling {
+        let times = self.options.times.unwrap_or(DEFAULT_TIMES);
+        Sampling {
+            times,
+            max_times: self.options.max_times.unwrap_or(2 * times).max(times),
+            cv_threshold: self.options.cv_threshold.unwrap_or(DEFAULT_CV_THRESHOLD),
+            outlier_fence: self.options.outlier_fence.unwrap_or(DEFAULT_OUTLIER_FENCE),
+            warmup: self.options.warmup.unwrap_or_default(),
+            cache: self.options.cache.unwrap_or_default(),
+        }
+    }
+
+    pub(crate) fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// The tag or commit the repo is checked out at.
+    pub(crate) fn commit(&self) -> &str {
+        &self.commit
+    }
+
+    pub(crate) fn feature_set(self: &Repo) -> FeatureSet {
+        let options = &self.options;
+        FeatureSet {
+            features: options.features.clone().unwrap_or_default(),
+            all_features: options.all_features.unwrap_or_default(),
+            no_default_features: options.no_default_features.unwrap_or_default(),
+            package: options.package.clone(),
+            cargo_args: options.cargo_args.clone().unwrap_or_default(),
+        }
+    }
+
     pub(crate) fn clone_repo(self: &Repo) -> Result<()> {
         let repo_dir = self
             .get_base_directory()
@@ -43,17 +148,34 @@ impl Repo {
     }
 
     pub(crate) fn remove_target_dir(self: &Repo) -> Result<()> {
-        let target_dir = self
-            .get_target_directory()
-            .ok_or_else(|| anyhow!("Could not find target directory"))?;
+        let target_dir = self.get_target_directory()?;
         if !target_dir.exists() {
-            log::warn!("Directory {:?} doesn't exist. Skipping delete", &target_dir);
+            log::info!("Directory {:?} doesn't exist. Skipping delete", &target_dir);
             return Ok(());
         }
         std::fs::remove_dir_all(&target_dir)
             .with_context(|| anyhow!("failed to remove target directory - {:?}", target_dir))
     }
 
+    /// Fails if the target directory has anything in it. Called before every Clean build so that
+    /// a target directory we failed to find or delete can't turn it into a warm build.
+    pub(crate) fn ensure_target_dir_empty(self: &Repo) -> Result<()> {
+        let target_dir = self.get_target_directory()?;
+        if !target_dir.exists() {
+            return Ok(());
+        }
+        let mut entries = std::fs::read_dir(&target_dir)
+            .with_context(|| anyhow!("failed to read target directory - {:?}", target_dir))?;
+        if entries.next().is_some() {
+            return Err(anyhow!(
+                "Target directory {:?} of {} is not empty before a clean build",
+                target_dir,
+                &self.name
+            ));
+        }
+        Ok(())
+    }
+
     pub(crate) fn touch_src(self: &Repo) -> Result<()> {
         let touch_file = self
             .get_touch_file()
@@ -61,10 +183,10 @@ impl Repo {
         if !touch_file.exists() {
             return Err(anyhow!("Touch file does not exist"));
         }
-        let output = Command::new("touch")
-            .args(&[&touch_file])
-            .output()
-            .with_context(|| "failed to execute touch")?;
+        let output = process::run(
+            Command::new("touch").args([&touch_file]),
+            CommandKind::Touch,
+        )?;
         if !output.status.success() {
             let stderr = std::str::from_utf8(&output.stderr)
                 .with_context(|| "failed to decode touch stderr")?;
@@ -86,14 +208,32 @@ impl Repo {
             return Err(anyhow!("Touch file does not exist"));
         }
         let contents = std::fs::read_to_string(&touch_file)
-            .with_context(|| anyhow!("Failed to read touch file - {:?}"))?;
-        let re = Lazy::new(|| regex::Regex::new("((fn main.*)|(pub fn.*))").unwrap());
-        let contents = re.replace(&contents, r#"$1 println!("hello");"#);
+            .with_context(|| anyhow!("Failed to read touch file - {:?}", touch_file))?;
+        let contents = PRINTLN_RE.replace(&contents, r#"$1 println!("hello");"#);
         std::fs::write(&touch_file, contents.as_ref())
             .with_context(|| anyhow!("Failed to modify touch file - {:?}", touch_file))?;
         Ok(())
     }
 
+    /// Checks that the touch file exists and has a function `add_println` can patch.
+    pub(crate) fn check_touch_file(self: &Repo) -> Result<()> {
+        let touch_file = self
+            .get_touch_file()
+            .ok_or_else(|| anyhow!("Could not find touch file"))?;
+        if !touch_file.exists() {
+            return Err(anyhow!("Touch file does not exist - {:?}", touch_file));
+        }
+        let contents = std::fs::read_to_string(&touch_file)
+            .with_context(|| anyhow!("Failed to read touch file - {:?}", touch_file))?;
+        if !PRINTLN_RE.is_match(&contents) {
+            return Err(anyhow!(
+                "Touch file {:?} has no `fn main` or `pub fn` to add a println to",
+                touch_file
+            ));
+        }
+        Ok(())
+    }
+
     pub(crate) fn git_reset(self: &Repo) -> Result<()> {
         self.git(GitCommand::Reset)
     }
@@ -103,22 +243,10 @@ impl Repo {
         Some(dir.join(&self.name).join(&self.sub_directory))
     }
 
-    pub(crate) fn get_debug_output_path(self: &Repo) -> Option<PathBuf> {
-        self.get_output_path("debug")
-    }
-
-    pub(crate) fn get_release_output_path(self: &Repo) -> Option<PathBuf> {
-        self.get_output_path("release")
-    }
-
-    fn get_target_directory(self: &Repo) -> Option<PathBuf> {
-        let dir = WORKING_DIRECTORY.get()?;
-        Some(dir.join(&self.name).join("target"))
-    }
-
-    fn get_output_path(self: &Repo, folder: &str) -> Option<PathBuf> {
-        let target_directory = self.get_target_directory()?;
-        Some(target_directory.join(folder).join(&self.output))
+    /// Asks cargo where the target directory is, so that `CARGO_TARGET_DIR` and repos whose
+    /// `sub_directory` isn't part of a larger workspace are handled.
+    fn get_target_directory(self: &Repo) -> Result<PathBuf> {
+        Ok(crate::cargo::metadata(self)?.target_directory)
     }
 
     fn get_touch_file(self: &Repo) -> Option<PathBuf> {
@@ -148,11 +276,11 @@ impl Repo {
             Reset => ["reset", "--hard"],
         };
 
-        let output = Command::new("git")
-            .current_dir(&directory)
-            .args(&args)
-            .output()
-            .with_context(|| "failed to execute git")?;
+        let kind = match command {
+            Reset => CommandKind::GitReset,
+            Checkout | CloneRepo => CommandKind::Git,
+        };
+        let output = process::run(Command::new("git").current_dir(&directory).args(args), kind)?;
 
         if !output.status.success() {
             let stderr = std::str::from_utf8(&output.stderr)
@@ -168,6 +296,10 @@ impl Repo {
     }
 }
 
+pub(crate) fn working_directory() -> Option<&'static Path> {
+    WORKING_DIRECTORY.get().map(PathBuf::as_path)
+}
+
 pub(crate) fn create_working_directory(mut working_dir: PathBuf) -> Result<()> {
     WORKING_DIRECTORY.get_or_init(|| {
         if !working_dir.ends_with(ARE_WE_FAST_YET) {
@@ -224,7 +356,7 @@ mod test {
     }
 
     fn file_size(path: &PathBuf) -> Result<u64> {
-        let file = std::fs::File::open(&path)
+        let file = std::fs::File::open(path)
             .with_context(|| anyhow!("failed to find file - {:?}", path))?;
         let metadata = file.metadata()?;
         Ok(metadata.len())
@@ -0,0 +1,348 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::path::Path;
+
+use anyhow::{anyhow, Context, Result};
+use enum_iterator::IntoEnumIterator;
+
+use crate::cargo::{CompilerMode, ProfileMode};
+use crate::compare::{self, Verdict};
+use crate::profile::Profile;
+use crate::repo::Repo;
+use crate::rustup::Version;
+use crate::{stats, store};
+
+/// Colours of the lines of each machine, fewest cores first, then of each CompilerMode in the
+/// size chart.
+const COLOURS: &[&str] = &[
+    "#4e79a7", "#f28e2b", "#e15759", "#76b7b2", "#59a14f", "#edc948", "#b07aa1", "#ff9da7",
+];
+
+/// Rows in the table of the biggest changes.
+const BIGGEST_CHANGES: usize = 20;
+
+const CHART_WIDTH: f64 = 300.0;
+const CHART_HEIGHT: f64 = 170.0;
+/// Room for the axis labels, left and bottom.
+const MARGIN_LEFT: f64 = 40.0;
+const MARGIN_BOTTOM: f64 = 20.0;
+const MARGIN: f64 = 8.0;
+
+const STYLE: &str = "body{font-family:sans-serif;margin:2em;color:#222}\
+h2{margin-top:2em;border-bottom:1px solid #ccc}\
+table{border-collapse:collapse}td,th{padding:2px 8px;text-align:right}\
+td:nth-child(-n+3),th:nth-child(-n+3){text-align:left}\
+.regression{color:#c00}.improvement{color:#080}\
+.charts{display:flex;flex-wrap:wrap;gap:8px}\
+svg{font-size:10px}.legend span{margin-right:1em}";
+
+struct Machine {
+    label: String,
+    profiles: BTreeMap<String, Profile>,
+}
+
+/// A line of a chart, with a y value for some of the chart's x values.
+struct Series {
+    label: String,
+    colour: &'static str,
+    points: Vec<(usize, f64)>,
+}
+
+/// Writes a self-contained HTML report of every results file in `results_dir` to `output`.
+/// Compile times are medians, and the biggest changes between the two most recent versions
+/// are significant if their p-value is below `alpha`.
+pub(crate) fn report(
+    repos_file: &Path,
+    results_dir: &Path,
+    output: &Path,
+    alpha: f64,
+) -> Result<()> {
+    let repos = store::get_repos(repos_file, &Default::default())?;
+    let results_files = store::get_result_files(results_dir)?;
+    let mut machines = Vec::new();
+    for path in &results_files {
+        let (system_info, profiles) = store::read_results(path)?;
+        let label = compare::machine_label(path, &system_info)?;
+        machines.push((system_info.num_cores, Machine { label, profiles }));
+    }
+    machines.sort_by(|a, b| (a.0, &a.1.label).cmp(&(b.0, &b.1.label)));
+    let machines: Vec<Machine> = machines.into_iter().map(|(_, machine)| machine).collect();
+
+    let versions: Vec<Version> = Version::into_enum_iter()
+        .filter(|v| {
+            machines
+                .iter()
+                .any(|m| m.profiles.values().any(|p| p.has_compile_times(*v)))
+        })
+        .collect();
+    if versions.is_empty() {
+        return Err(anyhow!("No compile times in {:?}", results_dir));
+    }
+
+    let mut html = String::new();
+    writeln!(
+        html,
+        "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\"><title>arewefastyet report</title>\
+         <style>{}</style></head><body>\n<h1>arewefastyet report</h1>",
+        STYLE
+    )?;
+    writeln!(html, "<p class=\"legend\">")?;
+    for (machine, colour) in machines.iter().zip(COLOURS.iter().cycle()) {
+        writeln!(
+            html,
+            "<span style=\"color:{}\">&#9632; {}</span>",
+            colour,
+            escape(&machine.label)
+        )?;
+    }
+    writeln!(html, "</p>")?;
+
+    if let [.., baseline, candidate] = versions.as_slice() {
+        let comparisons = compare::comparisons(&results_files, *baseline, *candidate, alpha)?;
+        summary(&mut html, &comparisons, *baseline, *candidate)?;
+    }
+    for repo in &repos {
+        repo_section(&mut html, repo, &machines, &versions)?;
+    }
+    writeln!(html, "</body></html>")?;
+
+    log::info!("Writing to {:?}", output);
+    std::fs::write(output, html).with_context(|| anyhow!("Failed to write report - {:?}", output))
+}
+
+/// The largest changes of a median, regressions or improvements, between two versions.
+fn summary(
+    html: &mut String,
+    comparisons: &[compare::Comparison],
+    baseline: Version,
+    candidate: Version,
+) -> Result<()> {
+    let mut comparisons: Vec<&compare::Comparison> = comparisons.iter().collect();
+    comparisons.sort_by(|a, b| b.change.abs().partial_cmp(&a.change.abs()).unwrap());
+    writeln!(
+        html,
+        "<h2>Biggest changes from {} to {}</h2>\n<table>\n<tr><th>repo</th><th>mode</th>\
+         <th>machine</th><th>{}</th><th>{}</th><th>change</th><th>95% CI</th><th>p</th></tr>",
+        baseline.get_string(),
+        candidate.get_string(),
+        baseline.get_string(),
+        candidate.get_string()
+    )?;
+    for c in comparisons.into_iter().take(BIGGEST_CHANGES) {
+        let class = match c.verdict {
+            Verdict::Regression => "regression",
+            Verdict::Improvement => "improvement",
+            Verdict::Unchanged | Verdict::InsufficientSamples => "",
+        };
+        writeln!(
+            html,
+            "<tr class=\"{}\"><td>{}</td><td>{}</td><td>{}</td><td>{:.2}s</td><td>{:.2}s</td>\
+             <td>{:+.1}%</td><td>{:+.1}% to {:+.1}%</td><td>{:.3}</td></tr>",
+            class,
+            escape(&c.repo),
+            c.mode(),
+            escape(&c.machine),
+            c.baseline / 1000.0,
+            c.candidate / 1000.0,
+            c.change,
+            c.change_ci[0],
+            c.change_ci[1],
+            c.p_value
+        )?;
+    }
+    writeln!(html, "</table>")?;
+    Ok(())
+}
+
+/// A chart of the median compile times of each pair of modes, one line per machine, and one of
+/// the output sizes.
+fn repo_section(
+    html: &mut String,
+    repo: &Repo,
+    machines: &[Machine],
+    versions: &[Version],
+) -> Result<()> {
+    writeln!(
+        html,
+        "<h2><a href=\"{}\">{}</a> ({})</h2>\n<div class=\"charts\">",
+        escape(repo.url()),
+        escape(&repo.name),
+        escape(repo.commit())
+    )?;
+    for compiler_mode in CompilerMode::into_enum_iter() {
+        for profile_mode in ProfileMode::into_enum_iter() {
+            let series: Vec<Series> = machines
+                .iter()
+                .zip(COLOURS.iter().cycle())
+                .map(|(machine, colour)| Series {
+                    label: machine.label.clone(),
+                    colour,
+                    points: machine
+                        .profiles
+                        .get(&repo.name)
+                        .map(|profile| {
+                            compile_times(profile, versions, compiler_mode, profile_mode)
+                        })
+                        .unwrap_or_default(),
+                })
+                .collect();
+            let title = format!("{:?}/{:?} (s)", compiler_mode, profile_mode);
+            html.push_str(&line_chart(&title, versions, &series));
+        }
+    }
+
+    // Sizes are the same on every machine, so those of the first one with any are shown.
+    let sizes = machines
+        .iter()
+        .filter_map(|machine| machine.profiles.get(&repo.name))
+        .map(|profile| output_sizes(profile, versions))
+        .find(|series| series.iter().any(|s| !s.points.is_empty()));
+    if let Some(series) = sizes {
+        html.push_str(&line_chart("Output size (MB)", versions, &series));
+    }
+    writeln!(html, "</div>")?;
+    Ok(())
+}
+
+fn compile_times(
+    profile: &Profile,
+    versions: &[Version],
+    compiler_mode: CompilerMode,
+    profile_mode: ProfileMode,
+) -> Vec<(usize, f64)> {
+    versions
+        .iter()
+        .enumerate()
+        .filter_map(|(x, version)| {
+            let times = profile.compile_times(*version, compiler_mode, profile_mode)?;
+            let times: Vec<f64> = times.iter().map(|ms| ms.as_f64()).collect();
+            Some((x, stats::median(&times) / 1000.0))
+        })
+        .collect()
+}
+
+fn output_sizes(profile: &Profile, versions: &[Version]) -> Vec<Series> {
+    let mut sizes: BTreeMap<CompilerMode, Vec<(usize, f64)>> = BTreeMap::new();
+    for (version, compiler_mode, bytes) in profile.all_output_sizes() {
+        if let Some(x) = versions.iter().position(|v| *v == version) {
+            let mb = bytes.as_u64() as f64 / (1024.0 * 1024.0);
+            sizes.entry(compiler_mode).or_default().push((x, mb));
+        }
+    }
+    sizes
+        .into_iter()
+        .zip(COLOURS.iter().rev())
+        .map(|((compiler_mode, points), colour)| Series {
+            label: format!("{:?}", compiler_mode),
+            colour,
+            points,
+        })
+        .collect()
+}
+
+/// An SVG line chart with versions along the x axis and a y axis starting at 0.
+fn line_chart(title: &str, versions: &[Version], series: &[Series]) -> String {
+    let max = series
+        .iter()
+        .flat_map(|s| s.points.iter().map(|(_, y)| *y))
+        .fold(0.0, f64::max);
+    let max = if max > 0.0 { max * 1.1 } else { 1.0 };
+    let plot_width = CHART_WIDTH - MARGIN_LEFT - MARGIN;
+    let plot_height = CHART_HEIGHT - MARGIN_BOTTOM - MARGIN * 2.0;
+    let x = |i: usize| MARGIN_LEFT + plot_width * i as f64 / (versions.len().max(2) - 1) as f64;
+    let y = |value: f64| MARGIN * 2.0 + plot_height * (1.0 - value / max);
+
+    let mut svg = format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{w}\" height=\"{h}\" viewBox=\"0 0 {w} {h}\">\
+         <text x=\"{}\" y=\"10\" font-weight=\"bold\">{}</text>",
+        MARGIN_LEFT,
+        escape(title),
+        w = CHART_WIDTH,
+        h = CHART_HEIGHT
+    );
+    for step in 0..=4 {
+        let value = max * step as f64 / 4.0;
+        let _ = write!(
+            svg,
+            "<line x1=\"{l}\" x2=\"{r}\" y1=\"{y:.1}\" y2=\"{y:.1}\" stroke=\"#ddd\"/>\
+             <text x=\"{}\" y=\"{:.1}\" text-anchor=\"end\">{}</text>",
+            MARGIN_LEFT - 3.0,
+            y(value) + 3.0,
+            format_tick(value),
+            l = MARGIN_LEFT,
+            r = MARGIN_LEFT + plot_width,
+            y = y(value)
+        );
+    }
+    // Versions without the patch, e.g. "1.34", with enough room between them to be read.
+    let every = versions.len().div_ceil(8).max(1);
+    for (i, version) in versions.iter().enumerate().step_by(every) {
+        let _ = write!(
+            svg,
+            "<text x=\"{:.1}\" y=\"{}\" text-anchor=\"middle\">{}</text>",
+            x(i),
+            CHART_HEIGHT - 5.0,
+            &version.get_string()[..4]
+        );
+    }
+    for s in series.iter().filter(|s| !s.points.is_empty()) {
+        let points: Vec<String> = s
+            .points
+            .iter()
+            .map(|(i, value)| format!("{:.1},{:.1}", x(*i), y(*value)))
+            .collect();
+        let _ = write!(
+            svg,
+            "<polyline fill=\"none\" stroke=\"{}\" stroke-width=\"1.5\" points=\"{}\"><title>{}</title></polyline>",
+            s.colour,
+            points.join(" "),
+            escape(&s.label)
+        );
+    }
+    svg.push_str("</svg>\n");
+    svg
+}
+
+fn format_tick(value: f64) -> String {
+    if value >= 10.0 || value == 0.0 {
+        format!("{:.0}", value)
+    } else {
+        format!("{:.1}", value)
+    }
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_line_chart() {
+        let versions = [Version::V1_50, Version::V1_51, Version::V1_52];
+        let series = [
+            Series {
+                label: "2 <cores>".to_string(),
+                colour: COLOURS[0],
+                points: vec![(0, 10.0), (2, 5.0)],
+            },
+            Series {
+                label: "empty".to_string(),
+                colour: COLOURS[1],
+                points: Vec::new(),
+            },
+        ];
+        let svg = line_chart("Check/Clean (s)", &versions, &series);
+        assert!(svg.starts_with("<svg"));
+        // The y axis goes 10% past the highest point.
+        assert!(svg.contains("points=\"40.0,28.2 292.0,89.1\""));
+        assert!(svg.contains("<title>2 &lt;cores&gt;</title>"));
+        assert_eq!(svg.matches("<polyline").count(), 1);
+        assert!(svg.contains(">1.50<") && svg.contains(">1.52<"));
+    }
+}
@@ -1,9 +1,11 @@
 use std::process::Command;
 
-use anyhow::{anyhow, Context, Result};
+use anyhow::Result;
 use enum_iterator::IntoEnumIterator;
 use serde::{Deserialize, Serialize};
 
+use crate::process::{self, CommandError, CommandKind};
+
 #[derive(
     Debug,
     Copy,
@@ -47,22 +49,23 @@ pub(crate) fn set_profile_minimal() -> Result<()> {
 }
 
 pub(crate) fn set_version(version: Version) -> Result<()> {
+    install(version)?;
     let version = version.get_string();
-    rustup(&["toolchain", "install", version])?;
     rustup(&["default", version])?;
     log::info!("Switched to version {}", version);
     Ok(())
 }
 
+/// Installs the toolchain without making it the default. Cargo can then be pointed at it with
+/// `RUSTUP_TOOLCHAIN`.
+pub(crate) fn install(version: Version) -> Result<()> {
+    rustup(&["toolchain", "install", version.get_string()])
+}
+
 fn rustup(args: &[&str]) -> Result<()> {
-    let output = Command::new("rustup")
-        .args(args)
-        .output()
-        .with_context(|| "failed to execute rustup")?;
+    let output = process::run(Command::new("rustup").args(args), CommandKind::Rustup)?;
     if !output.status.success() {
-        let stderr =
-            std::str::from_utf8(&output.stderr).with_context(|| "failed to decode output")?;
-        return Err(anyhow!("Failed to execute rustup. Stderr - {:?}", stderr));
+        return Err(CommandError::new("rustup", &output.stderr).into());
     }
     Ok(())
 }
@@ -92,6 +95,13 @@ impl Version {
             Version::V1_53 => "1.53.0",
         }
     }
+
+    /// When the version was released, in seconds since the Unix epoch. Stable versions have been
+    /// released every six weeks since 1.34.0, on 2019-04-11.
+    pub(crate) fn released(self) -> u64 {
+        const V1_34_RELEASED: u64 = 1_554_940_800;
+        V1_34_RELEASED + (self as u64 - Version::V1_34 as u64) * 42 * 24 * 60 * 60
+    }
 }
 
 impl std::str::FromStr for Version {
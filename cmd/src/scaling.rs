@@ -0,0 +1,128 @@
+use std::path::Path;
+
+use anyhow::Result;
+use enum_iterator::IntoEnumIterator;
+
+use crate::conditions::{self, NotQuietError};
+use crate::profile::Profile;
+use crate::repo::{Repo, RepoOptions};
+use crate::rustup::{self, Version};
+use crate::{cargo, process, store};
+
+/// Profiles the compile times of every repo on `version` at each job count from 1 up to the
+/// number of cores, so that parallel speedup can be seen without comparing different machines.
+/// The latest version is used if none is given.
+pub(crate) fn scaling(
+    repos_file: &Path,
+    results_dir: &Path,
+    overrides: &RepoOptions,
+    version: Option<Version>,
+    continue_from: Option<&Path>,
+) -> Result<()> {
+    let version = match version {
+        Some(version) => version,
+        None => Version::into_enum_iter()
+            .last()
+            .expect("there is at least one version"),
+    };
+    let repos = store::get_repos(repos_file, overrides)?;
+    let mut profiles = store::get_profiles(results_dir, continue_from)?;
+    conditions::apply()?;
+
+    // After pinning, so that only the CPUs cargo may use are counted.
+    let levels = job_counts(num_cpus::get() as u32);
+    log::info!(
+        "Scaling study on version {} with -j {:?}",
+        version.get_string(),
+        levels
+    );
+    let default_jobs = conditions::jobs();
+    rustup::set_version(version)?;
+
+    for repo in repos {
+        if repo.min_version > version {
+            log::info!(
+                "{} - Skipping, since it needs version {}",
+                &repo.name,
+                repo.min_version.get_string()
+            );
+            continue;
+        }
+        repo.clone_repo()?;
+
+        let profile = profiles
+            .entry(repo.name.clone())
+            .or_insert_with(Profile::new);
+        let result = scale_repo(&repo, profile, version, &levels);
+        conditions::set_jobs(default_jobs);
+
+        store::overwrite_profiles(results_dir, &profiles)?;
+        if result.is_err() {
+            repo.git_reset()?;
+        }
+        result?;
+        if process::cancelled() {
+            repo.git_reset()?;
+            log::warn!("Cancelled. Results of the job counts profiled so far have been saved");
+            break;
+        }
+    }
+    Ok(())
+}
+
+fn scale_repo(repo: &Repo, profile: &mut Profile, version: Version, levels: &[u32]) -> Result<()> {
+    for &jobs in levels {
+        if process::cancelled() {
+            break;
+        }
+        if profile.scaling_measured(version, jobs) {
+            continue;
+        }
+        log::info!("{} - Profiling with -j {}", &repo.name, jobs);
+        conditions::set_jobs(Some(jobs));
+        match cargo::compile_time_profile(repo, &repo.sampling()) {
+            Ok(compile_times) => profile.add_scaling(version, jobs, compile_times),
+            Err(e) => {
+                if process::cancelled() {
+                    break;
+                }
+                if e.is::<NotQuietError>() {
+                    return Err(e);
+                }
+                // A build that fails at one job count fails at all of them.
+                log::error!(
+                    "Failed to profile {} with -j {}. Error - {}",
+                    &repo.name,
+                    jobs,
+                    e
+                );
+                repo.git_reset()?;
+                break;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Powers of two below `num_cores`, then `num_cores` itself.
+fn job_counts(num_cores: u32) -> Vec<u32> {
+    let mut levels: Vec<u32> = std::iter::successors(Some(1u32), |jobs| jobs.checked_mul(2))
+        .take_while(|jobs| *jobs < num_cores)
+        .collect();
+    levels.push(num_cores.max(1));
+    levels
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_job_counts() {
+        assert_eq!(job_counts(0), vec![1]);
+        assert_eq!(job_counts(1), vec![1]);
+        assert_eq!(job_counts(4), vec![1, 2, 4]);
+        assert_eq!(job_counts(6), vec![1, 2, 4, 6]);
+        assert_eq!(job_counts(16), vec![1, 2, 4, 8, 16]);
+    }
+}
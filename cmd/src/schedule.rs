@@ -0,0 +1,561 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use anyhow::Result;
+use enum_iterator::IntoEnumIterator;
+
+use crate::cache::Cache;
+use crate::cargo::{self, CompilerMode, Milliseconds, ProfileMode, Sampling, StopReason};
+use crate::conditions::Conditions;
+use crate::journal::Journal;
+use crate::process;
+use crate::profile::{FailureReason, Profile};
+use crate::progress::{self, Progress};
+use crate::repo::Repo;
+use crate::rustup::{self, Version};
+
+/// One pair of CompilerMode and ProfileMode on one toolchain.
+#[derive(Debug, Copy, Clone, PartialOrd, Ord, PartialEq, Eq)]
+struct Cell {
+    version: Version,
+    compiler_mode: CompilerMode,
+    profile_mode: ProfileMode,
+}
+
+/// SplitMix64 (Steele, Lea and Flood, 2014). A hand-rolled generator rather than a crate, so
+/// that a recorded seed reproduces the same order in every build of arewefastyet.
+pub(crate) struct SplitMix64(u64);
+
+impl SplitMix64 {
+    pub(crate) fn new(seed: u64) -> SplitMix64 {
+        SplitMix64(seed)
+    }
+
+    pub(crate) fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// Fisher-Yates shuffle.
+    pub(crate) fn shuffle<T>(&mut self, items: &mut [T]) {
+        for i in (1..items.len()).rev() {
+            let j = (self.next_u64() % (i as u64 + 1)) as usize;
+            items.swap(i, j);
+        }
+    }
+}
+
+/// A seed for when none was given on the command line.
+pub(crate) fn random_seed() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |d| d.as_nanos() as u64)
+}
+
+/// Samples taken so far by `profile_interleaved`.
+#[derive(Default)]
+struct Taken {
+    samples: BTreeMap<Cell, Vec<Milliseconds>>,
+    conditions: BTreeMap<Cell, Conditions>,
+    failed: BTreeSet<Version>,
+    /// A cell is done once it has `times` samples, or its version has failed.
+    done: BTreeSet<Cell>,
+}
+
+/// Profiles all `versions` of the repo at once. Each round takes one sample of every cell in a
+/// random order, so that a noisy period on the machine is spread across versions instead of
+/// biasing one. After `times` rounds, only cells whose samples are still noisy get more. If
+/// sampling is stopped by Ctrl-C or an error, every cell with `times` samples is still kept.
+pub(crate) fn profile_interleaved(
+    repo: &Repo,
+    profile: &mut Profile,
+    versions: Vec<Version>,
+    seed: u64,
+    journal: &Journal,
+    progress: &mut Progress,
+) -> Result<()> {
+    log::info!(
+        "{} - Interleaving {} versions with seed {}",
+        &repo.name,
+        versions.len(),
+        seed
+    );
+
+    let mut cells = Vec::new();
+    for version in versions {
+        if process::cancelled() {
+            return Ok(());
+        }
+        progress.start_version(version);
+        if let Err(e) = rustup::install(version) {
+            log::error!(
+                "Failed to install version {}. Error - {}",
+                version.get_string(),
+                e
+            );
+            profile.add_failure(version, FailureReason::toolchain_install(&e));
+            journal.record(&repo.name, profile, version)?;
+            progress.done(progress::cells_per_version());
+            continue;
+        }
+        let built = cargo::fetch(repo, Some(version))
+            .and_then(|_| cargo::cargo_check_with_toolchain(repo, version));
+        if let Err(e) = built {
+            let reason = match FailureReason::build_error(&e) {
+                Some(reason) => reason,
+                None => return Err(e),
+            };
+            log::error!(
+                "Failed to build {} on version {}. Error - {}",
+                &repo.name,
+                version.get_string(),
+                e
+            );
+            profile.add_failure(version, reason);
+            journal.record(&repo.name, profile, version)?;
+            progress.done(progress::cells_per_version());
+            continue;
+        }
+        for compiler_mode in CompilerMode::into_enum_iter() {
+            for profile_mode in ProfileMode::into_enum_iter() {
+                cells.push(Cell {
+                    version,
+                    compiler_mode,
+                    profile_mode,
+                });
+            }
+        }
+    }
+
+    let mut taken = Taken::default();
+    let result = take_samples(repo, profile, &cells, seed, journal, progress, &mut taken);
+    commit(repo, profile, &cells, seed, journal, &taken);
+    result
+}
+
+/// Takes the samples of `cells` in shuffled rounds. Returns early on Ctrl-C, and on an error
+/// that isn't the fault of the version being built.
+fn take_samples(
+    repo: &Repo,
+    profile: &mut Profile,
+    cells: &[Cell],
+    seed: u64,
+    journal: &Journal,
+    progress: &mut Progress,
+    taken: &mut Taken,
+) -> Result<()> {
+    let sampling = repo.sampling();
+    let mut rng = SplitMix64::new(seed);
+    let cache = Cache::new(repo, sampling.cache)?;
+    let no_cache = Cache::unmanaged();
+    // Warm-up rounds are shuffled like the others, and their samples thrown away.
+    for round in 0..sampling.warmup + sampling.max_times {
+        let warmup = round < sampling.warmup;
+        let round = round.saturating_sub(sampling.warmup);
+        let pending = taken.pending(cells, &sampling, round, warmup, &mut rng);
+        if pending.is_empty() {
+            break;
+        }
+
+        for cell in pending {
+            if taken.failed.contains(&cell.version) {
+                continue;
+            }
+            let cell_cache = if warmup { &no_cache } else { &cache };
+            progress.start_version(cell.version);
+            match cargo::measure(
+                repo,
+                cell.version,
+                cell.compiler_mode,
+                cell.profile_mode,
+                cell_cache,
+            ) {
+                Ok(_) if warmup => {}
+                Ok((time, sample_conditions)) => {
+                    if taken.add(cell, time, sample_conditions, &sampling) {
+                        if taken.done.insert(cell) {
+                            progress.done(1);
+                        }
+                        let version_profile = taken.profile(repo, cell.version, seed);
+                        journal.record(&repo.name, &version_profile, cell.version)?;
+                    }
+                }
+                Err(e) => {
+                    if process::cancelled() {
+                        return Ok(());
+                    }
+                    let reason = match FailureReason::build_error(&e) {
+                        Some(reason) => reason,
+                        None => return Err(e),
+                    };
+                    log::error!(
+                        "Failed to profile times {} on version {}. Error - {}",
+                        &repo.name,
+                        cell.version.get_string(),
+                        e
+                    );
+                    profile.add_failure(cell.version, reason);
+                    journal.record(&repo.name, profile, cell.version)?;
+                    taken.failed.insert(cell.version);
+                    let abandoned = cells
+                        .iter()
+                        .filter(|c| c.version == cell.version && taken.done.insert(**c))
+                        .count();
+                    progress.done(abandoned);
+                    repo.git_reset()?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Adds every cell with `times` samples to the profile, and the output sizes of each version
+/// with all of its cells. Errors are only logged, so that none of the samples are lost.
+fn commit(
+    repo: &Repo,
+    profile: &mut Profile,
+    cells: &[Cell],
+    seed: u64,
+    journal: &Journal,
+    taken: &Taken,
+) {
+    let versions: BTreeSet<Version> = taken
+        .samples
+        .keys()
+        .map(|cell| cell.version)
+        .filter(|version| !taken.failed.contains(version))
+        .collect();
+    for version in versions {
+        let compile_times = taken.compile_times(&repo.sampling(), version, seed);
+        if compile_times.is_empty() {
+            continue;
+        }
+        let complete = compile_times.len() == cells.iter().filter(|c| c.version == version).count();
+        profile.add_compile_times(version, compile_times);
+        profile.set_feature_set(version, repo.feature_set());
+
+        if complete && !process::cancelled() {
+            match rustup::set_version(version).and_then(|_| cargo::size_profile(repo)) {
+                Ok((debug_sizes, release_sizes)) => {
+                    profile.add_output_sizes(version, debug_sizes, release_sizes)
+                }
+                Err(e) => {
+                    log::error!(
+                        "Failed to profile sizes {} on version {}. Error - {}",
+                        &repo.name,
+                        version.get_string(),
+                        e
+                    );
+                }
+            };
+        }
+        if let Err(e) = journal.record(&repo.name, profile, version) {
+            log::error!(
+                "Failed to record {} on version {} in the journal. Error - {}",
+                &repo.name,
+                version.get_string(),
+                e
+            );
+        }
+    }
+}
+
+impl Taken {
+    /// The cells to sample in `round`, counted from the first timed one, in a shuffled order.
+    /// Every cell of a version that hasn't failed is sampled in warm-up rounds and the first
+    /// `times`, and after that only those whose samples are still noisy.
+    fn pending(
+        &self,
+        cells: &[Cell],
+        sampling: &Sampling,
+        round: u32,
+        warmup: bool,
+        rng: &mut SplitMix64,
+    ) -> Vec<Cell> {
+        let mut pending: Vec<Cell> = cells
+            .iter()
+            .filter(|cell| !self.failed.contains(&cell.version))
+            .filter(|cell| {
+                warmup
+                    || round < sampling.times
+                    || sampling.is_noisy(self.samples.get(cell).map_or(&[], Vec::as_slice))
+            })
+            .copied()
+            .collect();
+        rng.shuffle(&mut pending);
+        pending
+    }
+
+    /// Adds a sample of `cell`. Returns whether the cell has `times` samples.
+    fn add(
+        &mut self,
+        cell: Cell,
+        time: Milliseconds,
+        conditions: Option<Conditions>,
+        sampling: &Sampling,
+    ) -> bool {
+        let times = self.samples.entry(cell).or_default();
+        times.push(time);
+        let enough = times.len() >= sampling.times as usize;
+        if let Some(merged) = Conditions::merge(self.conditions.remove(&cell), conditions) {
+            self.conditions.insert(cell, merged);
+        }
+        enough
+    }
+
+    /// The samples of every cell of `version` that has `times` of them.
+    fn compile_times(
+        &self,
+        sampling: &Sampling,
+        version: Version,
+        seed: u64,
+    ) -> BTreeMap<(CompilerMode, ProfileMode), cargo::Samples> {
+        self.samples
+            .iter()
+            .filter(|(cell, times)| {
+                cell.version == version && times.len() >= sampling.times as usize
+            })
+            .map(|(cell, times)| {
+                let stop_reason = if !sampling.is_noisy(times) {
+                    if times.len() > sampling.times as usize {
+                        StopReason::Converged
+                    } else {
+                        StopReason::Times
+                    }
+                } else if times.len() >= sampling.max_times as usize {
+                    StopReason::MaxSamples
+                } else {
+                    StopReason::Stopped
+                };
+                let mut info = sampling.info(times, stop_reason);
+                info.seed = Some(seed);
+                info.conditions = self.conditions.get(cell).cloned();
+                (
+                    (cell.compiler_mode, cell.profile_mode),
+                    cargo::Samples {
+                        times: times.clone(),
+                        info,
+                    },
+                )
+            })
+            .collect()
+    }
+
+    /// What has been taken of `version` so far, for the journal.
+    fn profile(&self, repo: &Repo, version: Version, seed: u64) -> Profile {
+        let mut profile = Profile::new();
+        profile.add_compile_times(version, self.compile_times(&repo.sampling(), version, seed));
+        profile.set_feature_set(version, repo.feature_set());
+        profile
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cache::CacheMode;
+
+    fn sampling(warmup: u32) -> Sampling {
+        Sampling {
+            times: 2,
+            max_times: 4,
+            cv_threshold: 0.05,
+            outlier_fence: 3.0,
+            warmup,
+            cache: CacheMode::Unmanaged,
+        }
+    }
+
+    fn cells(versions: &[Version]) -> Vec<Cell> {
+        let mut cells = Vec::new();
+        for &version in versions {
+            for compiler_mode in CompilerMode::into_enum_iter() {
+                for profile_mode in ProfileMode::into_enum_iter() {
+                    cells.push(Cell {
+                        version,
+                        compiler_mode,
+                        profile_mode,
+                    });
+                }
+            }
+        }
+        cells
+    }
+
+    /// Takes samples like `take_samples`, with `ms(cell, n)` as the time of the nth sample of a
+    /// cell, until `stop_after` samples have been taken. Returns the order of every round.
+    fn run(
+        cells: &[Cell],
+        sampling: &Sampling,
+        seed: u64,
+        stop_after: usize,
+        ms: impl Fn(Cell, usize) -> u64,
+    ) -> (Taken, Vec<Vec<Cell>>) {
+        let mut taken = Taken::default();
+        let mut rounds = Vec::new();
+        let mut rng = SplitMix64::new(seed);
+        let mut taken_so_far = 0;
+        for round in 0..sampling.warmup + sampling.max_times {
+            let warmup = round < sampling.warmup;
+            let round = round.saturating_sub(sampling.warmup);
+            let pending = taken.pending(cells, sampling, round, warmup, &mut rng);
+            if pending.is_empty() {
+                break;
+            }
+            rounds.push(pending.clone());
+            if warmup {
+                continue;
+            }
+            for cell in pending {
+                if taken_so_far == stop_after {
+                    return (taken, rounds);
+                }
+                let n = taken.samples.get(&cell).map_or(0, Vec::len);
+                let time = serde_json::from_value(ms(cell, n).into()).unwrap();
+                taken.add(cell, time, None, sampling);
+                taken_so_far += 1;
+            }
+        }
+        (taken, rounds)
+    }
+
+    fn stop_reasons(
+        taken: &Taken,
+        sampling: &Sampling,
+        version: Version,
+    ) -> BTreeMap<(CompilerMode, ProfileMode), StopReason> {
+        taken
+            .compile_times(sampling, version, 7)
+            .into_iter()
+            .map(|(key, samples)| {
+                assert_eq!(samples.info.seed, Some(7));
+                (key, samples.info.stop_reason)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_interleaved_rounds() {
+        let sampling = sampling(1);
+        let cells = cells(&[Version::V1_50, Version::V1_51]);
+        let noisy = Cell {
+            version: Version::V1_50,
+            compiler_mode: CompilerMode::Check,
+            profile_mode: ProfileMode::Clean,
+        };
+        let settling = Cell {
+            profile_mode: ProfileMode::Incremental,
+            ..noisy
+        };
+        let (taken, rounds) = run(&cells, &sampling, 7, usize::MAX, |cell, n| {
+            if cell == noisy {
+                [1000, 1500][n % 2]
+            } else if cell == settling {
+                [1000, 1100, 1050, 1050][n]
+            } else {
+                1000
+            }
+        });
+
+        // A warm-up round and two of every cell, both versions in one shuffled order.
+        for round in &rounds[..3] {
+            let mut sorted = round.clone();
+            sorted.sort();
+            assert_eq!(sorted, cells);
+            assert!(
+                round
+                    .windows(2)
+                    .filter(|w| w[0].version != w[1].version)
+                    .count()
+                    > 1
+            );
+        }
+        assert_ne!(rounds[1], rounds[2]);
+        // Then only noisy cells, up to max_times.
+        let mut third = rounds[3].clone();
+        third.sort();
+        assert_eq!(third, vec![noisy, settling]);
+        assert_eq!(rounds[4], vec![noisy]);
+        assert_eq!(rounds.len(), 5);
+
+        let mut expected: BTreeMap<_, _> = cells[..progress::cells_per_version()]
+            .iter()
+            .map(|cell| ((cell.compiler_mode, cell.profile_mode), StopReason::Times))
+            .collect();
+        assert_eq!(stop_reasons(&taken, &sampling, Version::V1_51), expected);
+        expected.insert(
+            (noisy.compiler_mode, noisy.profile_mode),
+            StopReason::MaxSamples,
+        );
+        expected.insert(
+            (settling.compiler_mode, settling.profile_mode),
+            StopReason::Converged,
+        );
+        assert_eq!(stop_reasons(&taken, &sampling, Version::V1_50), expected);
+        assert_eq!(taken.samples[&noisy].len(), 4);
+        assert_eq!(taken.samples[&settling].len(), 3);
+    }
+
+    #[test]
+    fn test_stopped_run() {
+        let sampling = sampling(0);
+        let cells = cells(&[Version::V1_50, Version::V1_51]);
+        // Stopped halfway through the second round.
+        let stop_after = cells.len() + cells.len() / 2;
+        let (taken, _) = run(&cells, &sampling, 7, stop_after, |cell, n| {
+            if cell.version == Version::V1_50 {
+                [1000, 1500][n % 2]
+            } else {
+                1000
+            }
+        });
+
+        let mut kept = 0;
+        for version in [Version::V1_50, Version::V1_51] {
+            let reasons = stop_reasons(&taken, &sampling, version);
+            for cell in cells.iter().filter(|cell| cell.version == version) {
+                let reason = reasons.get(&(cell.compiler_mode, cell.profile_mode));
+                // Cells with fewer than `times` samples are left out.
+                match taken.samples[cell].len() {
+                    1 => assert_eq!(reason, None),
+                    2 if version == Version::V1_50 => {
+                        assert_eq!(reason, Some(&StopReason::Stopped))
+                    }
+                    2 => assert_eq!(reason, Some(&StopReason::Times)),
+                    n => panic!("{} samples of {:?}", n, cell),
+                }
+            }
+            kept += reasons.len();
+        }
+        assert_eq!(kept, cells.len() / 2);
+    }
+
+    #[test]
+    fn test_split_mix_64() {
+        // First outputs for seed 0, from the reference implementation.
+        let mut rng = SplitMix64::new(0);
+        assert_eq!(rng.next_u64(), 0xE220_A839_7B1D_CDAF);
+        assert_eq!(rng.next_u64(), 0x6E78_9E6A_A1B9_65F4);
+    }
+
+    #[test]
+    fn test_shuffle_is_reproducible() {
+        let items: Vec<u32> = (0..27).collect();
+
+        let mut first = items.clone();
+        SplitMix64::new(42).shuffle(&mut first);
+        let mut second = items.clone();
+        SplitMix64::new(42).shuffle(&mut second);
+        let mut other = items.clone();
+        SplitMix64::new(43).shuffle(&mut other);
+
+        assert_eq!(first, second);
+        assert_ne!(first, other);
+        assert_ne!(first, items);
+        first.sort_unstable();
+        assert_eq!(first, items);
+    }
+}
@@ -0,0 +1,585 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use anyhow::{anyhow, Result};
+use enum_iterator::IntoEnumIterator;
+use serde::Serialize;
+use serde_json::{json, Value};
+
+use crate::cargo::{CompilerMode, ProfileMode};
+use crate::compare;
+use crate::metrics;
+use crate::profile::Profile;
+use crate::progress;
+use crate::repo::Repo;
+use crate::rustup::Version;
+use crate::stats::Summary;
+use crate::store;
+
+/// Everything the API serves, read from the repos file and the results directory.
+struct Data {
+    repos: Vec<Repo>,
+    /// Fewest cores first.
+    machines: Vec<Machine>,
+}
+
+struct Machine {
+    id: String,
+    label: String,
+    num_cores: usize,
+    profiles: BTreeMap<String, Profile>,
+}
+
+/// The data, and the modification times of the files it was read from.
+struct Loaded {
+    files: Vec<(PathBuf, Option<SystemTime>)>,
+    data: Data,
+}
+
+/// A JSON response, or a JSON error with its message.
+type Response = (u16, Value);
+
+/// Serves the results in `results_dir` over HTTP on `address` as JSON, as OpenMetrics on
+/// /metrics and as a Grafana JSON datasource, re-reading them when the repos file,
+/// `machines.json` or any results file changes.
+pub(crate) fn serve(repos_file: &Path, results_dir: &Path, address: &str) -> Result<()> {
+    let server = tiny_http::Server::http(address)
+        .map_err(|e| anyhow!("Failed to listen on {} - {}", address, e))?;
+    let mut loaded = Loaded::new(repos_file, results_dir)?;
+    log::info!("Serving {:?} on http://{}/api/", results_dir, address);
+
+    for mut request in server.incoming_requests() {
+        // A results file that's being written may not parse yet. Until it does, keep serving
+        // what was read before.
+        if let Err(e) = loaded.reload_if_changed(repos_file, results_dir) {
+            log::warn!(
+                "Failed to reload results, serving the previous ones - {:#}",
+                e
+            );
+        }
+        let path = request.url().split('?').next().unwrap_or("").to_string();
+        let (status, content_type, body) = match request.method() {
+            tiny_http::Method::Get if path == "/metrics" => {
+                match openmetrics(&loaded.data, results_dir) {
+                    Ok(text) => (200, OPENMETRICS, text),
+                    Err(e) => json_response(error(500, &format!("{:#}", e))),
+                }
+            }
+            tiny_http::Method::Get => json_response(route(&loaded.data, request.url())),
+            tiny_http::Method::Post => {
+                let mut body = String::new();
+                match request.as_reader().read_to_string(&mut body) {
+                    Ok(_) => json_response(grafana(&loaded.data, &path, &body)),
+                    Err(e) => json_response(error(400, &format!("Failed to read body - {}", e))),
+                }
+            }
+            _ => json_response(error(405, "Only GET and POST are supported")),
+        };
+        log::debug!("{} {} - {}", request.method(), request.url(), status);
+        let response = tiny_http::Response::from_string(body)
+            .with_status_code(status)
+            .with_header(header("Content-Type", content_type))
+            .with_header(header("Access-Control-Allow-Origin", "*"));
+        if let Err(e) = request.respond(response) {
+            log::warn!("Failed to respond - {}", e);
+        }
+    }
+    Ok(())
+}
+
+const JSON: &str = "application/json";
+const OPENMETRICS: &str = "application/openmetrics-text; version=1.0.0; charset=utf-8";
+
+fn json_response((status, value): Response) -> (u16, &'static str, String) {
+    (status, JSON, value.to_string())
+}
+
+/// The metrics of the loaded results, and of runs in progress, which are read on every request
+/// since they change far more often.
+fn openmetrics(data: &Data, results_dir: &Path) -> Result<String> {
+    let machines: Vec<metrics::Machine> = data
+        .machines
+        .iter()
+        .map(|m| metrics::Machine {
+            id: &m.id,
+            label: &m.label,
+            num_cores: m.num_cores,
+            profiles: &m.profiles,
+        })
+        .collect();
+    Ok(metrics::render(
+        &machines,
+        &progress::read_progress(results_dir)?,
+    ))
+}
+
+fn header(name: &str, value: &str) -> tiny_http::Header {
+    tiny_http::Header::from_bytes(name, value).expect("valid header")
+}
+
+impl Loaded {
+    fn new(repos_file: &Path, results_dir: &Path) -> Result<Loaded> {
+        let files = watched_files(repos_file, results_dir)?;
+        let data = Data::load(repos_file, results_dir)?;
+        Ok(Loaded { files, data })
+    }
+
+    fn reload_if_changed(&mut self, repos_file: &Path, results_dir: &Path) -> Result<()> {
+        let files = watched_files(repos_file, results_dir)?;
+        if files != self.files {
+            log::info!("Results changed, reloading");
+            self.data = Data::load(repos_file, results_dir)?;
+            self.files = files;
+        }
+        Ok(())
+    }
+}
+
+/// The repos file, `machines.json` and every results file, with when each was last modified,
+/// or None if it doesn't exist.
+fn watched_files(
+    repos_file: &Path,
+    results_dir: &Path,
+) -> Result<Vec<(PathBuf, Option<SystemTime>)>> {
+    let mut paths = vec![
+        repos_file.to_path_buf(),
+        results_dir.join(store::MACHINES_FILE),
+    ];
+    paths.extend(store::get_result_files(results_dir)?);
+    Ok(paths
+        .into_iter()
+        .map(|path| {
+            let modified = std::fs::metadata(&path).and_then(|m| m.modified()).ok();
+            (path, modified)
+        })
+        .collect())
+}
+
+impl Data {
+    fn load(repos_file: &Path, results_dir: &Path) -> Result<Data> {
+        let repos = store::get_repos(repos_file, &Default::default())?;
+        let mut machines = Vec::new();
+        for path in store::get_result_files(results_dir)? {
+            let (system_info, profiles) = store::read_results(&path)?;
+            machines.push(Machine {
+                id: store::machine_id(&system_info),
+                label: compare::machine_label(&path, &system_info)?,
+                num_cores: system_info.num_cores,
+                profiles,
+            });
+        }
+        machines.sort_by(|a, b| (a.num_cores, &a.label).cmp(&(b.num_cores, &b.label)));
+        Ok(Data { repos, machines })
+    }
+}
+
+/// Answers a GET of `url`, a path under /api/ and its query string, or of / to check that
+/// the server is up.
+fn route(data: &Data, url: &str) -> Response {
+    let (path, query) = match url.split_once('?') {
+        Some((path, query)) => (path, query_params(query)),
+        None => (url, BTreeMap::new()),
+    };
+    let result = match path.trim_end_matches('/') {
+        "" => Ok(json!({ "status": "ok" })),
+        "/api/machines" => Ok(machines(data)),
+        "/api/repos" => Ok(repos(data)),
+        "/api/toolchains" => Ok(toolchains(data)),
+        "/api/series" => series(data, &query),
+        "/api/samples" => samples(data, &query),
+        _ => return error(404, &format!("No such endpoint - {}", path)),
+    };
+    match result {
+        Ok(value) => (200, value),
+        Err(e) => error(400, &e.to_string()),
+    }
+}
+
+fn error(status: u16, message: &str) -> Response {
+    (status, json!({ "error": message }))
+}
+
+fn machines(data: &Data) -> Value {
+    data.machines
+        .iter()
+        .map(|m| json!({ "id": m.id, "label": m.label, "num_cores": m.num_cores }))
+        .collect()
+}
+
+fn repos(data: &Data) -> Value {
+    data.repos
+        .iter()
+        .map(|repo| json!({ "name": repo.name, "url": repo.url(), "commit": repo.commit() }))
+        .collect()
+}
+
+/// Versions with compile times on any machine, oldest first.
+fn toolchains(data: &Data) -> Value {
+    Version::into_enum_iter()
+        .filter(|v| {
+            data.machines
+                .iter()
+                .any(|m| m.profiles.values().any(|p| p.has_compile_times(*v)))
+        })
+        .map(|v| v.get_string())
+        .collect()
+}
+
+/// A summary of the compile times of each version of one repo and pair of modes.
+#[derive(Debug, Serialize)]
+struct Point {
+    version: &'static str,
+    #[serde(flatten)]
+    summary: Summary,
+}
+
+/// `?repo=&compiler_mode=&profile_mode=[&machine=]`, one series per machine.
+fn series(data: &Data, query: &BTreeMap<String, String>) -> Result<Value> {
+    Ok(summaries(data, query)?
+        .into_iter()
+        .map(|(machine, summaries)| {
+            let points: Vec<Point> = summaries
+                .into_iter()
+                .map(|(version, summary)| Point {
+                    version: version.get_string(),
+                    summary,
+                })
+                .collect();
+            json!({ "machine": machine.id, "label": machine.label, "points": points })
+        })
+        .collect())
+}
+
+/// The summary of the compile times of each version, for each machine with samples.
+type Summaries<'a> = Vec<(&'a Machine, Vec<(Version, Summary)>)>;
+
+/// The summaries for the query parameters of `series`.
+fn summaries<'a>(data: &'a Data, query: &BTreeMap<String, String>) -> Result<Summaries<'a>> {
+    let (repo, compiler_mode, profile_mode) = series_params(query)?;
+    let mut summaries = Vec::new();
+    for machine in selected_machines(data, query)? {
+        let profile = match machine.profiles.get(repo) {
+            Some(profile) => profile,
+            None => continue,
+        };
+        let versions = Version::into_enum_iter()
+            .filter_map(|version| {
+                let times = profile.compile_times(version, compiler_mode, profile_mode)?;
+                let times: Vec<f64> = times.iter().map(|ms| ms.as_f64()).collect();
+                Some((version, Summary::new(&times)))
+            })
+            .collect();
+        summaries.push((machine, versions));
+    }
+    Ok(summaries)
+}
+
+/// Answers a POST of the Grafana JSON datasource, to /search or /query, with a JSON `body`.
+fn grafana(data: &Data, path: &str, body: &str) -> Response {
+    let body: Value = if body.trim().is_empty() {
+        Value::Null
+    } else {
+        match serde_json::from_str(body) {
+            Ok(body) => body,
+            Err(e) => return error(400, &format!("Failed to parse body - {}", e)),
+        }
+    };
+    let result = match path.trim_end_matches('/') {
+        "/search" => Ok(search(data, body["target"].as_str().unwrap_or(""))),
+        "/query" => query(data, &body),
+        _ => return error(404, &format!("No such endpoint - {}", path)),
+    };
+    match result {
+        Ok(value) => (200, value),
+        Err(e) => error(400, &e.to_string()),
+    }
+}
+
+/// Every repo and pair of modes with compile times, as targets like "syn Check/Clean", that
+/// contain `filter`.
+fn search(data: &Data, filter: &str) -> Value {
+    let mut targets = BTreeSet::new();
+    for machine in &data.machines {
+        for (repo, profile) in &machine.profiles {
+            for compiler_mode in CompilerMode::into_enum_iter() {
+                for profile_mode in ProfileMode::into_enum_iter() {
+                    if Version::into_enum_iter().any(|v| {
+                        profile
+                            .compile_times(v, compiler_mode, profile_mode)
+                            .is_some()
+                    }) {
+                        targets.insert(format!("{} {:?}/{:?}", repo, compiler_mode, profile_mode));
+                    }
+                }
+            }
+        }
+    }
+    targets
+        .into_iter()
+        .filter(|target| target.contains(filter))
+        .collect()
+}
+
+/// The median compile times of each target, one time series per machine, with each version at
+/// its release date. The queried time range is left to Grafana.
+fn query(data: &Data, body: &Value) -> Result<Value> {
+    let targets = body["targets"]
+        .as_array()
+        .ok_or_else(|| anyhow!("Missing targets"))?;
+    let mut response = Vec::new();
+    for target in targets {
+        let target = match target["target"].as_str() {
+            Some(target) if !target.is_empty() => target,
+            _ => continue,
+        };
+        let (repo, modes) = target
+            .rsplit_once(' ')
+            .ok_or_else(|| anyhow!("Expected a target like \"syn Check/Clean\" - {}", target))?;
+        let (compiler_mode, profile_mode) = modes
+            .split_once('/')
+            .ok_or_else(|| anyhow!("Expected a target like \"syn Check/Clean\" - {}", target))?;
+        let params: BTreeMap<String, String> = [
+            ("repo", repo),
+            ("compiler_mode", compiler_mode),
+            ("profile_mode", profile_mode),
+        ]
+        .iter()
+        .map(|(name, value)| (name.to_string(), value.to_string()))
+        .collect();
+        for (machine, summaries) in summaries(data, &params)? {
+            let datapoints: Vec<Value> = summaries
+                .into_iter()
+                .map(|(version, summary)| json!([summary.median, version.released() * 1000]))
+                .collect();
+            response.push(json!({
+                "target": format!("{} - {}", target, machine.label),
+                "datapoints": datapoints,
+            }));
+        }
+    }
+    Ok(Value::Array(response))
+}
+
+/// `?repo=&compiler_mode=&profile_mode=[&version=][&machine=]`, every sample in milliseconds.
+fn samples(data: &Data, query: &BTreeMap<String, String>) -> Result<Value> {
+    let (repo, compiler_mode, profile_mode) = series_params(query)?;
+    let version: Option<Version> = query
+        .get("version")
+        .map(|v| v.parse().map_err(|e| anyhow!("version - {}", e)))
+        .transpose()?;
+    let mut samples = Vec::new();
+    for machine in selected_machines(data, query)? {
+        let profile = match machine.profiles.get(repo) {
+            Some(profile) => profile,
+            None => continue,
+        };
+        for v in Version::into_enum_iter().filter(|v| version.is_none_or(|version| version == *v)) {
+            if let Some(times) = profile.compile_times(v, compiler_mode, profile_mode) {
+                let times: Vec<u64> = times.iter().map(|ms| ms.as_u64()).collect();
+                samples.push(json!({
+                    "machine": machine.id,
+                    "version": v.get_string(),
+                    "samples": times,
+                }));
+            }
+        }
+    }
+    Ok(Value::Array(samples))
+}
+
+fn series_params(query: &BTreeMap<String, String>) -> Result<(&str, CompilerMode, ProfileMode)> {
+    let param = |name: &str| {
+        query
+            .get(name)
+            .map(String::as_str)
+            .ok_or_else(|| anyhow!("Missing query parameter - {}", name))
+    };
+    let repo = param("repo")?;
+    let compiler_mode = param("compiler_mode")?
+        .parse()
+        .map_err(|e| anyhow!("compiler_mode - {}", e))?;
+    let profile_mode = param("profile_mode")?
+        .parse()
+        .map_err(|e| anyhow!("profile_mode - {}", e))?;
+    Ok((repo, compiler_mode, profile_mode))
+}
+
+/// Every machine, or the one whose ID or label is `?machine=`.
+fn selected_machines<'a>(
+    data: &'a Data,
+    query: &BTreeMap<String, String>,
+) -> Result<Vec<&'a Machine>> {
+    match query.get("machine") {
+        Some(machine) => {
+            let selected: Vec<&Machine> = data
+                .machines
+                .iter()
+                .filter(|m| &m.id == machine || &m.label == machine)
+                .collect();
+            if selected.is_empty() {
+                return Err(anyhow!("No such machine - {}", machine));
+            }
+            Ok(selected)
+        }
+        None => Ok(data.machines.iter().collect()),
+    }
+}
+
+/// Splits a query string into its decoded parameters. Later ones replace earlier ones.
+fn query_params(query: &str) -> BTreeMap<String, String> {
+    query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+            (decode(name), decode(value))
+        })
+        .collect()
+}
+
+/// Percent-decodes `s`, with `+` as a space. Invalid escapes are kept as they are.
+fn decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'+' => decoded.push(b' '),
+            b'%' if i + 2 < bytes.len() => {
+                let byte = std::str::from_utf8(&bytes[i + 1..i + 3])
+                    .ok()
+                    .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+                match byte {
+                    Some(byte) => {
+                        decoded.push(byte);
+                        i += 2;
+                    }
+                    None => decoded.push(b'%'),
+                }
+            }
+            byte => decoded.push(byte),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use anyhow::Result;
+
+    fn data() -> Result<Data> {
+        let profile: Profile = serde_json::from_str(
+            r#"{
+                "compile_times": {
+                    "1.50.0,Check,Clean": [1000, 1010, 1011],
+                    "1.51.0,Check,Clean": [900, 910],
+                    "1.51.0,Debug,Clean": [2000]
+                },
+                "output_sizes": {}
+            }"#,
+        )?;
+        Ok(Data {
+            repos: Vec::new(),
+            machines: vec![Machine {
+                id: "3ca971dfaacb948d".to_string(),
+                label: "ci-2".to_string(),
+                num_cores: 2,
+                profiles: std::iter::once(("syn".to_string(), profile)).collect(),
+            }],
+        })
+    }
+
+    #[test]
+    fn test_route() -> Result<()> {
+        let data = data()?;
+        assert_eq!(
+            route(&data, "/api/toolchains"),
+            (200, json!(["1.50.0", "1.51.0"]))
+        );
+
+        let (status, series) = route(
+            &data,
+            "/api/series?repo=syn&compiler_mode=Check&profile_mode=Clean",
+        );
+        assert_eq!(status, 200);
+        assert_eq!(series[0]["label"], "ci-2");
+        assert_eq!(series[0]["points"][1]["version"], "1.51.0");
+        assert_eq!(series[0]["points"][1]["median"], 905.0);
+        assert_eq!(series[0]["points"][1]["samples"], 2);
+
+        assert_eq!(
+            route(
+                &data,
+                "/api/samples?repo=syn&compiler_mode=Check&profile_mode=Clean&version=1.51.0&machine=ci-2",
+            ),
+            (
+                200,
+                json!([{ "machine": "3ca971dfaacb948d", "version": "1.51.0", "samples": [900, 910] }])
+            )
+        );
+
+        assert_eq!(route(&data, "/api/series?repo=syn").0, 400);
+        assert_eq!(
+            route(
+                &data,
+                "/api/samples?repo=syn&compiler_mode=Check&profile_mode=Clean&machine=x"
+            )
+            .0,
+            400
+        );
+        assert_eq!(route(&data, "/api/nothing").0, 404);
+        assert_eq!(route(&data, "/").0, 200);
+        Ok(())
+    }
+
+    #[test]
+    fn test_grafana() -> Result<()> {
+        let data = data()?;
+        assert_eq!(
+            grafana(&data, "/search", r#"{ "target": "" }"#),
+            (200, json!(["syn Check/Clean", "syn Debug/Clean"]))
+        );
+        assert_eq!(
+            grafana(&data, "/search", r#"{ "target": "Debug" }"#),
+            (200, json!(["syn Debug/Clean"]))
+        );
+
+        let (status, response) = grafana(
+            &data,
+            "/query",
+            r#"{
+                "range": { "from": "2020-01-01T00:00:00.000Z", "to": "2021-12-31T00:00:00.000Z" },
+                "targets": [{ "target": "syn Check/Clean", "refId": "A", "type": "timeserie" }]
+            }"#,
+        );
+        assert_eq!(status, 200);
+        assert_eq!(
+            response,
+            json!([{
+                "target": "syn Check/Clean - ci-2",
+                // 1.50.0 was released on 2021-02-11 and 1.51.0 on 2021-03-25.
+                "datapoints": [[1010.0, 1_613_001_600_000u64], [905.0, 1_616_630_400_000u64]],
+            }])
+        );
+
+        assert_eq!(
+            grafana(&data, "/query", r#"{ "targets": [{ "target": "syn" }] }"#).0,
+            400
+        );
+        assert_eq!(grafana(&data, "/query", "not json").0, 400);
+        assert_eq!(grafana(&data, "/annotations", "{}").0, 404);
+        Ok(())
+    }
+
+    #[test]
+    fn test_query_params() {
+        let params = query_params("repo=hello%20world&machine=ci+2&empty&bad=%zz");
+        assert_eq!(params["repo"], "hello world");
+        assert_eq!(params["machine"], "ci 2");
+        assert_eq!(params["empty"], "");
+        assert_eq!(params["bad"], "%zz");
+    }
+}
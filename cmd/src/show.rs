@@ -0,0 +1,389 @@
+use std::collections::BTreeMap;
+use std::io::IsTerminal;
+use std::path::Path;
+
+use anyhow::{anyhow, Result};
+use enum_iterator::IntoEnumIterator;
+
+use crate::cargo::{CompilerMode, ProfileMode};
+use crate::profile::Profile;
+use crate::rustup::Version;
+use crate::{stats, store};
+
+const SPARKS: &[char] = &['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+/// Rows of an ASCII line chart.
+const CHART_HEIGHT: usize = 10;
+
+/// Width of the label and the y axis in front of each sparkline or chart.
+const LABEL_WIDTH: usize = 26;
+const Y_AXIS_WIDTH: usize = 9;
+
+/// Width of the times and change after each sparkline, `"  {:>7.2}s → {:>7.2}s {:+6.1}%"`.
+const STATS_WIDTH: usize = 29;
+
+/// How to order the rows of the size table.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub(crate) enum SortBy {
+    Version,
+    /// Largest first.
+    Size(CompilerMode),
+}
+
+/// Whether to colour the output, and the number of columns to fit it in.
+#[derive(Debug, Copy, Clone)]
+struct Terminal {
+    colour: bool,
+    width: usize,
+}
+
+/// Prints the median compile time of `repo` on each version, as a sparkline for each pair of
+/// modes matching `compiler_mode` and `profile_mode`, or as a line chart if `chart`, followed by
+/// a table of its output sizes.
+pub(crate) fn show(
+    profiles: &BTreeMap<String, Profile>,
+    repo: &str,
+    compiler_mode: Option<CompilerMode>,
+    profile_mode: Option<ProfileMode>,
+    chart: bool,
+    sort: SortBy,
+) -> Result<()> {
+    let profile = profiles
+        .get(repo)
+        .ok_or_else(|| anyhow!("No results for {} on this machine", repo))?;
+    let terminal = Terminal::new();
+    let versions: Vec<Version> = Version::into_enum_iter()
+        .filter(|v| profile.has_compile_times(*v))
+        .collect();
+
+    println!(
+        "{}",
+        terminal.paint("1", &format!("{} - median compile time", repo))
+    );
+    for cm in CompilerMode::into_enum_iter().filter(|cm| compiler_mode.is_none_or(|m| m == *cm)) {
+        for pm in ProfileMode::into_enum_iter().filter(|pm| profile_mode.is_none_or(|m| m == *pm)) {
+            let medians: Vec<Option<f64>> = versions
+                .iter()
+                .map(|v| {
+                    profile.compile_times(*v, cm, pm).map(|times| {
+                        let times: Vec<f64> = times.iter().map(|ms| ms.as_f64()).collect();
+                        stats::median(&times) / 1000.0
+                    })
+                })
+                .collect();
+            if medians.iter().all(Option::is_none) {
+                continue;
+            }
+            let label = format!("{:?}/{:?}", cm, pm);
+            if chart {
+                println!();
+                println!("{}", label);
+                for line in line_chart(&versions, &medians, terminal.width) {
+                    println!("{}", line);
+                }
+            } else {
+                println!("{}", sparkline_row(&label, &medians, terminal));
+            }
+        }
+    }
+
+    println!();
+    for line in sizes_table(profile, sort, terminal) {
+        println!("{}", line);
+    }
+    Ok(())
+}
+
+impl Terminal {
+    /// Colours only a terminal, and not if NO_COLOR is set. The width is that of the terminal,
+    /// or COLUMNS, or 80.
+    fn new() -> Terminal {
+        let stdout = std::io::stdout();
+        let no_color = std::env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty());
+        let width = terminal_width(libc::STDOUT_FILENO)
+            .or_else(|| std::env::var("COLUMNS").ok()?.parse().ok())
+            .unwrap_or(80);
+        Terminal {
+            colour: stdout.is_terminal() && !no_color,
+            width,
+        }
+    }
+
+    /// Wraps `s` in the ANSI SGR code `code`, e.g. "1" for bold.
+    fn paint(&self, code: &str, s: &str) -> String {
+        if self.colour {
+            format!("\x1b[{}m{}\x1b[0m", code, s)
+        } else {
+            s.to_string()
+        }
+    }
+}
+
+/// Columns of the terminal `fd` is, if it is one.
+pub(crate) fn terminal_width(fd: libc::c_int) -> Option<usize> {
+    let mut size: libc::winsize = unsafe { std::mem::zeroed() };
+    let ok = unsafe { libc::ioctl(fd, libc::TIOCGWINSZ, &mut size) } == 0;
+    (ok && size.ws_col > 0).then_some(size.ws_col as usize)
+}
+
+/// The newest of `values` that fit in `width` columns, with a leading "…" if any don't.
+fn fit(values: &[Option<f64>], width: usize) -> (&[Option<f64>], bool) {
+    if values.len() <= width {
+        (values, false)
+    } else {
+        let width = width.max(2);
+        (&values[values.len() + 1 - width..], true)
+    }
+}
+
+/// One character per value, from the lowest to the highest, and a space where there is none.
+fn sparkline(values: &[Option<f64>]) -> String {
+    let (min, max) = range(values);
+    values
+        .iter()
+        .map(|value| match value {
+            Some(value) if max > min => {
+                let level = (value - min) / (max - min) * (SPARKS.len() - 1) as f64;
+                SPARKS[level.round() as usize]
+            }
+            Some(_) => SPARKS[0],
+            None => ' ',
+        })
+        .collect()
+}
+
+fn sparkline_row(label: &str, values: &[Option<f64>], terminal: Terminal) -> String {
+    let room = terminal.width.saturating_sub(LABEL_WIDTH + STATS_WIDTH);
+    let (shown, truncated) = fit(values, room);
+    let spark = format!("{}{}", if truncated { "…" } else { "" }, sparkline(shown));
+
+    let mut present = values.iter().flatten();
+    let first = present.next().copied().unwrap_or(0.0);
+    let last = present.last().copied().unwrap_or(first);
+    let change = (last - first) / first * 100.0;
+    let change = format!("{:+6.1}%", change);
+    // Slower is worse.
+    let change = match last.partial_cmp(&first) {
+        Some(std::cmp::Ordering::Greater) => terminal.paint("31", &change),
+        Some(std::cmp::Ordering::Less) => terminal.paint("32", &change),
+        _ => change,
+    };
+    format!(
+        "{:<width$}{}  {:>7.2}s → {:>7.2}s {}",
+        label,
+        spark,
+        first,
+        last,
+        change,
+        width = LABEL_WIDTH
+    )
+}
+
+/// A chart of `values` with one column per version, the y axis from the lowest value to the
+/// highest, and the first and last versions shown under it.
+fn line_chart(versions: &[Version], values: &[Option<f64>], width: usize) -> Vec<String> {
+    let room = width.saturating_sub(Y_AXIS_WIDTH + 1);
+    let (shown, truncated) = fit(values, room);
+    let shown_versions = &versions[versions.len() - shown.len()..];
+    let (min, max) = range(shown);
+    let row = |value: f64| {
+        if max > min {
+            ((value - min) / (max - min) * (CHART_HEIGHT - 1) as f64).round() as usize
+        } else {
+            0
+        }
+    };
+
+    // Points are joined by a vertical line from the previous point's row.
+    let mut grid = vec![vec![' '; shown.len()]; CHART_HEIGHT];
+    let mut previous = None;
+    for (x, value) in shown.iter().enumerate() {
+        if let Some(value) = value {
+            let y = row(*value);
+            if let Some(previous) = previous {
+                let (low, high) = if previous < y {
+                    (previous, y)
+                } else {
+                    (y, previous)
+                };
+                for line in grid.iter_mut().take(high).skip(low + 1) {
+                    line[x] = '|';
+                }
+            }
+            grid[y][x] = '*';
+            previous = Some(y);
+        }
+    }
+
+    let mut lines: Vec<String> = grid
+        .iter()
+        .enumerate()
+        .rev()
+        .map(|(y, line)| {
+            let axis = if y == CHART_HEIGHT - 1 {
+                format!("{:>7.2}s", max)
+            } else if y == 0 {
+                format!("{:>7.2}s", min)
+            } else {
+                String::new()
+            };
+            let line: String = line.iter().collect();
+            format!("{:>w$}|{}", axis, line.trim_end(), w = Y_AXIS_WIDTH - 1)
+        })
+        .collect();
+    lines.push(format!(
+        "{:>w$}+{}",
+        "",
+        "-".repeat(shown.len()),
+        w = Y_AXIS_WIDTH - 1
+    ));
+    if let (Some(first), Some(last)) = (shown_versions.first(), shown_versions.last()) {
+        let first = format!("{}{}", if truncated { "…" } else { "" }, first.get_string());
+        let gap = shown
+            .len()
+            .saturating_sub(first.chars().count() + last.get_string().len());
+        lines.push(format!(
+            "{:>w$} {}{}{}",
+            "",
+            first,
+            " ".repeat(gap.max(1)),
+            last.get_string(),
+            w = Y_AXIS_WIDTH - 1
+        ));
+    }
+    lines
+}
+
+fn range(values: &[Option<f64>]) -> (f64, f64) {
+    values
+        .iter()
+        .flatten()
+        .fold((f64::INFINITY, f64::NEG_INFINITY), |(min, max), v| {
+            (min.min(*v), max.max(*v))
+        })
+}
+
+/// Output sizes in MiB, one row per version and one column per CompilerMode.
+fn sizes_table(profile: &Profile, sort: SortBy, terminal: Terminal) -> Vec<String> {
+    let mut rows: BTreeMap<Version, BTreeMap<CompilerMode, f64>> = BTreeMap::new();
+    for (version, compiler_mode, bytes) in profile.all_output_sizes() {
+        let mib = bytes.as_u64() as f64 / (1024.0 * 1024.0);
+        rows.entry(version).or_default().insert(compiler_mode, mib);
+    }
+    if rows.is_empty() {
+        return vec!["No output sizes".to_string()];
+    }
+    let columns: Vec<CompilerMode> = CompilerMode::into_enum_iter()
+        .filter(|cm| rows.values().any(|sizes| sizes.contains_key(cm)))
+        .collect();
+
+    let mut rows: Vec<(Version, BTreeMap<CompilerMode, f64>)> = rows.into_iter().collect();
+    if let SortBy::Size(compiler_mode) = sort {
+        let size = |sizes: &BTreeMap<CompilerMode, f64>| sizes.get(&compiler_mode).copied();
+        rows.sort_by(|a, b| size(&b.1).partial_cmp(&size(&a.1)).unwrap());
+    }
+
+    let header: String = columns
+        .iter()
+        .map(|cm| format!("{:>14}", format!("{:?} (MiB)", cm)))
+        .collect();
+    let mut lines = vec![terminal.paint("1", &format!("{:<10}{}", "version", header))];
+    for (version, sizes) in rows {
+        let cells: String = columns
+            .iter()
+            .map(|cm| match sizes.get(cm) {
+                Some(mib) => format!("{:>14.2}", mib),
+                None => format!("{:>14}", "-"),
+            })
+            .collect();
+        lines.push(format!("{:<10}{}", version.get_string(), cells));
+    }
+    lines
+        .into_iter()
+        .map(|line| truncate(line, terminal.width))
+        .collect()
+}
+
+/// Cuts plain lines that don't fit. Coloured ones are left alone, as their escapes don't take
+/// up any columns.
+fn truncate(line: String, width: usize) -> String {
+    if line.contains('\x1b') || line.chars().count() <= width {
+        line
+    } else {
+        line.chars().take(width).collect()
+    }
+}
+
+impl std::str::FromStr for SortBy {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "version" => Ok(SortBy::Version),
+            _ => s
+                .parse()
+                .map(SortBy::Size)
+                .map_err(|_| "expected version or a CompilerMode"),
+        }
+    }
+}
+
+/// Reads `results_file`, or this machine's results file in `results_dir`.
+pub(crate) fn profiles(
+    results_dir: &Path,
+    results_file: Option<&Path>,
+) -> Result<BTreeMap<String, Profile>> {
+    match results_file {
+        Some(results_file) => Ok(store::read_results(results_file)?.1),
+        None => store::get_profiles(results_dir, None),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_sparkline() {
+        assert_eq!(sparkline(&[Some(1.0), Some(2.0), None, Some(8.0)]), "▁▂ █");
+        assert_eq!(sparkline(&[Some(3.0), Some(3.0)]), "▁▁");
+
+        let values: Vec<Option<f64>> = (1..=10).map(|i| Some(i as f64)).collect();
+        let terminal = Terminal {
+            colour: false,
+            width: LABEL_WIDTH + STATS_WIDTH + 4,
+        };
+        let row = sparkline_row("Check/Clean", &values, terminal);
+        assert_eq!(
+            row,
+            format!("{:<26}…▁▅█     1.00s →   10.00s +900.0%", "Check/Clean")
+        );
+        assert_eq!(row.chars().count(), terminal.width);
+    }
+
+    #[test]
+    fn test_line_chart() {
+        let versions = [
+            Version::V1_50,
+            Version::V1_51,
+            Version::V1_52,
+            Version::V1_53,
+        ];
+        let values = [Some(1.0), Some(10.0), None, Some(4.0)];
+        let lines = line_chart(&versions, &values, 80);
+        assert_eq!(lines.len(), CHART_HEIGHT + 2);
+        assert_eq!(lines[0], "  10.00s| *");
+        assert_eq!(lines[1], "        | | |");
+        assert_eq!(lines[6], "        | | *");
+        assert_eq!(lines[9], "   1.00s|*");
+        assert_eq!(lines[10], "        +----");
+        assert_eq!(lines[11], "         1.50.0 1.53.0");
+    }
+
+    #[test]
+    fn test_sort_by_from_str() {
+        assert_eq!("version".parse(), Ok(SortBy::Version));
+        assert_eq!("Release".parse(), Ok(SortBy::Size(CompilerMode::Release)));
+        assert!("size".parse::<SortBy>().is_err());
+    }
+}
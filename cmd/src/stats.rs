@@ -0,0 +1,365 @@
+use serde::Serialize;
+
+use crate::schedule::SplitMix64;
+
+/// Summary statistics of one list of samples.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub(crate) struct Summary {
+    pub(crate) samples: usize,
+    pub(crate) mean: f64,
+    pub(crate) median: f64,
+    /// 95% confidence interval of the mean, assuming normally distributed samples. None for
+    /// fewer than 2 samples.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) ci: Option<[f64; 2]>,
+    pub(crate) min: f64,
+    pub(crate) max: f64,
+}
+
+/// Scales the median absolute deviation so that it estimates the standard deviation of
+/// normally distributed samples.
+const MAD_SCALE: f64 = 1.4826;
+
+impl Summary {
+    pub(crate) fn new(samples: &[f64]) -> Summary {
+        let mean = mean(samples);
+        let ci = (samples.len() >= 2).then(|| {
+            let n = samples.len();
+            let step = t_quantile(n - 1) * std_dev(samples) / (n as f64).sqrt();
+            [mean - step, mean + step]
+        });
+        Summary {
+            samples: samples.len(),
+            mean,
+            median: median(samples),
+            ci,
+            min: samples.iter().copied().fold(f64::NAN, f64::min),
+            max: samples.iter().copied().fold(f64::NAN, f64::max),
+        }
+    }
+}
+
+/// The 97.5th percentile of Student's t distribution with 1 to 30 degrees of freedom.
+const T_QUANTILES: [f64; 30] = [
+    12.706, 4.303, 3.182, 2.776, 2.571, 2.447, 2.365, 2.306, 2.262, 2.228, 2.201, 2.179, 2.160,
+    2.145, 2.131, 2.120, 2.110, 2.101, 2.093, 2.086, 2.080, 2.074, 2.069, 2.064, 2.060, 2.056,
+    2.052, 2.048, 2.045, 2.042,
+];
+
+/// The multiple of the standard error either side of the mean that a 95% confidence interval
+/// spans, for `degrees_of_freedom` > 0. Past the table, from the first two terms of the
+/// Cornish-Fisher expansion of t around the normal distribution, within 0.001 of it.
+fn t_quantile(degrees_of_freedom: usize) -> f64 {
+    match T_QUANTILES.get(degrees_of_freedom.wrapping_sub(1)) {
+        Some(t) => *t,
+        None => {
+            const Z: f64 = 1.959_964;
+            let v = degrees_of_freedom as f64;
+            Z + (Z.powi(3) + Z) / (4.0 * v)
+                + (5.0 * Z.powi(5) + 16.0 * Z.powi(3) + 3.0 * Z) / (96.0 * v * v)
+        }
+    }
+}
+
+pub(crate) fn mean(samples: &[f64]) -> f64 {
+    if samples.is_empty() {
+        return 0.0;
+    }
+    samples.iter().sum::<f64>() / samples.len() as f64
+}
+
+/// Sample standard deviation, with Bessel's correction.
+pub(crate) fn std_dev(samples: &[f64]) -> f64 {
+    if samples.len() < 2 {
+        return 0.0;
+    }
+    let mean = mean(samples);
+    let sum_of_squares: f64 = samples.iter().map(|x| (x - mean).powi(2)).sum();
+    (sum_of_squares / (samples.len() - 1) as f64).sqrt()
+}
+
+pub(crate) fn coefficient_of_variation(samples: &[f64]) -> f64 {
+    let mean = mean(samples);
+    if mean == 0.0 {
+        return 0.0;
+    }
+    std_dev(samples) / mean
+}
+
+pub(crate) fn median(samples: &[f64]) -> f64 {
+    if samples.is_empty() {
+        return 0.0;
+    }
+    let mut sorted = samples.to_vec();
+    sorted.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    let mid = sorted.len() / 2;
+    if sorted.len().is_multiple_of(2) {
+        (sorted[mid - 1] + sorted[mid]) / 2.0
+    } else {
+        sorted[mid]
+    }
+}
+
+pub(crate) fn median_absolute_deviation(samples: &[f64]) -> f64 {
+    let median = median(samples);
+    let deviations: Vec<f64> = samples.iter().map(|x| (x - median).abs()).collect();
+    self::median(&deviations)
+}
+
+/// Indices of the samples further than `fence` scaled MADs from the median. Nothing is flagged
+/// when most samples are identical, since every other sample would be infinitely far out.
+pub(crate) fn outliers(samples: &[f64], fence: f64) -> Vec<usize> {
+    let median = median(samples);
+    let mad = median_absolute_deviation(samples) * MAD_SCALE;
+    if mad == 0.0 {
+        return Vec::new();
+    }
+    samples
+        .iter()
+        .enumerate()
+        .filter(|(_, x)| (*x - median).abs() > fence * mad)
+        .map(|(i, _)| i)
+        .collect()
+}
+
+/// Two-sided p-value of the Mann-Whitney U test that neither sample tends to be larger than
+/// the other. Exact when there are no ties and both samples are small, otherwise from the normal
+/// approximation with a correction for ties.
+pub(crate) fn mann_whitney(a: &[f64], b: &[f64]) -> f64 {
+    if a.is_empty() || b.is_empty() {
+        return 1.0;
+    }
+    let (ranks, ties) = ranks(a, b);
+    let (n1, n2) = (a.len(), b.len());
+    let rank_sum: f64 = ranks[..n1].iter().sum();
+    let u = rank_sum - (n1 * (n1 + 1)) as f64 / 2.0;
+
+    if ties.is_empty() && n1 <= EXACT_MAX_SAMPLES && n2 <= EXACT_MAX_SAMPLES {
+        return exact_p_value(n1, n2, u.round() as usize);
+    }
+
+    let n = (n1 + n2) as f64;
+    let tie_correction: f64 =
+        ties.iter().map(|&t| (t * t * t - t) as f64).sum::<f64>() / (n * (n - 1.0));
+    let variance = (n1 * n2) as f64 / 12.0 * ((n + 1.0) - tie_correction);
+    if variance <= 0.0 {
+        return 1.0;
+    }
+    let mean = (n1 * n2) as f64 / 2.0;
+    let z = ((u - mean).abs() - 0.5).max(0.0) / variance.sqrt();
+    erfc(z / std::f64::consts::SQRT_2).min(1.0)
+}
+
+/// Smallest p-value `mann_whitney` can give for samples of `n1` and `n2`, when every sample of one
+/// is below every sample of the other. A difference between fewer samples can't be significant at
+/// any alpha below this, however large it is.
+pub(crate) fn min_mann_whitney_p(n1: usize, n2: usize) -> f64 {
+    if n1 == 0 || n2 == 0 {
+        return 1.0;
+    }
+    // 2 / (n1 + n2 choose n1)
+    let arrangements = (1..=n1).fold(1.0, |product, i| product * (n2 + i) as f64 / i as f64);
+    (2.0 / arrangements).min(1.0)
+}
+
+/// 95% confidence interval of the change of the median from `a` to `b`, as a percentage of the
+/// median of `a`, from `resamples` bootstrap resamples of both.
+pub(crate) fn median_change_ci(a: &[f64], b: &[f64], resamples: usize, seed: u64) -> [f64; 2] {
+    if a.is_empty() || b.is_empty() || resamples == 0 {
+        return [0.0, 0.0];
+    }
+    let mut rng = SplitMix64::new(seed);
+    let mut resample = |samples: &[f64]| -> f64 {
+        let drawn: Vec<f64> = (0..samples.len())
+            .map(|_| samples[(rng.next_u64() % samples.len() as u64) as usize])
+            .collect();
+        median(&drawn)
+    };
+    let mut changes: Vec<f64> = (0..resamples)
+        .filter_map(|_| {
+            let before = resample(a);
+            let after = resample(b);
+            if before == 0.0 {
+                None
+            } else {
+                Some((after - before) / before * 100.0)
+            }
+        })
+        .collect();
+    if changes.is_empty() {
+        return [0.0, 0.0];
+    }
+    changes.sort_by(|x, y| x.partial_cmp(y).unwrap());
+    let quantile = |q: f64| changes[((changes.len() - 1) as f64 * q).round() as usize];
+    [quantile(0.025), quantile(0.975)]
+}
+
+/// Largest sample size for which `mann_whitney` counts every arrangement of the ranks.
+const EXACT_MAX_SAMPLES: usize = 30;
+
+/// Ranks of the samples of `a` followed by those of `b`, with tied samples sharing the mean of
+/// their ranks. Also returns the size of every group of ties.
+fn ranks(a: &[f64], b: &[f64]) -> (Vec<f64>, Vec<usize>) {
+    let samples: Vec<f64> = a.iter().chain(b).copied().collect();
+    let mut order: Vec<usize> = (0..samples.len()).collect();
+    order.sort_by(|&i, &j| samples[i].partial_cmp(&samples[j]).unwrap());
+
+    let mut ranks = vec![0.0; samples.len()];
+    let mut ties = Vec::new();
+    let mut start = 0;
+    while start < order.len() {
+        let mut end = start + 1;
+        while end < order.len() && samples[order[end]] == samples[order[start]] {
+            end += 1;
+        }
+        let rank = (start + end + 1) as f64 / 2.0;
+        for &i in &order[start..end] {
+            ranks[i] = rank;
+        }
+        if end - start > 1 {
+            ties.push(end - start);
+        }
+        start = end;
+    }
+    (ranks, ties)
+}
+
+/// Two-sided p-value of `u` from the distribution of U over every arrangement of n1 + n2
+/// distinct ranks.
+fn exact_p_value(n1: usize, n2: usize, u: usize) -> f64 {
+    // counts[j][k] is the number of arrangements of i and j samples with U = k, for the current i.
+    let mut counts: Vec<Vec<f64>> = (0..=n2).map(|_| vec![1.0]).collect();
+    for i in 1..=n1 {
+        let mut next: Vec<Vec<f64>> = Vec::with_capacity(n2 + 1);
+        next.push(vec![1.0]);
+        for j in 1..=n2 {
+            // The largest rank belongs to the first sample, adding j to U, or to the second.
+            let mut row = vec![0.0; i * j + 1];
+            for (k, count) in counts[j].iter().enumerate() {
+                row[k + j] += count;
+            }
+            for (k, count) in next[j - 1].iter().enumerate() {
+                row[k] += count;
+            }
+            next.push(row);
+        }
+        counts = next;
+    }
+
+    let distribution = &counts[n2];
+    let total: f64 = distribution.iter().sum();
+    let lower: f64 = distribution[..=u].iter().sum();
+    let upper: f64 = distribution[u..].iter().sum();
+    (2.0 * lower.min(upper) / total).min(1.0)
+}
+
+/// Complementary error function, with a fractional error below 1.2e-7.
+fn erfc(x: f64) -> f64 {
+    let z = x.abs();
+    let t = 1.0 / (1.0 + 0.5 * z);
+    let y = t
+        * (-z * z - 1.265_512_23
+            + t * (1.000_023_68
+                + t * (0.374_091_96
+                    + t * (0.096_784_18
+                        + t * (-0.186_288_06
+                            + t * (0.278_868_07
+                                + t * (-1.135_203_98
+                                    + t * (1.488_515_87
+                                        + t * (-0.822_152_23 + t * 0.170_872_77)))))))))
+            .exp();
+    if x >= 0.0 {
+        y
+    } else {
+        2.0 - y
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_summary_statistics() {
+        let samples = [2.0, 4.0, 4.0, 4.0, 5.0, 5.0, 7.0, 9.0];
+        assert_eq!(mean(&samples), 5.0);
+        assert_eq!(median(&samples), 4.5);
+        assert!((std_dev(&samples) - 2.138).abs() < 0.001);
+        assert!((coefficient_of_variation(&samples) - 0.4276).abs() < 0.001);
+        assert_eq!(median_absolute_deviation(&samples), 0.5);
+        assert_eq!(median(&[3.0, 1.0, 2.0]), 2.0);
+        assert_eq!(std_dev(&[1.0]), 0.0);
+    }
+
+    #[test]
+    fn test_summary() {
+        let summary = Summary::new(&[2.0, 4.0, 4.0, 4.0, 5.0, 5.0, 7.0, 9.0]);
+        assert_eq!(summary.samples, 8);
+        assert_eq!(summary.median, 4.5);
+        assert_eq!((summary.min, summary.max), (2.0, 9.0));
+        let [low, high] = summary.ci.unwrap();
+        assert!((low - 3.212).abs() < 0.001);
+        assert!((high - 6.788).abs() < 0.001);
+        assert_eq!(Summary::new(&[3.0]).ci, None);
+        assert_eq!(Summary::new(&[]).ci, None);
+        assert_eq!(t_quantile(2), 4.303);
+        assert!((t_quantile(31) - 2.0395).abs() < 0.001);
+        assert!((t_quantile(1000) - 1.962).abs() < 0.001);
+    }
+
+    #[test]
+    fn test_outliers() {
+        let samples = [1000.0, 1010.0, 990.0, 1005.0, 1800.0, 995.0];
+        assert_eq!(outliers(&samples, 3.0), vec![4]);
+        assert!(outliers(&[1000.0, 1000.0, 1000.0, 1300.0], 3.0).is_empty());
+        assert!(outliers(&[], 3.0).is_empty());
+    }
+
+    #[test]
+    fn test_mann_whitney() {
+        // Completely separated samples of 5 are the most extreme of 252 arrangements.
+        let a = [10.0, 11.0, 12.0, 13.0, 14.0];
+        let b = [20.0, 21.0, 22.0, 23.0, 24.0];
+        assert!((mann_whitney(&a, &b) - 2.0 / 252.0).abs() < 1e-12);
+        assert!((mann_whitney(&b, &a) - 2.0 / 252.0).abs() < 1e-12);
+
+        // U = 3, P(U <= 3) = 7 / 70
+        let a = [1.0, 2.0, 4.0, 6.0];
+        let b = [3.0, 5.0, 7.0, 8.0];
+        assert!((mann_whitney(&a, &b) - 0.2).abs() < 1e-12);
+
+        // Ties use the normal approximation.
+        let a = [1.0, 2.0, 2.0, 3.0, 4.0];
+        let b = [2.0, 5.0, 6.0, 6.0, 7.0];
+        assert!((mann_whitney(&a, &b) - 0.056_24).abs() < 1e-4);
+
+        assert_eq!(mann_whitney(&[1.0, 1.0], &[1.0, 1.0]), 1.0);
+        assert_eq!(mann_whitney(&[1.0], &[]), 1.0);
+        assert!((erfc(0.0) - 1.0).abs() < 1e-7);
+        assert!((erfc(1.0) - 0.157_299_2).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_min_mann_whitney_p() {
+        assert!((min_mann_whitney_p(3, 3) - 0.1).abs() < 1e-12);
+        assert!((min_mann_whitney_p(5, 5) - 2.0 / 252.0).abs() < 1e-12);
+        assert!((min_mann_whitney_p(3, 4) - 2.0 / 35.0).abs() < 1e-12);
+        assert_eq!(min_mann_whitney_p(1, 1), 1.0);
+        assert_eq!(min_mann_whitney_p(0, 5), 1.0);
+        let a = [10.0, 11.0, 12.0];
+        let b = [20.0, 21.0, 22.0];
+        assert_eq!(mann_whitney(&a, &b), min_mann_whitney_p(3, 3));
+    }
+
+    #[test]
+    fn test_median_change_ci() {
+        let a = [1000.0, 1010.0, 990.0, 1005.0, 995.0];
+        let b = [1100.0, 1110.0, 1090.0, 1105.0, 1095.0];
+        let [low, high] = median_change_ci(&a, &b, 2000, 1);
+        assert!(low <= 10.0 && 10.0 <= high);
+        assert!(low > 7.0 && high < 13.0);
+        assert_eq!(median_change_ci(&a, &b, 2000, 1), [low, high]);
+        assert_eq!(median_change_ci(&a, &[], 2000, 1), [0.0, 0.0]);
+        assert_eq!(median_change_ci(&[3.0], &[3.0], 10, 1), [0.0, 0.0]);
+    }
+}
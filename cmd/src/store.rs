@@ -4,17 +4,22 @@ use std::collections::BTreeMap;
 use std::fs::File;
 use std::hash::{Hash, Hasher};
 use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
 
-use anyhow::Result;
+use anyhow::{anyhow, Context, Result};
 use serde::{Deserialize, Serialize};
 
 use crate::profile::Profile;
-use crate::repo::Repo;
+use crate::repo::{Repo, RepoOptions};
 use crate::system::SystemInfo;
 
 #[derive(Debug, Serialize, Deserialize)]
 struct FinalResult<'a> {
     system_info: SystemInfo,
+    /// Seconds since the Unix epoch when the samples were last added to. Missing from results
+    /// files written before it was recorded.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    updated: Option<u64>,
     profiles: Cow<'a, BTreeMap<String, Profile>>,
 }
 
@@ -22,20 +27,117 @@ impl<'a> FinalResult<'a> {
     fn new(system_info: SystemInfo) -> Self {
         FinalResult {
             system_info,
+            updated: None,
             profiles: Cow::Owned(BTreeMap::new()),
         }
     }
 }
 
-pub(crate) fn get_repos(repos_file: &Path) -> Result<Vec<Repo>> {
-    let file = File::open(repos_file)?;
-    Ok(serde_json::from_reader(file)?)
+/// A machine results were collected on, as it's shown on the site. Kept in `machines.json` in
+/// the results directory, keyed by machine ID.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct Machine {
+    pub(crate) label: String,
+    /// Cloud instance type, e.g. "c5.2xlarge".
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) instance_type: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) notes: Option<String>,
 }
 
-pub(crate) fn get_profiles(results_dir: &Path) -> Result<BTreeMap<String, Profile>> {
-    let system_info = SystemInfo::new()?;
+pub(crate) const MACHINES_FILE: &str = "machines.json";
+
+/// The TOML repos file. Each `[[repo]]` inherits whatever it doesn't set from `[defaults]`.
+#[derive(Debug, Serialize, Deserialize)]
+struct Manifest {
+    #[serde(default)]
+    defaults: RepoOptions,
+    #[serde(rename = "repo")]
+    repos: Vec<Repo>,
+}
+
+/// Reads a repos file, either a TOML manifest or a JSON array of repos. `overrides` take
+/// precedence over the manifest's `[defaults]`, but not over options set on a repo.
+pub(crate) fn get_repos(repos_file: &Path, overrides: &RepoOptions) -> Result<Vec<Repo>> {
+    let contents = std::fs::read_to_string(repos_file)
+        .with_context(|| anyhow!("Failed to open repos file - {:?}", repos_file))?;
+    let manifest = if is_toml(repos_file) {
+        toml::from_str(&contents)
+            .with_context(|| anyhow!("Failed to parse repos file - {:?}", repos_file))?
+    } else {
+        Manifest {
+            defaults: RepoOptions::default(),
+            repos: serde_json::from_str(&contents)
+                .with_context(|| anyhow!("Failed to parse repos file - {:?}", repos_file))?,
+        }
+    };
+
+    let defaults = overrides.clone().inherit(&manifest.defaults);
+    Ok(manifest
+        .repos
+        .into_iter()
+        .map(|mut repo| {
+            repo.options = repo.options.inherit(&defaults);
+            repo
+        })
+        .collect())
+}
+
+/// Writes repos in the format given by the file's extension. JSON has no `[defaults]`, so each
+/// repo is written with its options resolved.
+pub(crate) fn write_repos(repos_file: &Path, repos: Vec<Repo>) -> Result<()> {
+    let contents = if is_toml(repos_file) {
+        let manifest = Manifest {
+            defaults: RepoOptions::default(),
+            repos,
+        };
+        toml::to_string(&manifest)?
+    } else {
+        let mut contents = Vec::new();
+        let formatter = serde_json::ser::PrettyFormatter::with_indent(b"    ");
+        let mut serializer = serde_json::Serializer::with_formatter(&mut contents, formatter);
+        repos.serialize(&mut serializer)?;
+        String::from_utf8(contents)?
+    };
+    log::info!("Writing to {:?}", repos_file);
+    std::fs::write(repos_file, contents)
+        .with_context(|| anyhow!("Failed to write repos file - {:?}", repos_file))
+}
+
+fn is_toml(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| ext == "toml")
+}
+
+/// Reads this machine's results file. `continue_from` is a results file of this machine from
+/// before a kernel or OS update, which is continued under the new name.
+pub(crate) fn get_profiles(
+    results_dir: &Path,
+    continue_from: Option<&Path>,
+) -> Result<BTreeMap<String, Profile>> {
+    let system_info = SystemInfo::current()?;
     log::trace!("{:?}", &system_info);
     let results_file = get_result_file_path(results_dir, &system_info);
+    if !results_file.exists() {
+        // Written on this machine by an older arewefastyet, either before SystemInfo had its
+        // optional fields or before file names were stable. Continue it under the new name.
+        let legacy_file = vec![
+            get_legacy_result_file_path(results_dir, &system_info),
+            get_legacy_result_file_path(results_dir, &system_info.legacy()),
+        ]
+        .into_iter()
+        .find(|path| path.exists());
+        if let Some(legacy_file) = legacy_file {
+            log::info!("Renaming {:?} to {:?}", &legacy_file, &results_file);
+            std::fs::rename(&legacy_file, &results_file)?;
+        }
+    }
+    match continue_from {
+        Some(continue_from) => {
+            continue_results(results_dir, continue_from, &system_info, &results_file)?
+        }
+        None if !results_file.exists() => warn_if_updated(results_dir, &system_info)?,
+        None => {}
+    }
     log::info!("Attempting to read results file - {:?}", &results_file);
     let profiles = if results_file.exists() {
         let file = File::open(results_file)?;
@@ -49,24 +151,199 @@ pub(crate) fn get_profiles(results_dir: &Path) -> Result<BTreeMap<String, Profil
     Ok(profiles)
 }
 
+/// Renames `continue_from` to `results_file`, and moves its machine's entry in the registry to
+/// the new ID.
+fn continue_results(
+    results_dir: &Path,
+    continue_from: &Path,
+    system_info: &SystemInfo,
+    results_file: &Path,
+) -> Result<()> {
+    if results_file.exists() {
+        return Err(anyhow!(
+            "This machine already has a results file, {:?}, so it can't continue {:?}",
+            results_file,
+            continue_from
+        ));
+    }
+    let (previous, _) = read_results(continue_from)?;
+    if !system_info.is_update_of(&previous) {
+        return Err(anyhow!(
+            "{:?} isn't a results file of this machine before a kernel or OS update",
+            continue_from
+        ));
+    }
+    log::warn!(
+        "Continuing {:?} as {:?}, although its samples were taken on kernel {} and new ones on {}",
+        continue_from,
+        results_file,
+        previous.kernel(),
+        system_info.kernel()
+    );
+    std::fs::rename(continue_from, results_file)?;
+
+    let mut machines = get_machines(results_dir)?;
+    if let Some(machine) = machines.remove(&machine_id(&previous)) {
+        machines.entry(machine_id(system_info)).or_insert(machine);
+        write_machines(results_dir, &machines)?;
+    }
+    Ok(())
+}
+
+/// Warns about results files of this machine from before a kernel or OS update, since
+/// starting a new one means profiling every repo again.
+fn warn_if_updated(results_dir: &Path, system_info: &SystemInfo) -> Result<()> {
+    for path in get_result_files(results_dir)? {
+        let previous = match read_results(&path) {
+            Ok((previous, _)) => previous,
+            Err(_) => continue,
+        };
+        if system_info.is_update_of(&previous) {
+            log::warn!(
+                "{:?} has the results of this machine from before its kernel changed from {} to {}. A new results file is started, which means profiling every repo again. To continue that one instead, pass --continue-from {}",
+                &path,
+                previous.kernel(),
+                system_info.kernel(),
+                path.display()
+            );
+        }
+    }
+    Ok(())
+}
+
 pub(crate) fn overwrite_profiles(
     results_dir: &Path,
     profiles: &BTreeMap<String, Profile>,
 ) -> Result<()> {
-    let system_info = SystemInfo::new()?;
+    let system_info = SystemInfo::current()?;
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs());
+    write_results(results_dir, system_info, profiles, Some(now))
+}
+
+/// Writes the results file of the machine `system_info` describes, and registers the machine.
+/// `updated` is when the samples were last added to.
+pub(crate) fn write_results(
+    results_dir: &Path,
+    system_info: SystemInfo,
+    profiles: &BTreeMap<String, Profile>,
+    updated: Option<u64>,
+) -> Result<()> {
     let results_file = get_result_file_path(results_dir, &system_info);
 
     let final_result = FinalResult {
         system_info,
-        profiles: Cow::Borrowed(&profiles),
+        updated,
+        profiles: Cow::Borrowed(profiles),
     };
     let output = File::create(&results_file)?;
     log::info!("Writing to {:?}", &results_file);
     serde_json::to_writer_pretty(output, &final_result)?;
-    Ok(())
+    register_machine(results_dir, &final_result.system_info)
 }
 
-fn get_result_file_path(path: &Path, system_info: &SystemInfo) -> PathBuf {
+/// Adds the machine to the registry, unless it's there. It's labelled with its number of cores
+/// and the start of its ID, since machines with as many cores would have the same label
+/// otherwise.
+fn register_machine(results_dir: &Path, system_info: &SystemInfo) -> Result<()> {
+    let mut machines = get_machines(results_dir)?;
+    let id = machine_id(system_info);
+    if machines.contains_key(&id) {
+        return Ok(());
+    }
+    log::info!("Registering machine {}", &id);
+    let label = format!("{} cores ({})", system_info.num_cores, &id[..8]);
+    machines.insert(
+        id,
+        Machine {
+            label,
+            instance_type: None,
+            notes: None,
+        },
+    );
+    write_machines(results_dir, &machines)
+}
+
+pub(crate) fn get_machines(results_dir: &Path) -> Result<BTreeMap<String, Machine>> {
+    let path = results_dir.join(MACHINES_FILE);
+    if !path.exists() {
+        return Ok(BTreeMap::new());
+    }
+    let contents = std::fs::read_to_string(&path)
+        .with_context(|| anyhow!("Failed to open machines file - {:?}", path))?;
+    serde_json::from_str(&contents)
+        .with_context(|| anyhow!("Failed to parse machines file - {:?}", path))
+}
+
+pub(crate) fn write_machines(
+    results_dir: &Path,
+    machines: &BTreeMap<String, Machine>,
+) -> Result<()> {
+    let path = results_dir.join(MACHINES_FILE);
+    let mut contents = Vec::new();
+    let formatter = serde_json::ser::PrettyFormatter::with_indent(b"    ");
+    let mut serializer = serde_json::Serializer::with_formatter(&mut contents, formatter);
+    machines.serialize(&mut serializer)?;
+    log::info!("Writing to {:?}", path);
+    std::fs::write(&path, contents)
+        .with_context(|| anyhow!("Failed to write machines file - {:?}", path))
+}
+
+/// Paths of all results files in `results_dir`, sorted.
+pub(crate) fn get_result_files(results_dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut paths: Vec<PathBuf> = std::fs::read_dir(results_dir)
+        .with_context(|| anyhow!("Failed to read results directory - {:?}", results_dir))?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| {
+            path.file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| name.starts_with("results-") && name.ends_with(".json"))
+        })
+        .collect();
+    paths.sort();
+    Ok(paths)
+}
+
+pub(crate) fn read_results(results_file: &Path) -> Result<(SystemInfo, BTreeMap<String, Profile>)> {
+    let (system_info, profiles, _) = read_timed_results(results_file)?;
+    Ok((system_info, profiles))
+}
+
+/// Like `read_results`, with when the samples were last added to, if the file records it.
+pub(crate) fn read_timed_results(
+    results_file: &Path,
+) -> Result<(SystemInfo, BTreeMap<String, Profile>, Option<u64>)> {
+    let file = File::open(results_file)
+        .with_context(|| anyhow!("Failed to open results file - {:?}", results_file))?;
+    let final_result: FinalResult = serde_json::from_reader(file)
+        .with_context(|| anyhow!("Failed to parse results file - {:?}", results_file))?;
+    Ok((
+        final_result.system_info,
+        final_result.profiles.into_owned(),
+        final_result.updated,
+    ))
+}
+
+/// Identifies the machine in results file names and in `machines.json`. The 64 bit FNV-1a hash
+/// of `SystemInfo::identity` in hex. Unlike `DefaultHasher`, this doesn't depend on the Rust
+/// release arewefastyet was built with.
+pub(crate) fn machine_id(system_info: &SystemInfo) -> String {
+    format!("{:016x}", fnv1a(system_info.identity().as_bytes()))
+}
+
+/// `results-<num_cores>-<machine ID>.json`.
+pub(crate) fn get_result_file_path(path: &Path, system_info: &SystemInfo) -> PathBuf {
+    let file_name = format!(
+        "results-{}-{}.json",
+        system_info.num_cores,
+        machine_id(system_info)
+    );
+    path.join(file_name)
+}
+
+/// The name results files had when they were hashed with `DefaultHasher`.
+pub(crate) fn get_legacy_result_file_path(path: &Path, system_info: &SystemInfo) -> PathBuf {
     let mut hasher = DefaultHasher::new();
     system_info.hash(&mut hasher);
     let hash = hasher.finish();
@@ -76,13 +353,21 @@ fn get_result_file_path(path: &Path, system_info: &SystemInfo) -> PathBuf {
     filepath
 }
 
+fn fnv1a(bytes: &[u8]) -> u64 {
+    const OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+    const PRIME: u64 = 0x0100_0000_01b3;
+    bytes.iter().fold(OFFSET_BASIS, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(PRIME)
+    })
+}
+
 #[cfg(test)]
 mod test {
     use anyhow::{anyhow, Result};
     use std::path::PathBuf;
     #[test]
     fn test_file_path() -> Result<()> {
-        let system_info = serde_json::from_str(
+        let system_info: crate::system::SystemInfo = serde_json::from_str(
             r#"{
                 "num_cores": 2,
                 "num_physical_cores": 2,
@@ -90,10 +375,92 @@ mod test {
               }"#,
         )?;
         let file_path = super::get_result_file_path(&PathBuf::from("/tmp/"), &system_info);
+        assert_eq!(
+            file_path.to_str().ok_or(anyhow!("no path"))?,
+            "/tmp/results-2-3ca971dfaacb948d.json"
+        );
+        let file_path = super::get_legacy_result_file_path(&PathBuf::from("/tmp/"), &system_info);
         assert_eq!(
             file_path.to_str().ok_or(anyhow!("no path"))?,
             "/tmp/results-2-4397104837942437864.json"
         );
+
+        // The optional fields change the name only when present.
+        let system_info: crate::system::SystemInfo = serde_json::from_str(
+            r#"{
+                "num_cores": 2,
+                "num_physical_cores": 2,
+                "cpu_model": "Intel(R) Xeon(R) Gold 6140 CPU @ 2.30GHz",
+                "memory_gib": 8,
+                "rotational": false
+              }"#,
+        )?;
+        let file_path = super::get_result_file_path(&PathBuf::from("/tmp/"), &system_info);
+        assert_ne!(
+            file_path.to_str().ok_or(anyhow!("no path"))?,
+            "/tmp/results-2-3ca971dfaacb948d.json"
+        );
+        let file_path = super::get_result_file_path(&PathBuf::from("/tmp/"), &system_info.legacy());
+        assert_eq!(
+            file_path.to_str().ok_or(anyhow!("no path"))?,
+            "/tmp/results-2-3ca971dfaacb948d.json"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_fnv1a() {
+        assert_eq!(super::fnv1a(b""), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(super::fnv1a(b"a"), 0xaf63_dc4c_8601_ec8c);
+        assert_eq!(super::fnv1a(b"foobar"), 0x8594_4171_f739_67e8);
+    }
+
+    #[test]
+    fn test_manifest_defaults() -> Result<()> {
+        let manifest = r#"
+            [defaults]
+            times = 3
+            env = { RUST_BACKTRACE = "1" }
+
+            [[repo]]
+            name = "helloworld"
+            sub_directory = ""
+            url = "https://github.com/nindalf/helloworld"
+            touch_file = "src/main.rs"
+            commit = "v1.0"
+            min_version = "V1_34"
+            times = 10
+
+            [[repo]]
+            name = "rav1e"
+            sub_directory = ""
+            url = "https://github.com/xiph/rav1e"
+            touch_file = "src/lib.rs"
+            commit = "v0.3.4"
+            min_version = "V1_44"
+            env = { CARGO_INCREMENTAL = "0" }
+        "#;
+        let path = std::env::temp_dir().join("arewefastyet-test-manifest.toml");
+        std::fs::write(&path, manifest)?;
+
+        let repos = super::get_repos(&path, &Default::default())?;
+        assert_eq!(repos[0].sampling().times, 10);
+        assert_eq!(repos[1].sampling().times, 3);
+        assert_eq!(repos[1].options.env.len(), 2);
+
+        let overrides = crate::repo::RepoOptions {
+            times: Some(1),
+            ..Default::default()
+        };
+        let repos = super::get_repos(&path, &overrides)?;
+        assert_eq!(repos[0].sampling().times, 10);
+        assert_eq!(repos[1].sampling().times, 1);
+
+        let converted = std::env::temp_dir().join("arewefastyet-test-manifest.json");
+        super::write_repos(&converted, repos)?;
+        let repos = super::get_repos(&converted, &Default::default())?;
+        assert_eq!(repos[1].sampling().times, 1);
+        assert_eq!(repos[1].options.env["RUST_BACKTRACE"], "1");
         Ok(())
     }
 }
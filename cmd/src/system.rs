@@ -1,30 +1,185 @@
+use std::hash::{Hash, Hasher};
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+use std::process::Command;
+
 use anyhow::{anyhow, Context, Result};
+use once_cell::sync::OnceCell;
 use once_cell::unsync::Lazy;
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Hash, Serialize, Deserialize)]
+use crate::process::{self, CommandKind};
+use crate::repo;
+
+static CURRENT: OnceCell<SystemInfo> = OnceCell::new();
+
+/// The machine results were collected on. Its `identity` is hashed into the name of the results
+/// file, so that each machine gets its own.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub(crate) struct SystemInfo {
     pub(crate) num_cores: usize,
     num_physical_cores: usize,
     cpu_model: String,
+    /// Total memory, rounded to the nearest GiB so that it survives kernel updates.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    memory_gib: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    kernel: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    os: Option<String>,
+    /// Filesystem type of the working directory, where repos are built.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    filesystem: Option<String>,
+    /// Whether the working directory is on a spinning disk.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    rotational: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    rustup_host: Option<String>,
 }
 
 impl SystemInfo {
-    pub(crate) fn new() -> Result<SystemInfo> {
+    /// `working_directory` is where repos are built, if it's known yet.
+    pub(crate) fn new(working_directory: Option<&Path>) -> Result<SystemInfo> {
         let cpu_model = SystemInfo::cpu_model()?;
         Ok(SystemInfo {
             num_cores: num_cpus::get(),
             num_physical_cores: num_cpus::get_physical(),
             cpu_model,
+            memory_gib: SystemInfo::memory_gib().ok(),
+            kernel: std::fs::read_to_string("/proc/sys/kernel/osrelease")
+                .ok()
+                .map(|kernel| kernel.trim().to_string()),
+            os: SystemInfo::os().ok(),
+            filesystem: working_directory.and_then(|dir| SystemInfo::filesystem(dir).ok()),
+            rotational: working_directory.and_then(|dir| SystemInfo::rotational(dir).ok()),
+            rustup_host: SystemInfo::rustup_host().ok(),
         })
     }
 
+    /// This machine, with the working directory repos are built in. Only read once, so that
+    /// everything a run writes is named after the same SystemInfo, even if a command it needs
+    /// fails later on, e.g. after Ctrl-C.
+    pub(crate) fn current() -> Result<SystemInfo> {
+        CURRENT
+            .get_or_try_init(|| SystemInfo::new(repo::working_directory()))
+            .cloned()
+    }
+
+    /// Whether this is the machine `other` describes after a kernel or OS update, which
+    /// changes its identity but not its hardware.
+    pub(crate) fn is_update_of(&self, other: &SystemInfo) -> bool {
+        let hardware = |info: &SystemInfo| SystemInfo {
+            kernel: None,
+            os: None,
+            ..info.clone()
+        };
+        self != other && hardware(self) == hardware(other)
+    }
+
+    pub(crate) fn kernel(&self) -> &str {
+        self.kernel.as_deref().unwrap_or("unknown")
+    }
+
+    /// Only the fields SystemInfo had before the optional ones were added. Results files
+    /// written back then are named after this.
+    pub(crate) fn legacy(&self) -> SystemInfo {
+        SystemInfo {
+            num_cores: self.num_cores,
+            num_physical_cores: self.num_physical_cores,
+            cpu_model: self.cpu_model.clone(),
+            memory_gib: None,
+            kernel: None,
+            os: None,
+            filesystem: None,
+            rotational: None,
+            rustup_host: None,
+        }
+    }
+
+    /// The fields that identify the machine, one `name=value` per line, optional fields only
+    /// when present. This is what the results file name is derived from, so it must not change
+    /// for an existing SystemInfo.
+    pub(crate) fn identity(&self) -> String {
+        let mut identity = format!(
+            "num_cores={}\nnum_physical_cores={}\ncpu_model={}\n",
+            self.num_cores, self.num_physical_cores, self.cpu_model
+        );
+        let optional = [
+            ("memory_gib", self.memory_gib.map(|m| m.to_string())),
+            ("kernel", self.kernel.clone()),
+            ("os", self.os.clone()),
+            ("filesystem", self.filesystem.clone()),
+            ("rotational", self.rotational.map(|r| r.to_string())),
+            ("rustup_host", self.rustup_host.clone()),
+        ];
+        for (name, value) in optional {
+            if let Some(value) = value {
+                identity.push_str(&format!("{}={}\n", name, value));
+            }
+        }
+        identity
+    }
+
     // Only works on Linux
     fn cpu_model() -> Result<String> {
         let re = Lazy::new(|| regex::Regex::new("model name.*: (.*)").unwrap());
         SystemInfo::read_file("/proc/cpuinfo", &re)
     }
 
+    fn memory_gib() -> Result<u64> {
+        let re = Lazy::new(|| regex::Regex::new(r"MemTotal:\s*([0-9]+) kB").unwrap());
+        let kib: u64 = SystemInfo::read_file("/proc/meminfo", &re)?.parse()?;
+        Ok((kib + (1 << 19)) >> 20)
+    }
+
+    fn os() -> Result<String> {
+        let re = Lazy::new(|| regex::Regex::new(r#"PRETTY_NAME="?([^"\n]*)"?"#).unwrap());
+        SystemInfo::read_file("/etc/os-release", &re)
+    }
+
+    /// Type of the filesystem mounted closest to `dir`.
+    fn filesystem(dir: &Path) -> Result<String> {
+        let dir = dir.canonicalize()?;
+        let mounts = std::fs::read_to_string("/proc/mounts")
+            .with_context(|| anyhow!("Unable to open file - /proc/mounts"))?;
+        mounts
+            .lines()
+            .filter_map(|line| {
+                let fields: Vec<&str> = line.split_whitespace().collect();
+                match fields.as_slice() {
+                    [_, mount_point, fs_type, ..] => Some((*mount_point, *fs_type)),
+                    _ => None,
+                }
+            })
+            .filter(|(mount_point, _)| dir.starts_with(mount_point))
+            .max_by_key(|(mount_point, _)| mount_point.len())
+            .map(|(_, fs_type)| fs_type.to_string())
+            .ok_or_else(|| anyhow!("no mount point found for {:?}", dir))
+    }
+
+    /// Looks up the block device `dir` is on. For a partition, the queue belongs to the disk
+    /// it's a part of.
+    fn rotational(dir: &Path) -> Result<bool> {
+        let dev = std::fs::metadata(dir)?.dev();
+        let major = libc::major(dev);
+        let minor = libc::minor(dev);
+        let device = Path::new("/sys/dev/block").join(format!("{}:{}", major, minor));
+        let rotational = std::fs::read_to_string(device.join("queue/rotational"))
+            .or_else(|_| std::fs::read_to_string(device.join("../queue/rotational")))
+            .with_context(|| anyhow!("Unable to find the disk of {:?}", dir))?;
+        Ok(rotational.trim() == "1")
+    }
+
+    fn rustup_host() -> Result<String> {
+        let output = process::run(Command::new("rustup").arg("show"), CommandKind::Rustup)?;
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let re = regex::Regex::new("Default host: (.*)").unwrap();
+        re.captures(&stdout)
+            .and_then(|capture| capture.get(1))
+            .map(|m| m.as_str().trim().to_string())
+            .ok_or_else(|| anyhow!("failed to find the host in rustup show"))
+    }
+
     fn read_file(file_name: &str, re: &regex::Regex) -> Result<String> {
         let all_info: String = std::fs::read_to_string(file_name)
             .with_context(|| anyhow!("Unable to open file - {}", file_name))?;
@@ -37,13 +192,73 @@ impl SystemInfo {
     }
 }
 
+/// Hashes the original fields like `#[derive(Hash)]` did, then only the optional fields that
+/// are present, each with its name. Only used to recognise the names of results files written
+/// before `identity`.
+impl Hash for SystemInfo {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.num_cores.hash(state);
+        self.num_physical_cores.hash(state);
+        self.cpu_model.hash(state);
+        if let Some(memory_gib) = self.memory_gib {
+            "memory_gib".hash(state);
+            memory_gib.hash(state);
+        }
+        if let Some(kernel) = &self.kernel {
+            "kernel".hash(state);
+            kernel.hash(state);
+        }
+        if let Some(os) = &self.os {
+            "os".hash(state);
+            os.hash(state);
+        }
+        if let Some(filesystem) = &self.filesystem {
+            "filesystem".hash(state);
+            filesystem.hash(state);
+        }
+        if let Some(rotational) = self.rotational {
+            "rotational".hash(state);
+            rotational.hash(state);
+        }
+        if let Some(rustup_host) = &self.rustup_host {
+            "rustup_host".hash(state);
+            rustup_host.hash(state);
+        }
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
     use anyhow::Result;
     #[test]
     fn test_fill_system_info() -> Result<()> {
-        println!("{:?}", SystemInfo::new()?);
+        println!("{:?}", SystemInfo::new(Some(&std::env::temp_dir()))?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_is_update_of() -> Result<()> {
+        let before: SystemInfo = serde_json::from_str(
+            r#"{
+                "num_cores": 8,
+                "num_physical_cores": 4,
+                "cpu_model": "AMD Ryzen 7 3700X",
+                "memory_gib": 32,
+                "kernel": "5.10.0-8-amd64"
+            }"#,
+        )?;
+        let after = SystemInfo {
+            kernel: Some("5.10.0-9-amd64".to_string()),
+            ..before.clone()
+        };
+        let other = SystemInfo {
+            memory_gib: Some(64),
+            ..after.clone()
+        };
+        assert!(after.is_update_of(&before));
+        assert!(!before.is_update_of(&before));
+        assert!(!other.is_update_of(&before));
         Ok(())
     }
 }
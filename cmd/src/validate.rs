@@ -0,0 +1,98 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use anyhow::{anyhow, Result};
+
+use crate::repo::Repo;
+use crate::{cargo, rustup, store};
+
+pub(crate) fn validate(repos_file: &Path) -> Result<()> {
+    let repos = store::get_repos(repos_file, &Default::default())?;
+    let duplicates = duplicate_names(&repos);
+
+    let mut failures = 0;
+    for repo in &repos {
+        let result = if duplicates.contains_key(repo.name.as_str()) {
+            Err(anyhow!("Name is used by more than one repo"))
+        } else {
+            validate_repo(repo)
+        };
+        match result {
+            Ok(()) => println!("PASS {}", &repo.name),
+            Err(e) => {
+                failures += 1;
+                println!("FAIL {} - {:#}", &repo.name, e);
+            }
+        }
+    }
+
+    if failures > 0 {
+        return Err(anyhow!(
+            "{} of {} repos failed validation",
+            failures,
+            repos.len()
+        ));
+    }
+    Ok(())
+}
+
+/// Clones the repo, checks the files the profiler touches and builds it once on its
+/// `min_version`, and once more in Debug if it names an `output` to check that it's built.
+/// Nothing is recorded.
+fn validate_repo(repo: &Repo) -> Result<()> {
+    repo.clone_repo()?;
+    repo.check_touch_file()?;
+    rustup::set_version(repo.min_version)?;
+    cargo::cargo_check(repo)?;
+    cargo::check_output(repo)?;
+    Ok(())
+}
+
+fn duplicate_names(repos: &[Repo]) -> BTreeMap<&str, usize> {
+    let mut counts = BTreeMap::new();
+    for repo in repos {
+        *counts.entry(repo.name.as_str()).or_insert(0) += 1;
+    }
+    counts.retain(|_, count| *count > 1);
+    counts
+}
+
+#[cfg(test)]
+mod test {
+    use anyhow::Result;
+
+    #[test]
+    fn test_duplicate_names() -> Result<()> {
+        let repos: Vec<crate::repo::Repo> = serde_json::from_str(
+            r#"[
+                {
+                    "name": "syn",
+                    "sub_directory": "",
+                    "url": "https://github.com/dtolnay/syn",
+                    "touch_file": "src/lib.rs",
+                    "commit": "1.0.42",
+                    "min_version": "V1_34"
+                },
+                {
+                    "name": "helloworld",
+                    "sub_directory": "",
+                    "url": "https://github.com/nindalf/helloworld",
+                    "touch_file": "src/main.rs",
+                    "commit": "v1.0",
+                    "min_version": "V1_34"
+                },
+                {
+                    "name": "syn",
+                    "sub_directory": "",
+                    "url": "https://github.com/dtolnay/syn",
+                    "touch_file": "src/lib.rs",
+                    "commit": "1.0.60",
+                    "min_version": "V1_34"
+                }
+            ]"#,
+        )?;
+        let duplicates = super::duplicate_names(&repos);
+        assert_eq!(duplicates.into_iter().collect::<Vec<_>>(), vec![("syn", 2)]);
+        Ok(())
+    }
+}
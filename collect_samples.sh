@@ -23,8 +23,8 @@ echo "Building arewefastyet"
 cargo build --release
 
 # Log at level info to file output.log.
-# Collect 5 samples for each pair of CompilerMode and ProfileMode.
+# The number of samples for each pair of CompilerMode and ProfileMode is set in data/repos.toml.
 # Run in the background.
-env RUST_LOG=info ./target/release/arewefastyet --times 3 --results-dir ../data/ 2> output.log &
+env RUST_LOG=info ./target/release/arewefastyet --results-dir ../data/ 2> output.log &
 
 tail -f output.log
\ No newline at end of file
@@ -9,17 +9,24 @@ import ToggleButton from 'react-bootstrap/ToggleButton'
 interface AppConfig {
     compiler_modes: Array<CompilerMode>,
     profile_modes: Array<ProfileMode>,
-    systems: Array<System>,
+    // IDs of the machines shown
+    systems: Array<string>,
     show_size_chart: boolean,
 }
 
-export class AppContainer extends Component<Array<ChartData>, AppConfig> {
+interface AppContainerProps {
+    charts: Array<ChartData>,
+    // The machines with results, labelled from data/machines.json
+    systems: Array<System>,
+}
+
+export class AppContainer extends Component<AppContainerProps, AppConfig> {
     constructor(props) {
         super(props);
         this.state = {
             profile_modes: [ProfileMode.Clean],
             compiler_modes: [CompilerMode.Debug, CompilerMode.Release],
-            systems: [System.FourCores, System.EightCores],
+            systems: defaultSystems(props.systems),
             show_size_chart: false,
         };
         this.onCMChanged = this.onCMChanged.bind(this);
@@ -67,11 +74,10 @@ export class AppContainer extends Component<Array<ChartData>, AppConfig> {
                     <ToggleButton value={ProfileMode.Incremental}>Incremental</ToggleButton>
                 </ToggleButtonGroup>
 
-                <ToggleButtonGroup name='Number of Cores' type="checkbox" defaultValue={[System.FourCores, System.EightCores]} className="mb-2" onChange={this.onSystemChanged}>
-                    <ToggleButton value={System.TwoCores}>2 cores</ToggleButton>
-                    <ToggleButton value={System.FourCores}>4 cores</ToggleButton>
-                    <ToggleButton value={System.EightCores}>8 cores</ToggleButton>
-                    <ToggleButton value={System.SixteenCores}>16 cores</ToggleButton>
+                <ToggleButtonGroup name='Machine' type="checkbox" defaultValue={defaultSystems(this.props.systems)} className="mb-2" onChange={this.onSystemChanged}>
+                    {this.props.systems.map(system => {
+                        return <ToggleButton value={system.id} key={system.id}>{system.label}</ToggleButton>
+                    })}
                 </ToggleButtonGroup>
                 
                 <ToggleButtonGroup name='Number of Cores' type="checkbox" defaultValue={[]} className="mb-2" onChange={this.onSizeChartClicked}>
@@ -79,17 +85,27 @@ export class AppContainer extends Component<Array<ChartData>, AppConfig> {
                 </ToggleButtonGroup>
             </div>
             {
-                Object.keys(this.props).map(repo => {
+                this.props.charts.map(chartData => {
                     const props: LineChartXProps = {
-                        chartData: this.props[repo],
+                        chartData: chartData,
                         profile_modes: this.state.profile_modes,
                         compiler_modes: this.state.compiler_modes,
-                        systems: this.state.systems,
+                        systems: this.props.systems.filter(system => this.state.systems.includes(system.id)),
                         show_size_chart: this.state.show_size_chart,
                     };
-                    return <LineChartX {...props} key={repo} />
+                    return <LineChartX {...props} key={chartData.repo.name} />
                 })
             }
         </div >
     }
-}
\ No newline at end of file
+}
+
+// IDs of the middle two machines, which were 4 and 8 cores when there were four
+function defaultSystems(systems: Array<System>): Array<string> {
+    const ids = systems.map(system => system.id);
+    if (ids.length <= 2) {
+        return ids;
+    }
+    const start = Math.floor((ids.length - 2) / 2);
+    return ids.slice(start, start + 2);
+}
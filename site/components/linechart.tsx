@@ -3,7 +3,7 @@ import React, { Component } from "react"
 import { ChartData } from '../data/chartData'
 import { CompilerMode, ProfileMode, System } from '../data/types'
 import {
-    LineChart, Line, XAxis, YAxis, CartesianGrid, Tooltip, Legend, Label, ResponsiveContainer, Text
+    LineChart, Line, XAxis, YAxis, CartesianGrid, Tooltip, Legend, Label, ResponsiveContainer, Text, ReferenceLine
 } from 'recharts';
 
 export interface LineChartXProps {
@@ -31,6 +31,13 @@ const lineColours: Record<string, string> = {
     'Release, 16 cores': '#05b511',
 };
 
+// For machines labelled other than by their number of cores
+const defaultLineColours: Record<string, string> = {
+    'Check': '#fc3d60',
+    'Debug': '#6556f5',
+    'Release': '#2bb534',
+};
+
 const strokeWidthMap: Record<string, number> = {
     '2 cores': 1,
     '4 cores': 1.33,
@@ -61,13 +68,12 @@ export class LineChartX extends Component<LineChartXProps> {
         return this.props.compiler_modes.map(compile_mode => {
             return this.props.profile_modes.map(profile_mode => {
                 return this.props.systems.map(system => {
-                    return [compile_mode, profile_mode, system]
+                    return [compile_mode, profile_mode, system] as [CompilerMode, ProfileMode, System]
                 })
             })
         })
             .flatMap(x => x)
-            .flatMap(x => x)
-            .sort((a, b) => parseInt(a[2].substr(0, 2)) - parseInt(b[2].substr(0, 2)));
+            .flatMap(x => x);
     }
 
 
@@ -90,6 +96,13 @@ export class LineChartX extends Component<LineChartXProps> {
     }
 
 
+    failedVersions(): Array<string> {
+        return this.props.chartData.compile_times
+            .filter(point => point['does_not_build'])
+            .map(point => point['version'] as string);
+    }
+
+
     compileTimeCharts() {
         return <ResponsiveContainer width="99%" height={300}>
             <LineChart
@@ -102,18 +115,23 @@ export class LineChartX extends Component<LineChartXProps> {
                 <XAxis dataKey="version"></XAxis>
                 <YAxis><Label value="Time (seconds)" position='left' angle={-90} /></YAxis>
                 <Tooltip
-                   labelFormatter={e => `v${e}`}
+                   labelFormatter={e => this.failedVersions().includes(e) ? `v${e} (does not build)` : `v${e}`}
                    separator={': '}
                    formatter={(value, name, props) => {
                      const currentVersion = props.payload.version;
-                     const delta = this.perfDelta(name, currentVersion);
+                     const delta = this.perfDelta(props.dataKey as string, currentVersion);
                      return `${value.toFixed(2)}s (${delta.toFixed(2)}%)`;
                    }}
                 />
                 <Legend align='right' />
+                {this.failedVersions().map(version => {
+                    return <ReferenceLine x={version} stroke="#bbb" strokeDasharray="3 3" label={{ value: 'does not build', angle: -90, fontSize: 10 }} key={version} />;
+                })
+                }
                 {this.compileTimeDataKeys().map(([cm, pm, system]) => {
-                    const key = `${cm}, ${pm}, ${system}`;
-                    return <Line type="monotone" dataKey={key} stroke={lineColours[cm + ', ' + system]} strokeWidth={strokeWidthMap[system]} key={key} />;
+                    const key = `${cm}, ${pm}, ${system.id}`;
+                    const name = `${cm}, ${pm}, ${system.label}`;
+                    return <Line type="monotone" dataKey={key} name={name} stroke={lineColours[cm + ', ' + system.label] || defaultLineColours[cm]} strokeWidth={strokeWidthMap[system.label] || 1.5} key={key} />;
                 })
                 }
 
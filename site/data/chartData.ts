@@ -1,6 +1,5 @@
-import { average } from './math'
-import { Profile, Repo, getResults, getRepos } from './results'
-import { getSystem } from './types';
+import { Machine, Repo, RepoData, getDashboard } from './dashboard'
+import { System } from './types';
 
 
 export interface ChartData {
@@ -12,70 +11,61 @@ export interface ChartData {
 type ChartPoint = Record<string, string | number>;
 
 export function getChartData(): Array<ChartData> {
+    const dashboard = getDashboard();
 
-    const repos = getRepos();
-    const repo_names = repos.map(repo => repo.name);
-
-    const results = getResults();
-    const profiles: Array<[number, Record<string, Profile>]> = results.map(result => [result.system_info.num_cores, result.profiles]);
-    const compile_times = combineCompileTimes(repo_names, profiles);
-    const sizes = outputSizes(repo_names, profiles);
-
-    return repos.map(repo => {
+    return dashboard.repos.map(repo => {
         return {
-            "repo": repo,
-            "compile_times": compile_times[repo.name],
-            "sizes": sizes[repo.name],
+            "repo": { name: repo.name, url: repo.url, commit: repo.commit },
+            "compile_times": compileTimes(repo),
+            "sizes": outputSizes(repo, dashboard.machines),
         };
     })
 }
 
-function combineCompileTimes(repo_names: Array<string>, profiles: Array<[number, Record<string, Profile>]>): Record<string, ChartPoint[]> {
-    return repo_names.reduce((map, repo_name) => {
-        let output: { [version: string]: ChartPoint } = {};
-        profiles.forEach(([cores, profile]) => {
-            const system = getSystem(cores);
-            const compile_times = profile[repo_name].compile_times;
-            Object.entries(compile_times).forEach(([key, timings]) => {
-                const [version, compiler_mode, profile_mode] = key.split(",");
-                const new_key = compiler_mode + ", " + profile_mode + ", " + system;
+// All machines with results, fewest cores first
+export function getSystems(): Array<System> {
+    return getDashboard().machines.map(machine => ({ id: machine.id, label: machine.label }));
+}
+
+// Keyed by machine ID, the chart shows the label
+function compileTimes(repo: RepoData): ChartPoint[] {
+    const output: { [version: string]: ChartPoint } = {};
+    repo.compile_times.forEach(time => {
+        const key = time.compiler_mode + ", " + time.profile_mode + ", " + time.machine;
+        point(output, time.version)[key] = time.mean / 1000;
+    });
+    repo.failures.forEach(failure => {
+        point(output, failure.version)['does_not_build'] = failure.kind;
+    });
+    return sortedByVersion(output);
+}
 
-                const average_timing = average(timings);
-                if (!output[version]) {
-                    output[version] = {}
-                    output[version]['version'] = version.substr(0, 4);
-                }
-                output[version][new_key] = average_timing / 1000;
-            });
+// Sizes are the same on every machine, so only those of the first machine that has them are shown
+function outputSizes(repo: RepoData, machines: Array<Machine>): ChartPoint[] {
+    const machine = machines.find(machine => repo.sizes.some(size => size.machine === machine.id));
+    const output: { [version: string]: ChartPoint } = {};
+    repo.sizes
+        .filter(size => machine && size.machine === machine.id)
+        .forEach(size => {
+            point(output, size.version)[size.compiler_mode] = (size.bytes / (1024 * 1024)).toFixed(3);
         });
-        const values = Object.entries(output).map(([_, value]) => value);
+    return sortedByVersion(output);
+}
+
+function point(output: { [version: string]: ChartPoint }, version: string): ChartPoint {
+    if (!output[version]) {
+        output[version] = {};
+        output[version]['version'] = version.substr(0, 4);
+    }
+    return output[version];
+}
 
-        map[repo_name] = values;
-        return map;
-    }, {});
+function sortedByVersion(output: { [version: string]: ChartPoint }): ChartPoint[] {
+    return Object.entries(output)
+        .sort(([a, _x], [b, _y]) => minorVersion(a) - minorVersion(b))
+        .map(([_, value]) => value);
 }
 
-function outputSizes(repo_names: Array<string>, profiles: Array<[number, Record<string, Profile>]>): Record<string, ChartPoint[]> {
-    const [_, profile] = profiles.shift();
-    return repo_names.reduce((map, repo_name) => {
-        const sizes = profile[repo_name].output_sizes;
-        const output: { [version: string]: ChartPoint } = {};
-        Object.entries(sizes).forEach(([key, size]) => {
-            const [version, compiler_mode] = key.split(',');
-            if (!output[version]) {
-                output[version] = {};
-                output[version]['version'] = version.substr(0, 4);
-            }
-            const sizeMB = (size / (1024 * 1024)).toFixed(3);
-            if (compiler_mode == 'Debug') {
-                output[version]['Debug'] = sizeMB;
-            }
-            if (compiler_mode == 'Release') {
-                output[version]['Release'] = sizeMB;
-            }
-        })
-        const values: ChartPoint[] = Object.entries(output).map(([_, value]) => value);
-        map[repo_name] = values;
-        return map;
-    }, {});
+function minorVersion(version: string): number {
+    return parseInt(version.split('.')[1]);
 }
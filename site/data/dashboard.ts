@@ -0,0 +1,68 @@
+import * as fs from 'fs'
+import * as path from 'path'
+import { CompilerMode, ProfileMode } from './types'
+
+// Written by `arewefastyet dashboard`, see cmd/src/dashboard.rs. Must match its SCHEMA_VERSION.
+const SCHEMA_VERSION = 1
+
+export interface Dashboard {
+    schema_version: number,
+    // Fewest cores first
+    machines: Array<Machine>,
+    repos: Array<RepoData>,
+}
+
+export interface Machine {
+    id: string,
+    label: string,
+    num_cores: number,
+}
+
+export interface Repo {
+    name: string,
+    url: string,
+    commit: string,
+}
+
+export interface RepoData extends Repo {
+    compile_times: Array<CompileTime>,
+    sizes: Array<Size>,
+    failures: Array<Failure>,
+}
+
+// Summary of the samples of one version, in milliseconds
+export interface CompileTime {
+    machine: string,
+    version: string,
+    compiler_mode: CompilerMode,
+    profile_mode: ProfileMode,
+    samples: number,
+    mean: number,
+    median: number,
+    // 95% confidence interval of the mean. Missing for a single sample
+    ci?: [number, number],
+    min: number,
+    max: number,
+}
+
+export interface Size {
+    machine: string,
+    version: string,
+    compiler_mode: CompilerMode,
+    bytes: number,
+}
+
+export interface Failure {
+    machine: string,
+    version: string,
+    kind: 'BuildError' | 'Timeout' | 'ToolchainInstall',
+}
+
+export function getDashboard(): Dashboard {
+    const fullPath = path.join(process.cwd(), '../data/dashboard.json');
+    const dashboard: Dashboard = JSON.parse(fs.readFileSync(fullPath, 'utf-8'));
+    if (dashboard.schema_version !== SCHEMA_VERSION) {
+        throw new Error(`data/dashboard.json has schema version ${dashboard.schema_version}, expected ${SCHEMA_VERSION}. Regenerate it with \`cargo run --release -- dashboard\``);
+    }
+    return dashboard;
+}
@@ -9,19 +9,9 @@ export enum ProfileMode {
     Clean = 'Clean',
     Incremental = 'Incremental',
 }
-export enum System {
-    TwoCores = '2 cores',
-    FourCores = '4 cores',
-    EightCores = '8 cores',
-    SixteenCores = '16 cores',
+// A machine with results. Series are keyed by its ID, since two machines can have the same label
+export interface System {
+    id: string,
+    // From data/machines.json, e.g. "8 cores"
+    label: string,
 }
-
-export function getSystem(cores: number): System {
-    switch (cores) {
-        case 2: return System.TwoCores;
-        case 4: return System.FourCores;
-        case 8: return System.EightCores;
-        case 16: return System.SixteenCores;
-        default: return null;
-    }
-}
\ No newline at end of file
@@ -34,7 +34,7 @@ const faq_md = `
 	- If you're looking for Rust projects to contribute to, keep an eye on the "calls for participation" section in [This week in Rust](https://this-week-in-rust.org/), a newsletter. To contribute to Rust itself, check out 
 - How to contribute to arewefastyet?
     - Contributions welcome to profiling code or frontend.
-    - If there is a important rust repo that is different enough from the existing ones, open [an issue](todo). Importance is correlated with how widely used the tool (ripgrep, alacritty, rav1e) or library (hyper, serde, clap) is. If you'd like to add one, please follow the format in [./data/repos.json](https://github.com/nindalf/arewefastyet/blob/master/data/repos.json)
+    - If there is a important rust repo that is different enough from the existing ones, open [an issue](todo). Importance is correlated with how widely used the tool (ripgrep, alacritty, rav1e) or library (hyper, serde, clap) is. If you'd like to add one, please follow the format in [./data/repos.toml](https://github.com/nindalf/arewefastyet/blob/master/data/repos.toml)
 `;
 
 export default function Home({ faqHtml }) {
@@ -1,9 +1,9 @@
 import Head from 'next/head'
 import styles from '../styles/Home.module.css'
-import { getChartData } from '../data/chartData'
+import { getChartData, getSystems } from '../data/chartData'
 import { AppContainer } from '../components/appcontainer'
 
-export default function Home({ chartData }) {
+export default function Home({ chartData, systems }) {
   return (
     <div className={styles.container}>
       <Head>
@@ -17,7 +17,7 @@ export default function Home({ chartData }) {
           Benchmarking the Rust compiler
         </h3>
         <div className={`${styles.card} ${styles.faq}`}>Arewefastyet measures how long the Rust compiler takes to compile common Rust programs.<br />Lower is better. Check out the <a href="/faq">FAQ</a></div>
-        <AppContainer {...chartData} />
+        <AppContainer charts={chartData} systems={systems} />
       </main>
 
       <footer className={styles.footer}>
@@ -35,9 +35,11 @@ export default function Home({ chartData }) {
 
 export async function getStaticProps() {
   const chartData = getChartData()
+  const systems = getSystems()
   return {
     props: {
-      chartData
+      chartData,
+      systems
     }
   }
 }